@@ -0,0 +1,121 @@
+//! Reading and writing git bundles without `git`
+//!
+//! A bundle is a header listing its refs (and the commits it requires, which aren't
+//! included), followed by a packfile:
+//!
+//! ```text
+//! # v2 git bundle
+//! -<prerequisite oid> <comment>
+//! <oid> <ref>
+//!
+//! PACK...
+//! ```
+
+use git2::Oid;
+use std::path::Path;
+
+/// Refs and prerequisites of a bundle
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Header {
+    /// Commits the bundle's pack refers to without including them
+    pub prerequisites: Vec<Oid>,
+    pub refs: Vec<(Oid, String)>,
+}
+
+impl Header {
+    /// Object id of a ref in the bundle
+    pub fn find(&self, name: &str) -> Option<Oid> {
+        self.refs
+            .iter()
+            .find(|(_, reference)| reference == name)
+            .map(|(oid, _)| *oid)
+    }
+}
+
+/// Splits a bundle into its header and pack
+pub(crate) fn parse(bundle: &[u8]) -> Result<(Header, &[u8]), anyhow::Error> {
+    let invalid = || anyhow::Error::msg("not a git bundle");
+    let mut header = Header::default();
+    let mut rest = bundle;
+    let mut first = true;
+    loop {
+        let end = rest.iter().position(|b| *b == b'\n').ok_or_else(invalid)?;
+        let line = std::str::from_utf8(&rest[..end]).map_err(|_| invalid())?;
+        rest = &rest[end + 1..];
+        if first {
+            if line != "# v2 git bundle" && line != "# v3 git bundle" {
+                return Err(invalid());
+            }
+            first = false;
+            continue;
+        }
+        if line.is_empty() {
+            break;
+        }
+        // Capabilities of v3 bundles
+        if line.starts_with('@') {
+            if line.starts_with("@object-format=") && line != "@object-format=sha1" {
+                return Err(anyhow::Error::msg(format!("unsupported bundle {line}")));
+            }
+            continue;
+        }
+        if let Some(prerequisite) = line.strip_prefix('-') {
+            let oid = prerequisite.split(' ').next().unwrap_or_default();
+            header
+                .prerequisites
+                .push(Oid::from_str(oid).map_err(|_| invalid())?);
+            continue;
+        }
+        let (oid, reference) = line.split_once(' ').ok_or_else(invalid)?;
+        header.refs.push((
+            Oid::from_str(oid).map_err(|_| invalid())?,
+            reference.to_string(),
+        ));
+    }
+    if rest.len() < 12 || &rest[..4] != b"PACK" {
+        return Err(anyhow::Error::msg("bundle has no pack"));
+    }
+    Ok((header, rest))
+}
+
+/// Writes a bundle of `pack` with a single `reference` pointing to `oid`
+pub(crate) fn write(path: &Path, oid: Oid, reference: &str, pack: &[u8]) -> std::io::Result<()> {
+    let mut bundle = format!("# v2 git bundle\n{oid} {reference}\n\n").into_bytes();
+    bundle.extend_from_slice(pack);
+    std::fs::write(path, bundle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_header() -> Result<(), anyhow::Error> {
+        let oid = "1".repeat(40);
+        let prerequisite = "2".repeat(40);
+        let bundle = format!(
+            "# v3 git bundle\n@object-format=sha1\n-{prerequisite} comment\n\
+             {oid} refs/heads/paravendor\n\nPACK\0\0\0\x02\0\0\0\0"
+        );
+        let (header, pack) = parse(bundle.as_bytes())?;
+        assert_eq!(
+            header,
+            Header {
+                prerequisites: vec![Oid::from_str(&prerequisite)?],
+                refs: vec![(Oid::from_str(&oid)?, "refs/heads/paravendor".to_string())],
+            }
+        );
+        assert_eq!(
+            header.find("refs/heads/paravendor"),
+            Some(Oid::from_str(&oid)?)
+        );
+        assert!(pack.starts_with(b"PACK"));
+
+        assert!(parse(b"# v2 git bundle\n\n").is_err());
+        assert!(parse(b"PACK").is_err());
+        assert!(
+            parse(b"# v3 git bundle\n@object-format=sha256\n\nPACK\0\0\0\x02\0\0\0\0").is_err()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,89 @@
+//! Identities recorded in paravendor commits
+
+use git2::{Repository, Signature};
+
+/// Used when no identity is configured at all, e.g. in CI containers
+const FALLBACK_NAME: &str = "paravendor";
+const FALLBACK_EMAIL: &str = "paravendor@localhost";
+
+#[derive(Clone, Copy)]
+pub(crate) enum Role {
+    Author,
+    Committer,
+}
+
+/// Signature for `role` in paravendor commits
+///
+/// Like git, `GIT_AUTHOR_NAME`/`GIT_AUTHOR_EMAIL` (or `GIT_COMMITTER_*`) take precedence
+/// over `user.name`/`user.email`. Whatever is missing falls back to a generic identity.
+pub(crate) fn signature(
+    repository: &Repository,
+    role: Role,
+) -> Result<Signature<'static>, anyhow::Error> {
+    let config = repository.config()?;
+    resolve(
+        role,
+        |var| std::env::var(var).ok(),
+        config.get_string("user.name").ok(),
+        config.get_string("user.email").ok(),
+    )
+}
+
+fn resolve(
+    role: Role,
+    env: impl Fn(&str) -> Option<String>,
+    name: Option<String>,
+    email: Option<String>,
+) -> Result<Signature<'static>, anyhow::Error> {
+    let prefix = match role {
+        Role::Author => "GIT_AUTHOR",
+        Role::Committer => "GIT_COMMITTER",
+    };
+    let non_empty = |value: Option<String>| value.filter(|v| !v.trim().is_empty());
+    let name = non_empty(env(&format!("{prefix}_NAME")))
+        .or(non_empty(name))
+        .unwrap_or_else(|| FALLBACK_NAME.to_string());
+    let email = non_empty(env(&format!("{prefix}_EMAIL")))
+        .or(non_empty(email))
+        .unwrap_or_else(|| FALLBACK_EMAIL.to_string());
+    Ok(Signature::now(&name, &email)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn precedence() -> Result<(), anyhow::Error> {
+        let env = |var: &str| match var {
+            "GIT_AUTHOR_NAME" => Some("Env Author".to_string()),
+            "GIT_COMMITTER_EMAIL" => Some("committer@example.com".to_string()),
+            _ => None,
+        };
+        let configured = || {
+            (
+                Some("Config".to_string()),
+                Some("config@example.com".to_string()),
+            )
+        };
+
+        let (name, email) = configured();
+        let author = resolve(Role::Author, env, name, email)?;
+        assert_eq!(author.name(), Some("Env Author"));
+        assert_eq!(author.email(), Some("config@example.com"));
+
+        let (name, email) = configured();
+        let committer = resolve(Role::Committer, env, name, email)?;
+        assert_eq!(committer.name(), Some("Config"));
+        assert_eq!(committer.email(), Some("committer@example.com"));
+        Ok(())
+    }
+
+    #[test]
+    fn fallback() -> Result<(), anyhow::Error> {
+        let signature = resolve(Role::Author, |_| None, None, Some(" ".to_string()))?;
+        assert_eq!(signature.name(), Some(FALLBACK_NAME));
+        assert_eq!(signature.email(), Some(FALLBACK_EMAIL));
+        Ok(())
+    }
+}
@@ -0,0 +1,179 @@
+//! Running work on several threads, with their progress reported to a single sink
+
+use crate::{ProgressSink, Transfer};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::time::Duration;
+
+/// Number of threads to use for `jobs`, where 0 means one per CPU
+pub(crate) fn effective(jobs: usize) -> usize {
+    match jobs {
+        0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+        jobs => jobs,
+    }
+}
+
+enum Event<R> {
+    Fetch(String),
+    Transfer(Transfer),
+    Empty(String),
+    Retry(String, git2::Error, Duration, u32, u32),
+    Done(usize, R),
+}
+
+/// Forwards progress from a worker thread to the thread owning the actual sink
+struct ChannelSink<R>(mpsc::Sender<Event<R>>);
+
+impl<R> ProgressSink for ChannelSink<R> {
+    fn on_fetch(&mut self, url: &str) {
+        let _ = self.0.send(Event::Fetch(url.to_string()));
+    }
+
+    fn on_transfer(&mut self, transfer: Transfer) {
+        let _ = self.0.send(Event::Transfer(transfer));
+    }
+
+    fn on_empty(&mut self, url: &str) {
+        let _ = self.0.send(Event::Empty(url.to_string()));
+    }
+
+    fn on_retry(
+        &mut self,
+        url: &str,
+        error: &git2::Error,
+        delay: Duration,
+        attempt: u32,
+        retries: u32,
+    ) {
+        let error = git2::Error::new(error.code(), error.class(), error.message());
+        let _ = self.0.send(Event::Retry(
+            url.to_string(),
+            error,
+            delay,
+            attempt,
+            retries,
+        ));
+    }
+}
+
+/// Runs `work` for each of `items` on up to `jobs` threads (see [`effective`])
+///
+/// Items are started in order. Once an outcome satisfies `stop`, no more items are
+/// started, and those that weren't have no outcome. With a single job, everything
+/// runs on the current thread, one item after another.
+pub(crate) fn run<T, R>(
+    jobs: usize,
+    items: &[T],
+    progress: &mut dyn ProgressSink,
+    stop: impl Fn(&R) -> bool + Sync,
+    work: impl Fn(&T, &mut dyn ProgressSink) -> R + Sync,
+) -> Vec<Option<R>>
+where
+    T: Sync,
+    R: Send,
+{
+    let mut outcomes = items.iter().map(|_| None).collect::<Vec<_>>();
+    let jobs = effective(jobs).min(items.len());
+    if jobs <= 1 {
+        for (item, outcome) in items.iter().zip(outcomes.iter_mut()) {
+            let result = work(item, progress);
+            let stopped = stop(&result);
+            *outcome = Some(result);
+            if stopped {
+                break;
+            }
+        }
+        return outcomes;
+    }
+
+    let next = AtomicUsize::new(0);
+    let stopped = AtomicBool::new(false);
+    let (sender, receiver) = mpsc::channel();
+    std::thread::scope(|scope| {
+        for _ in 0..jobs {
+            let mut sink = ChannelSink(sender.clone());
+            let (next, stopped, stop, work) = (&next, &stopped, &stop, &work);
+            scope.spawn(move || {
+                while !stopped.load(Ordering::SeqCst) {
+                    let i = next.fetch_add(1, Ordering::SeqCst);
+                    let Some(item) = items.get(i) else {
+                        break;
+                    };
+                    let result = work(item, &mut sink);
+                    if stop(&result) {
+                        stopped.store(true, Ordering::SeqCst);
+                    }
+                    let _ = sink.0.send(Event::Done(i, result));
+                }
+            });
+        }
+        // Only the workers' senders are left, so this ends once they are done
+        drop(sender);
+        for event in receiver {
+            match event {
+                Event::Fetch(url) => progress.on_fetch(&url),
+                Event::Transfer(transfer) => progress.on_transfer(transfer),
+                Event::Empty(url) => progress.on_empty(&url),
+                Event::Retry(url, error, delay, attempt, retries) => {
+                    progress.on_retry(&url, &error, delay, attempt, retries)
+                }
+                Event::Done(i, result) => outcomes[i] = Some(result),
+            }
+        }
+    });
+    outcomes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NoProgress;
+
+    #[derive(Default)]
+    struct Fetches(Vec<String>);
+
+    impl ProgressSink for Fetches {
+        fn on_fetch(&mut self, url: &str) {
+            self.0.push(url.to_string());
+        }
+    }
+
+    #[test]
+    fn all_items() {
+        let items = (0..10).collect::<Vec<_>>();
+        for jobs in [1, 4] {
+            let mut fetches = Fetches::default();
+            let outcomes = run(
+                jobs,
+                &items,
+                &mut fetches,
+                |_| false,
+                |i, progress| {
+                    progress.on_fetch(&i.to_string());
+                    i * 2
+                },
+            );
+            assert_eq!(
+                outcomes,
+                items.iter().map(|i| Some(i * 2)).collect::<Vec<_>>()
+            );
+            fetches.0.sort_by_key(|url| url.parse::<usize>().unwrap());
+            assert_eq!(
+                fetches.0,
+                items.iter().map(|i| i.to_string()).collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[test]
+    fn stop() {
+        let items = (0..10).collect::<Vec<_>>();
+        let outcomes = run(1, &items, &mut NoProgress, |r| *r == 3, |i, _| *i);
+        assert_eq!(outcomes[..4], [Some(0), Some(1), Some(2), Some(3)]);
+        assert!(outcomes[4..].iter().all(Option::is_none));
+
+        // Items started before the stop still finish
+        let outcomes = run(2, &items, &mut NoProgress, |r| *r == 3, |i, _| *i);
+        assert!(outcomes[..4].iter().all(Option::is_some));
+    }
+}
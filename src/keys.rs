@@ -0,0 +1,110 @@
+//! Addressing config values by dotted keys, like `dependencies.foo.url`
+
+use toml::Value;
+
+/// Looks up a dotted `key` in `value`
+///
+/// Table keys may contain dots themselves (`dependencies.example.com.url`), so at every
+/// level the longest matching key wins.
+pub(crate) fn get<'a>(value: &'a Value, key: &str) -> Option<&'a Value> {
+    let segments = key.split('.').collect::<Vec<_>>();
+    lookup(value, &segments)
+}
+
+fn lookup<'a>(value: &'a Value, segments: &[&str]) -> Option<&'a Value> {
+    if segments.is_empty() {
+        return Some(value);
+    }
+    let table = value.as_table()?;
+    (1..=segments.len())
+        .rev()
+        .find_map(|n| lookup(table.get(&segments[..n].join("."))?, &segments[n..]))
+}
+
+/// Replaces the scalar value at a dotted `key` with `new`, parsed as the same type
+pub(crate) fn set(value: &mut Value, key: &str, new: &str) -> Result<(), anyhow::Error> {
+    let segments = key.split('.').collect::<Vec<_>>();
+    let path =
+        resolve(value, &segments).ok_or_else(|| anyhow::Error::msg(format!("{key} is not set")))?;
+    let mut target = value;
+    for segment in path {
+        target = target.get_mut(&segment).unwrap();
+    }
+    let invalid = |expected: &str| anyhow::Error::msg(format!("{key} has to be {expected}"));
+    *target = match target {
+        Value::String(_) => Value::String(new.to_string()),
+        Value::Boolean(_) => Value::Boolean(new.parse().map_err(|_| invalid("a boolean"))?),
+        Value::Integer(_) => Value::Integer(new.parse().map_err(|_| invalid("an integer"))?),
+        Value::Float(_) => Value::Float(new.parse().map_err(|_| invalid("a number"))?),
+        Value::Datetime(_) => Value::Datetime(new.parse().map_err(|_| invalid("a datetime"))?),
+        Value::Array(_) | Value::Table(_) => {
+            return Err(anyhow::Error::msg(format!("{key} is not a scalar value")))
+        }
+    };
+    Ok(())
+}
+
+/// Finds the actual table keys a dotted key consists of
+fn resolve(value: &Value, segments: &[&str]) -> Option<Vec<String>> {
+    if segments.is_empty() {
+        return Some(vec![]);
+    }
+    let table = value.as_table()?;
+    (1..=segments.len()).rev().find_map(|n| {
+        let key = segments[..n].join(".");
+        let mut path = resolve(table.get(&key)?, &segments[n..])?;
+        path.insert(0, key);
+        Some(path)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value() -> Value {
+        toml::from_str(
+            r#"
+version = "1.2"
+
+[dependencies."example.com"]
+url = "https://example.com/dep.git"
+disabled = false
+groups = ["backend"]
+"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn get_dotted_names() {
+        let value = value();
+        assert_eq!(get(&value, "version").unwrap().as_str(), Some("1.2"));
+        assert_eq!(
+            get(&value, "dependencies.example.com.url")
+                .unwrap()
+                .as_str(),
+            Some("https://example.com/dep.git")
+        );
+        assert!(get(&value, "dependencies.example.url").is_none());
+    }
+
+    #[test]
+    fn set_matches_type() -> Result<(), anyhow::Error> {
+        let mut value = value();
+        set(&mut value, "dependencies.example.com.disabled", "true")?;
+        assert_eq!(
+            get(&value, "dependencies.example.com.disabled")
+                .unwrap()
+                .as_bool(),
+            Some(true)
+        );
+        let err = set(&mut value, "dependencies.example.com.disabled", "yes").unwrap_err();
+        assert!(err.to_string().contains("has to be a boolean"));
+        let err = set(&mut value, "dependencies.example.com.groups", "x").unwrap_err();
+        assert!(err.to_string().contains("not a scalar"));
+        let err = set(&mut value, "dependencies.example.com.description", "x").unwrap_err();
+        assert!(err.to_string().contains("is not set"));
+        Ok(())
+    }
+}
@@ -0,0 +1,7122 @@
+//! Storing git dependencies inside of a git repository
+//!
+//! Dependencies are fetched into the repository's object store and recorded in
+//! a `config` file on the `paravendor` branch, whose commits have the fetched
+//! dependency commits as extra parents, keeping them reachable.
+
+use git2::build::TreeUpdateBuilder;
+use git2::{
+    AutotagOption, BranchType, CertificateCheckStatus, Direction, FileMode, ObjectType,
+    ProxyOptions, Reference, RemoteCallbacks, Repository,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::{debug, info, trace, warn};
+
+mod bundle;
+mod identity;
+mod jobs;
+mod keys;
+mod lock;
+mod migrate;
+mod objects;
+mod sign;
+mod ssh;
+mod timestamp;
+mod transport;
+mod url;
+
+/// Errors that callers may want to tell apart from others, e.g. to pick an exit code
+///
+/// They are returned wrapped in [`anyhow::Error`] (possibly with added context), and can
+/// be found in its [`chain`](anyhow::Error::chain).
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    /// There's no paravendor branch
+    NotInitialized,
+    /// There's no dependency with this name
+    DependencyNotFound(String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::NotInitialized => {
+                write!(
+                    f,
+                    "paravendor is not initialized, run `git paravendor init`"
+                )
+            }
+            Error::DependencyNotFound(name) => write!(f, "dependency {name} not found"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Dependencies added with `add --no-commit` are accumulated here until committed
+pub const STAGED_REF: &str = "refs/paravendor/staged";
+
+/// Objects fetched by a sync that hasn't finished yet are kept reachable from here (see
+/// [`interrupted_sync`])
+pub const IN_PROGRESS_REF: &str = "refs/paravendor/in-progress";
+
+/// Notes on paravendor commits describe what they changed (see [`note`])
+pub const NOTES_REF: &str = "refs/notes/paravendor";
+
+/// Git config key naming the local paravendor branch (see [`branch_name`])
+pub const BRANCH_NAME_CONFIG: &str = "paravendor.branch";
+
+/// Trailer of paravendor commits with the SHA-256 of their config (see [`verify_config`])
+pub const CONFIG_SHA256_TRAILER: &str = "Paravendor-Config-Sha256";
+
+pub use migrate::{CURRENT_VERSION as CONFIG_VERSION, OLDEST_VERSION as OLDEST_CONFIG_VERSION};
+pub use timestamp::parse_duration;
+
+/// Contents of the `config` file on the paravendor branch
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Config {
+    /// Config format version
+    pub version: String,
+    /// Vendorized dependencies by name
+    pub dependencies: BTreeMap<String, Dependency>,
+}
+
+/// A vendorized dependency
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Dependency {
+    /// URL the dependency is fetched from
+    pub url: String,
+    /// URLs to fetch the dependency from, in order, if fetching from `url` fails
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mirrors: Vec<String>,
+    /// Why the dependency is vendored
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Skip the dependency when syncing all dependencies
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub disabled: bool,
+    /// Never sync the dependency, even when named explicitly, keeping its heads as they are
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub frozen: bool,
+    /// Groups the dependency belongs to, for syncing them together
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<String>,
+    /// When the dependency was last fetched successfully (RFC 3339), whether or not
+    /// anything changed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_synced: Option<String>,
+    /// URL the dependency was last fetched from successfully, either `url` or one of
+    /// `mirrors`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_source: Option<String>,
+    /// Refs advertised by the dependency, by full ref name
+    pub heads: BTreeMap<String, Head>,
+}
+
+/// A ref advertised by a dependency
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Head {
+    /// Object id the ref points to
+    pub commit: String,
+    /// Object id an annotated tag points to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peeled: Option<String>,
+}
+
+impl Head {
+    /// Object id of the ref with annotated tags peeled
+    pub fn target(&self) -> &str {
+        self.peeled.as_deref().unwrap_or(&self.commit)
+    }
+}
+
+impl Config {
+    /// Reads the config stored in a paravendor commit, upgrading it in memory if necessary
+    pub fn from_commit(
+        repository: &Repository,
+        commit: &git2::Commit,
+    ) -> Result<Self, anyhow::Error> {
+        let blob = config_blob(repository, commit)?;
+        Ok(migrate::migrate(std::str::from_utf8(blob.content())?, true)?.config)
+    }
+
+    /// Looks up a dependency by name
+    pub fn dependency(&self, name: &str) -> Result<&Dependency, anyhow::Error> {
+        self.dependencies
+            .get(name)
+            .ok_or_else(|| Error::DependencyNotFound(name.to_string()).into())
+    }
+}
+
+impl Dependency {
+    /// Resolves a ref name (or an abbreviated commit OID) to an object id
+    ///
+    /// If `peel` is set, annotated tags are resolved to the commits they point to,
+    /// otherwise to the tag objects themselves.
+    pub fn resolve<'a>(&'a self, reference: &str, peel: bool) -> Result<&'a str, anyhow::Error> {
+        let target = |head: &'a Head| -> &'a str {
+            if peel {
+                head.target()
+            } else {
+                &head.commit
+            }
+        };
+        if let Some(head) = self
+            .heads
+            .get(reference)
+            .or_else(|| self.heads.get(&format!("refs/heads/{reference}")))
+            .or_else(|| self.heads.get(&format!("refs/tags/{reference}")))
+        {
+            return Ok(target(head));
+        }
+
+        // Try treating the reference as an abbreviated commit OID
+        let prefix = reference.to_ascii_lowercase();
+        if prefix.len() < 4 || !prefix.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(anyhow::Error::msg("ref not found"));
+        }
+        let candidates = self
+            .heads
+            .values()
+            .map(target)
+            .filter(|c| c.starts_with(&prefix))
+            .collect::<BTreeSet<_>>();
+        match candidates.len() {
+            0 => Err(anyhow::Error::msg("ref not found")),
+            1 => Ok(candidates.into_iter().next().unwrap()),
+            _ => Err(anyhow::Error::msg(format!(
+                "short commit id {reference} is ambiguous, candidates:\n{}",
+                candidates.into_iter().collect::<Vec<_>>().join("\n")
+            ))),
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            version: migrate::CURRENT_VERSION.to_string(),
+            dependencies: BTreeMap::new(),
+        }
+    }
+}
+
+/// Settings affecting how dependencies are fetched
+#[derive(Clone, Debug)]
+pub struct FetchOptions {
+    /// Proxy to fetch through, defaults to `http.proxy` or the environment
+    pub proxy: Option<String>,
+    /// CA bundle to verify HTTPS certificates against, defaults to `http.sslCAInfo`
+    pub ca_info: Option<PathBuf>,
+    /// Don't verify HTTPS certificates
+    pub insecure: bool,
+    /// How many times to retry fetches failing due to transient network errors
+    pub retries: u32,
+    /// Fail on dependencies without any refs (such as empty repositories), instead of
+    /// reporting them with [`ProgressSink::on_empty`]
+    pub strict: bool,
+    /// Never access the network, only using objects that are already in the repository
+    pub offline: bool,
+}
+
+impl Default for FetchOptions {
+    fn default() -> Self {
+        Self {
+            proxy: None,
+            ca_info: None,
+            insecure: false,
+            retries: 2,
+            strict: false,
+            offline: false,
+        }
+    }
+}
+
+/// Transfer progress of a fetch, as reported to [`ProgressSink::on_transfer`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Transfer {
+    pub received_objects: usize,
+    pub total_objects: usize,
+    pub indexed_deltas: usize,
+    pub total_deltas: usize,
+}
+
+/// Receives the progress of fetching dependencies, leaving its presentation to the caller
+///
+/// All methods do nothing by default.
+pub trait ProgressSink {
+    /// A dependency is about to be fetched from `url`
+    fn on_fetch(&mut self, _url: &str) {}
+    /// Objects have been received or indexed
+    fn on_transfer(&mut self, _transfer: Transfer) {}
+    /// The dependency at `url` has no refs, so nothing is vendored for it
+    fn on_empty(&mut self, _url: &str) {}
+    /// Fetching `url` failed with a transient `error`, and will be retried after `delay`
+    fn on_retry(
+        &mut self,
+        _url: &str,
+        _error: &git2::Error,
+        _delay: Duration,
+        _attempt: u32,
+        _retries: u32,
+    ) {
+    }
+}
+
+/// [`ProgressSink`] that ignores all progress
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoProgress;
+
+impl ProgressSink for NoProgress {}
+
+/// Options for [`add`]
+#[derive(Clone, Debug, Default)]
+pub struct AddOptions {
+    pub fetch: FetchOptions,
+    /// Commit message, defaults to `Add <name> from <url>`
+    pub message: Option<String>,
+    /// Stage the dependency in [`STAGED_REF`] instead of committing it
+    pub no_commit: bool,
+    /// Description recorded in [`Dependency::description`]
+    pub description: Option<String>,
+    /// Groups recorded in [`Dependency::groups`]
+    pub groups: Vec<String>,
+    /// Mirrors recorded in [`Dependency::mirrors`]
+    pub mirrors: Vec<String>,
+    /// Sign the commit, even if `commit.gpgSign` is not set
+    pub sign: bool,
+    /// Record objects that are already in the repository instead of fetching
+    ///
+    /// The heads are taken from [`AddOptions::heads`] or, if there are none, from the
+    /// remote-tracking branches of a remote with the same URL.
+    pub no_fetch: bool,
+    /// Heads to record with [`AddOptions::no_fetch`], as ref names and revisions
+    pub heads: Vec<(String, String)>,
+}
+
+/// Options for [`sync`]
+#[derive(Clone, Debug, Default)]
+pub struct SyncOptions {
+    pub fetch: FetchOptions,
+    /// Commit message, defaults to `Sync: <names>`
+    pub message: Option<String>,
+    /// Keep syncing other dependencies if some fail
+    pub keep_going: bool,
+    /// Fetch dependencies and report changes without committing them
+    pub dry_run: bool,
+    /// Also sync members of these groups, in addition to the named dependencies
+    pub groups: Vec<String>,
+    /// Sign the commit, even if `commit.gpgSign` is not set
+    pub sign: bool,
+    /// How many dependencies to fetch at once, 0 meaning one per CPU
+    ///
+    /// With 1, dependencies are fetched one after another, in order.
+    pub jobs: usize,
+    /// Minimum interval between fetches from the same host
+    pub fetch_delay: Duration,
+    /// Only update these refs (full or short names), leaving the others as they were
+    ///
+    /// Requires syncing a single dependency.
+    pub refs: Vec<String>,
+}
+
+/// Outcome of [`sync`]
+#[derive(Debug, Default)]
+pub struct SyncReport {
+    /// Dependencies whose heads changed
+    pub changed: Vec<String>,
+    /// Refs that were deleted upstream, as dependency name and ref name
+    pub deleted: Vec<(String, String)>,
+    /// Disabled dependencies that weren't synced
+    pub skipped: Vec<String>,
+    /// Frozen dependencies that weren't synced
+    pub frozen: Vec<String>,
+    /// Dependencies that failed to sync (only with [`SyncOptions::keep_going`])
+    pub failed: Vec<(String, anyhow::Error)>,
+    /// New paravendor commit, if anything changed (never set with [`SyncOptions::dry_run`])
+    pub commit: Option<git2::Oid>,
+    /// Objects the sync brings into the paravendor branch
+    pub objects: ObjectCounts,
+    /// Changed refs, by dependency
+    pub refs: BTreeMap<String, BTreeMap<String, RefChange>>,
+}
+
+/// Numbers of objects, by type
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct ObjectCounts {
+    pub commits: usize,
+    pub trees: usize,
+    pub blobs: usize,
+}
+
+/// Objects of dependencies, as reported by [`size`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct Footprint {
+    pub objects: ObjectCounts,
+    /// Total size of the blobs
+    pub blob_bytes: u64,
+}
+
+/// Outcome of [`size`]
+#[derive(Debug, Default, PartialEq)]
+pub struct SizeReport {
+    /// Objects of each dependency that aren't shared with any other dependency
+    pub dependencies: BTreeMap<String, Footprint>,
+    /// All objects of the dependencies, each counted once
+    pub total: Footprint,
+}
+
+/// Outcome of [`stats`]
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct StatsReport {
+    /// All objects of each dependency, including those shared with other dependencies
+    pub dependencies: BTreeMap<String, Footprint>,
+    /// Sum of the objects of each dependency, as if they were stored separately
+    pub sum: Footprint,
+    /// All objects of the dependencies, each counted once
+    pub unique: Footprint,
+}
+
+impl StatsReport {
+    /// Objects (and blob bytes) that are stored once instead of for each dependency
+    pub fn saved(&self) -> Footprint {
+        Footprint {
+            objects: ObjectCounts {
+                commits: self.sum.objects.commits - self.unique.objects.commits,
+                trees: self.sum.objects.trees - self.unique.objects.trees,
+                blobs: self.sum.objects.blobs - self.unique.objects.blobs,
+            },
+            blob_bytes: self.sum.blob_bytes - self.unique.blob_bytes,
+        }
+    }
+}
+
+/// Outcome of [`objects`]
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct ObjectsReport {
+    /// All objects of the dependency
+    pub total: Footprint,
+    /// Objects that no other dependency has
+    pub unique: Footprint,
+    /// Objects that other dependencies have, too
+    pub shared: Footprint,
+}
+
+/// Differences between the configs of two paravendor commits
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ConfigDiff {
+    /// Dependencies present only in the newer config
+    pub added: Vec<String>,
+    /// Dependencies present only in the older config
+    pub removed: Vec<String>,
+    /// Refs that changed in dependencies present in both configs
+    pub changed: BTreeMap<String, BTreeMap<String, RefChange>>,
+}
+
+/// Change of a dependency ref, `None` meaning the ref didn't exist
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RefChange {
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+impl ConfigDiff {
+    /// Compares two configs
+    pub fn new(old: &Config, new: &Config) -> Self {
+        let mut diff = ConfigDiff::default();
+        for (name, dependency) in &new.dependencies {
+            let Some(old_dependency) = old.dependencies.get(name) else {
+                diff.added.push(name.to_string());
+                continue;
+            };
+            let refs = old_dependency
+                .heads
+                .keys()
+                .chain(dependency.heads.keys())
+                .collect::<BTreeSet<_>>();
+            let changes = refs
+                .into_iter()
+                .filter_map(|reference| {
+                    let old = old_dependency.heads.get(reference);
+                    let new = dependency.heads.get(reference);
+                    (old != new).then(|| {
+                        (
+                            reference.to_string(),
+                            RefChange {
+                                old: old.map(|h| h.commit.clone()),
+                                new: new.map(|h| h.commit.clone()),
+                            },
+                        )
+                    })
+                })
+                .collect::<BTreeMap<_, _>>();
+            if !changes.is_empty() {
+                diff.changed.insert(name.to_string(), changes);
+            }
+        }
+        diff.removed = old
+            .dependencies
+            .keys()
+            .filter(|name| !new.dependencies.contains_key(*name))
+            .cloned()
+            .collect();
+        diff
+    }
+}
+
+/// Options for [`log`]
+#[derive(Clone, Debug, Default)]
+pub struct LogOptions {
+    /// Only return this many of the newest commits
+    pub max_count: Option<usize>,
+    /// Oldest first (applied after `max_count`, like `git log --reverse`)
+    pub reverse: bool,
+    /// Also find out what each commit brought in ([`LogEntry::merged`] and
+    /// [`LogEntry::changes`])
+    pub changes: bool,
+    /// Only commits that added, removed or changed this dependency
+    pub dependency: Option<String>,
+}
+
+/// A commit on the paravendor branch
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogEntry {
+    pub id: git2::Oid,
+    pub summary: String,
+    /// Dependency commits this commit has as parents, besides the previous paravendor commit
+    pub merged: Vec<MergedCommit>,
+    /// Changes to the config since the previous paravendor commit
+    pub changes: Option<ConfigDiff>,
+}
+
+/// Dependency commit brought into the paravendor branch, see [`LogEntry::merged`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct MergedCommit {
+    pub id: git2::Oid,
+    /// Dependencies and their refs that point to this commit
+    pub refs: Vec<(String, String)>,
+}
+
+/// Name of the local paravendor branch
+///
+/// It's `paravendor`, unless [`BRANCH_NAME_CONFIG`] is set (by `init --local-branch`).
+/// Paravendor branches on remotes and in bundles are always called `paravendor`.
+pub fn branch_name(repository: &Repository) -> Result<String, anyhow::Error> {
+    match repository.config()?.get_string(BRANCH_NAME_CONFIG) {
+        Ok(name) => Ok(name),
+        Err(e) if e.code() == git2::ErrorCode::NotFound => Ok("paravendor".to_string()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Full ref name of the local paravendor branch
+fn branch_ref(repository: &Repository) -> Result<String, anyhow::Error> {
+    Ok(format!("refs/heads/{}", branch_name(repository)?))
+}
+
+/// Returns the paravendor branch and its config
+///
+/// If there's no local paravendor branch, it is created from a remote one when
+/// available. Configs written in an older format are upgraded in memory only, they're
+/// stored by the next commit of a mutating command, or by [`migrate`].
+pub fn ensure_initialized(
+    repository: &Repository,
+) -> Result<(git2::Branch<'_>, Config), anyhow::Error> {
+    let name = branch_name(repository)?;
+    let branch = match repository.find_branch(&name, BranchType::Local) {
+        Ok(branch) => Ok(branch),
+        Err(e) if e.code() == git2::ErrorCode::NotFound => {
+            match remote_with_paravendor(repository)? {
+                Some(remote) => paravendor_from_remote(repository, &remote)
+                    .and_then(|_| Ok(repository.find_branch(&name, BranchType::Local)?)),
+                None => Err(Error::NotInitialized.into()),
+            }
+        }
+        Err(e) => Err(e.into()),
+    };
+    branch.and_then(|branch| {
+        let obj = repository.revparse_single(&format!("refs/heads/{name}:config"))?;
+        if obj.kind() == Some(ObjectType::Blob) {
+            let migrated = migrate::migrate(
+                std::str::from_utf8(obj.as_blob().unwrap().content())?,
+                false,
+            )?;
+            if let Some(from) = migrated.from {
+                debug!(from, to = migrated.config.version, "migrated config");
+            }
+            Ok((branch, migrated.config))
+        } else {
+            Err(anyhow::Error::msg("paravendor config not found"))
+        }
+    })
+}
+
+/// Picks the remote to take the paravendor branch from when there's no local one
+///
+/// Among the remotes that have a `paravendor` branch, `origin` is preferred, then the
+/// remote of the current branch's upstream. Any other remote is only picked if it's the
+/// only one, as there's no telling which one is right otherwise.
+fn remote_with_paravendor(repository: &Repository) -> Result<Option<String>, anyhow::Error> {
+    let remotes = repository.remotes()?;
+    let candidates = remotes
+        .iter()
+        .flatten()
+        .filter(|remote| {
+            repository
+                .find_branch(&format!("{remote}/paravendor"), BranchType::Remote)
+                .is_ok()
+        })
+        .collect::<Vec<_>>();
+    if candidates.contains(&"origin") {
+        return Ok(Some("origin".to_string()));
+    }
+    let upstream = repository
+        .head()
+        .ok()
+        .filter(Reference::is_branch)
+        .and_then(|head| head.name().map(str::to_string))
+        .and_then(|branch| repository.branch_upstream_remote(&branch).ok())
+        .and_then(|remote| remote.as_str().map(str::to_string));
+    if let Some(upstream) = upstream.filter(|upstream| candidates.contains(&upstream.as_str())) {
+        return Ok(Some(upstream));
+    }
+    match candidates.as_slice() {
+        [] => Ok(None),
+        [remote] => Ok(Some(remote.to_string())),
+        _ => Err(anyhow::Error::msg(format!(
+            "there's no paravendor branch, but several remotes have one ({}), pick one with \
+             --from-remote",
+            candidates.join(", ")
+        ))),
+    }
+}
+
+/// Creates the local paravendor branch (see [`branch_name`]) from `<remote>/paravendor`,
+/// with the latter as its upstream, returning its tip
+pub fn paravendor_from_remote(
+    repository: &Repository,
+    remote: &str,
+) -> Result<git2::Oid, anyhow::Error> {
+    branch_from_remote(repository, remote, &branch_name(repository)?)
+}
+
+/// Creates the local branch `name` from `<remote>/paravendor`, with the latter as its
+/// upstream, returning its tip
+fn branch_from_remote(
+    repository: &Repository,
+    remote: &str,
+    name: &str,
+) -> Result<git2::Oid, anyhow::Error> {
+    let branch = repository
+        .find_branch(&format!("{remote}/paravendor"), BranchType::Remote)
+        .map_err(|e| {
+            anyhow::Error::new(e).context(format!(
+                "{remote}/paravendor not found, fetch it from {remote} first"
+            ))
+        })?;
+    let commit = branch.get().peel_to_commit()?;
+    repository
+        .branch(name, &commit, false)?
+        .set_upstream(Some(&format!("{remote}/paravendor")))?;
+    info!(remote, "created paravendor branch from remote");
+    Ok(commit.id())
+}
+
+/// Commits `config` on top of `tip`, updating `reference` to point to the new commit
+///
+/// The paravendor branch is only updated if it still points to `tip`, failing otherwise.
+/// `parents` are added as extra parents of the commit so that their objects
+/// become reachable from the branch. The commit is signed if `sign` or
+/// `commit.gpgSign` is set. Commits on the paravendor branch carry the checksum of
+/// their config in a trailer, and those that change any dependency refs get a note
+/// listing the changes (see [`note`]).
+pub(crate) fn commit_config<'a>(
+    repository: &'a Repository,
+    reference: &str,
+    tip: git2::Commit<'a>,
+    config: &Config,
+    message: &str,
+    mut parents: Vec<git2::Commit<'a>>,
+    sign: bool,
+) -> Result<git2::Oid, anyhow::Error> {
+    let serialized_config = toml::to_string_pretty(config)?;
+
+    let mut tree = TreeUpdateBuilder::new();
+    let odb = repository.odb()?;
+    let blob = odb.write(ObjectType::Blob, serialized_config.as_bytes())?;
+    tree.upsert("config", blob, FileMode::Blob);
+    let tree_oid = tree.create_updated(repository, &tip.tree()?)?;
+
+    let paravendor = reference == branch_ref(repository)?;
+    let expected = paravendor.then(|| tip.id());
+    let (diff, message) = if paravendor {
+        let diff = ConfigDiff::new(&Config::from_commit(repository, &tip)?, config);
+        let checksum = sha256(serialized_config.as_bytes());
+        let message = message.trim_end();
+        // Joining the message's own trailers, if it has any
+        let separator = if git2::message_trailers_strs(message)?.len() > 0 {
+            "\n"
+        } else {
+            "\n\n"
+        };
+        (
+            Some(diff),
+            format!("{message}{separator}{CONFIG_SHA256_TRAILER}: {checksum}"),
+        )
+    } else {
+        (None, message.to_string())
+    };
+
+    parents.insert(0, tip);
+
+    let oid = create_commit(
+        repository,
+        reference,
+        &message,
+        &repository.find_tree(tree_oid)?,
+        &parents.iter().collect::<Vec<_>>(),
+        expected,
+        sign,
+    )?;
+    if let Some(diff) = diff.filter(|diff| diff != &ConfigDiff::default()) {
+        let author = identity::signature(repository, identity::Role::Author)?;
+        let committer = identity::signature(repository, identity::Role::Committer)?;
+        repository.note(
+            &author,
+            &committer,
+            Some(NOTES_REF),
+            oid,
+            &toml::to_string_pretty(&diff)?,
+            false,
+        )?;
+    }
+    Ok(oid)
+}
+
+fn sha256(data: &[u8]) -> String {
+    use sha2::Digest;
+    format!("{:x}", sha2::Sha256::digest(data))
+}
+
+/// Creates a commit and points `reference` to it, signing it if `sign` or
+/// `commit.gpgSign` is set
+///
+/// If `expected` is set, `reference` is only updated if it still points to it. Another
+/// process (or tool) may have moved it since it was read, and overwriting that would
+/// lose its commit.
+fn create_commit(
+    repository: &Repository,
+    reference: &str,
+    message: &str,
+    tree: &git2::Tree,
+    parents: &[&git2::Commit],
+    expected: Option<git2::Oid>,
+    sign: bool,
+) -> Result<git2::Oid, anyhow::Error> {
+    let author = identity::signature(repository, identity::Role::Author)?;
+    let committer = identity::signature(repository, identity::Role::Committer)?;
+    let oid = write_commit(
+        repository, &author, &committer, message, tree, parents, sign,
+    )?;
+    update_reference(
+        repository,
+        reference,
+        oid,
+        expected,
+        &format!("commit: {}", message.lines().next().unwrap_or_default()),
+    )?;
+    Ok(oid)
+}
+
+/// Writes a commit without pointing any reference to it, signing it if `sign` or
+/// `commit.gpgSign` is set
+fn write_commit(
+    repository: &Repository,
+    author: &git2::Signature,
+    committer: &git2::Signature,
+    message: &str,
+    tree: &git2::Tree,
+    parents: &[&git2::Commit],
+    sign: bool,
+) -> Result<git2::Oid, anyhow::Error> {
+    if !sign && !sign::enabled(repository) {
+        Ok(repository.commit(None, author, committer, message, tree, parents)?)
+    } else {
+        let buffer = repository.commit_create_buffer(author, committer, message, tree, parents)?;
+        let buffer = buffer
+            .as_str()
+            .ok_or_else(|| anyhow::Error::msg("commit is not valid UTF-8"))?;
+        let signature = sign::sign(repository, buffer, committer)?;
+        Ok(repository.commit_signed(buffer, &signature, None)?)
+    }
+}
+
+/// Points `reference` to `oid`, only if it still points to `expected` (when set)
+fn update_reference(
+    repository: &Repository,
+    reference: &str,
+    oid: git2::Oid,
+    expected: Option<git2::Oid>,
+    log_message: &str,
+) -> Result<(), anyhow::Error> {
+    // The ref stays locked between checking and updating it
+    let mut transaction = repository.transaction()?;
+    transaction.lock_ref(reference)?;
+    if let Some(expected) = expected {
+        let current = repository.refname_to_id(reference).ok();
+        if current != Some(expected) {
+            let current = current.map_or_else(|| "nothing".to_string(), |oid| oid.to_string());
+            return Err(anyhow::Error::msg(format!(
+                "{reference} moved from {expected} to {current} in the meantime, re-run to \
+                 start from there"
+            )));
+        }
+    }
+    transaction.set_target(reference, oid, None, log_message)?;
+    transaction.commit()?;
+    Ok(())
+}
+
+/// Fetches a dependency with [`sync_dependency`], trying its `mirrors` in order if
+/// fetching from `url` fails
+///
+/// If all of them fail, the error of `url` is returned.
+fn sync_from_mirrors<'a>(
+    repository: &'a Repository,
+    url: &str,
+    mirrors: &[String],
+    known: Option<&BTreeMap<String, Head>>,
+    refs: &[String],
+    options: &FetchOptions,
+    progress: &mut dyn ProgressSink,
+) -> Result<FetchedDependency<'a>, anyhow::Error> {
+    let error = match sync_dependency(repository, url, known, refs, options, progress) {
+        Ok(fetched) => return Ok(fetched),
+        Err(e) => e,
+    };
+    for mirror in mirrors {
+        warn!(url, mirror, error = format!("{error:#}"), "trying mirror");
+        match sync_dependency(repository, mirror, known, refs, options, progress) {
+            Ok(fetched) => {
+                info!(url, mirror, "fetched from mirror");
+                return Ok(fetched);
+            }
+            Err(e) => warn!(mirror, error = format!("{e:#}"), "mirror failed"),
+        }
+    }
+    Err(error)
+}
+
+/// Outcome of connecting to a dependency URL, see [`health`]
+#[derive(Debug, Serialize)]
+pub struct HealthCheck {
+    /// Dependency name
+    pub name: String,
+    /// URL connected to, as recorded
+    pub url: String,
+    /// Whether `url` is one of the dependency's mirrors
+    pub mirror: bool,
+    pub reachable: bool,
+    /// Why connecting failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// How long connecting took, in milliseconds
+    pub elapsed_ms: u64,
+}
+
+/// Connects to the URL and mirrors of every dependency, without fetching anything
+pub fn health(
+    repository: &Repository,
+    options: &FetchOptions,
+) -> Result<Vec<HealthCheck>, anyhow::Error> {
+    if options.offline {
+        return Err(anyhow::Error::msg(
+            "can't check dependencies in offline mode",
+        ));
+    }
+    let (_, config) = ensure_initialized(repository)?;
+    let mut checks = vec![];
+    for (name, dependency) in &config.dependencies {
+        let urls = std::iter::once((&dependency.url, false))
+            .chain(dependency.mirrors.iter().map(|mirror| (mirror, true)));
+        for (url, mirror) in urls {
+            let started = Instant::now();
+            let result = connect(repository, url, options);
+            let elapsed_ms = started.elapsed().as_millis() as u64;
+            match &result {
+                Ok(()) => debug!(name, url, elapsed_ms, "reachable"),
+                Err(e) => warn!(name, url, error = format!("{e:#}"), "unreachable"),
+            }
+            checks.push(HealthCheck {
+                name: name.clone(),
+                url: url.clone(),
+                mirror,
+                reachable: result.is_ok(),
+                error: result.err().map(|e| format!("{e:#}")),
+                elapsed_ms,
+            });
+        }
+    }
+    Ok(checks)
+}
+
+/// Connects to `url` for fetching, the way [`sync_dependency`] does, and disconnects
+fn connect(
+    repository: &Repository,
+    url: &str,
+    options: &FetchOptions,
+) -> Result<(), anyhow::Error> {
+    let (fetched_url, connect_url, identity_files) = resolve_url(repository, url)?;
+    let proxy_url = configure_transport(repository, &fetched_url, options)?;
+    let git_config = repository.config()?;
+    let mut callbacks = RemoteCallbacks::new();
+    let mut credentials = transport::Credentials::new(&git_config, identity_files);
+    callbacks.credentials(move |url, username, allowed| credentials.get(url, username, allowed));
+    if options.insecure {
+        callbacks.certificate_check(|_, _| Ok(CertificateCheckStatus::CertificateOk));
+    }
+    let mut remote = repository.remote_anonymous(&connect_url)?;
+    let connection = remote.connect_auth(
+        Direction::Fetch,
+        Some(callbacks),
+        Some(proxy_options(proxy_url.as_deref())),
+    );
+    if let Err(e) = connection {
+        let context = match transport::hint(&e) {
+            Some(hint) => format!("failed to connect to {url}, {hint}"),
+            None => format!("failed to connect to {url}"),
+        };
+        return Err(anyhow::Error::new(e).context(context));
+    }
+    Ok(())
+}
+
+/// Drops commits that are already reachable from `tip`, as they don't need to become
+/// parents again
+fn unreachable_from<'a>(
+    repository: &'a Repository,
+    tip: &git2::Commit,
+    commits: Vec<git2::Commit<'a>>,
+) -> Result<Vec<git2::Commit<'a>>, anyhow::Error> {
+    let mut unreachable = vec![];
+    for commit in commits {
+        if commit.id() != tip.id() && !repository.graph_descendant_of(tip.id(), commit.id())? {
+            unreachable.push(commit);
+        }
+    }
+    Ok(unreachable)
+}
+
+/// Returns the commit and config staged by `add --no-commit`, if any
+pub fn staged(
+    repository: &Repository,
+) -> Result<Option<(git2::Commit<'_>, Config)>, anyhow::Error> {
+    match repository.find_reference(STAGED_REF) {
+        Ok(reference) => {
+            let commit = reference.peel_to_commit()?;
+            let config = Config::from_commit(repository, &commit)?;
+            Ok(Some((commit, config)))
+        }
+        Err(e) if e.code() == git2::ErrorCode::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Applies the CA bundle from `options` (or git config) and picks the proxy to use for `url`
+fn configure_transport(
+    repository: &Repository,
+    url: &str,
+    options: &FetchOptions,
+) -> Result<Option<String>, anyhow::Error> {
+    let config = repository.config()?;
+    if let Some(ca_info) = options
+        .ca_info
+        .clone()
+        .or_else(|| config.get_path("http.sslCAInfo").ok())
+    {
+        transport::set_ca_info(&ca_info)?;
+    }
+    let proxy_url = url::proxy(
+        url,
+        options.proxy.as_deref(),
+        config.get_string("http.proxy").ok().as_deref(),
+        |var| std::env::var(var).ok(),
+    );
+    if let Some(ref proxy_url) = proxy_url {
+        debug!(url, proxy = proxy_url, "using proxy");
+    }
+    Ok(proxy_url)
+}
+
+/// Resolves a dependency URL, returning the URL to validate and pick a proxy for, the
+/// URL to connect to and the SSH identity files to authenticate with
+fn resolve_url(
+    repository: &Repository,
+    url: &str,
+) -> Result<(String, String, Vec<PathBuf>), anyhow::Error> {
+    // Messages keep referring to the URL as stored, as the expanded one may contain secrets
+    let expanded_url = url::expand(url, |var| std::env::var(var).ok())?;
+    // libgit2 applies `url.<base>.insteadOf` rewrites when connecting, but the rewritten
+    // URL is the one to validate and pick a proxy for
+    let fetched_url = url::rewrite(
+        &expanded_url,
+        &url::instead_of_rules(&repository.config()?)?,
+    );
+    url::validate(&fetched_url)?;
+    // libgit2 doesn't read the SSH config, so host aliases are resolved here
+    let (connect_url, identity_files) = match ssh::resolve_url(&fetched_url) {
+        Some((resolved_url, identity_files)) if resolved_url != fetched_url => {
+            debug!(url, "resolved SSH host alias");
+            (resolved_url, identity_files)
+        }
+        Some((_, identity_files)) => (expanded_url, identity_files),
+        None => (expanded_url, vec![]),
+    };
+    Ok((fetched_url, connect_url, identity_files))
+}
+
+/// Outcome of [`sync_dependency`]
+#[derive(Debug)]
+pub struct FetchedDependency<'a> {
+    /// Heads advertised by the dependency
+    pub heads: BTreeMap<String, Head>,
+    /// Commits that need to be parents of the paravendor commit to keep the heads reachable
+    pub commits: Vec<git2::Commit<'a>>,
+    /// Whether objects were downloaded, which is skipped if the heads are unchanged
+    pub downloaded: bool,
+    /// URL the heads were fetched from, `None` if nothing was fetched
+    pub source: Option<String>,
+}
+
+/// Fetches a dependency, returning its heads and the commits that need to be
+/// parents of the paravendor commit to keep them reachable
+///
+/// If the advertised heads are the same as `known` (the recorded heads) and their
+/// objects are present, nothing is downloaded and no commits are returned, as they are
+/// already reachable.
+///
+/// Unless `refs` is empty, only the heads matching them (full or short names) are
+/// compared, downloaded and returned.
+pub fn sync_dependency<'a>(
+    repository: &'a Repository,
+    url: &str,
+    known: Option<&BTreeMap<String, Head>>,
+    refs: &[String],
+    options: &FetchOptions,
+    progress: &mut dyn ProgressSink,
+) -> Result<FetchedDependency<'a>, anyhow::Error> {
+    if options.offline {
+        let known = known
+            .ok_or_else(|| anyhow::Error::msg(format!("can't fetch {url} in offline mode")))?;
+        let odb = repository.odb()?;
+        for (name, head) in known {
+            if !odb.exists(git2::Oid::from_str(&head.commit)?) {
+                return Err(anyhow::Error::msg(format!(
+                    "{name} of {url} is missing, and can't be fetched in offline mode"
+                )));
+            }
+        }
+        info!(url, "offline, using local objects");
+        return Ok(FetchedDependency {
+            heads: known.clone(),
+            commits: vec![],
+            downloaded: false,
+            source: None,
+        });
+    }
+    let (fetched_url, connect_url, identity_files) = resolve_url(repository, url)?;
+    let git_config = repository.config()?;
+    info!(url, "fetching");
+    progress.on_fetch(url);
+    let started = Instant::now();
+    let mut remote = repository.remote_anonymous(&connect_url)?;
+    // Shared by the callbacks of the connection and the downloads
+    let progress = RefCell::new(progress);
+
+    let proxy_url = configure_transport(repository, &fetched_url, options)?;
+
+    // Options can't be reused across fetch attempts, so they are built for each one
+    let callbacks = || {
+        let mut cb = RemoteCallbacks::new();
+        cb.transfer_progress(|p| {
+            progress.borrow_mut().on_transfer(Transfer {
+                received_objects: p.received_objects(),
+                total_objects: p.total_objects(),
+                indexed_deltas: p.indexed_deltas(),
+                total_deltas: p.total_deltas(),
+            });
+            true
+        });
+        let mut credentials = transport::Credentials::new(&git_config, identity_files.clone());
+        cb.credentials(move |url, username, allowed| credentials.get(url, username, allowed));
+        if options.insecure {
+            cb.certificate_check(|_, _| Ok(CertificateCheckStatus::CertificateOk));
+        }
+        cb
+    };
+    let fetch_options = || {
+        let mut options = git2::FetchOptions::new();
+        options
+            .download_tags(AutotagOption::None)
+            .proxy_options(proxy_options(proxy_url.as_deref()))
+            .remote_callbacks(callbacks());
+        options
+    };
+    let odb = repository.odb()?;
+    // Peeled annotated tags (`<tag>^{}`) go along with their tags
+    let wanted = |name: &str| {
+        let name = name.trim_end_matches("^{}");
+        refs.is_empty() || refs.iter().any(|r| matches_ref(name, r))
+    };
+    // Heads are unchanged if they are the same as the known ones, and we have their objects
+    let unchanged = |advertised: &[(String, git2::Oid)]| {
+        let Some(known) = known else {
+            return false;
+        };
+        let advertised_heads = advertised
+            .iter()
+            .filter(|(name, _)| !name.ends_with("^{}"))
+            .collect::<Vec<_>>();
+        advertised_heads.len() == known.keys().filter(|name| wanted(name)).count()
+            && advertised_heads.iter().all(|(name, oid)| {
+                known
+                    .get(name)
+                    .is_some_and(|head| head.commit == oid.to_string())
+            })
+            && advertised.iter().all(|(_, oid)| odb.exists(*oid))
+    };
+
+    let fetch_error = |e: git2::Error| {
+        let context = match transport::hint(&e) {
+            Some(hint) => format!("failed to fetch {url}, {hint}"),
+            None => format!("failed to fetch {url}"),
+        };
+        anyhow::Error::new(e).context(context)
+    };
+
+    let empty_local = transport::is_empty_local(&connect_url);
+    // Refs are listed and objects downloaded over a single connection,
+    // and only if the heads have changed
+    let mut connect_and_download = || -> Result<(Vec<(String, git2::Oid)>, bool), git2::Error> {
+        let mut connection = remote.connect_auth(
+            Direction::Fetch,
+            Some(callbacks()),
+            Some(proxy_options(proxy_url.as_deref())),
+        )?;
+        let advertised = if empty_local {
+            vec![]
+        } else {
+            connection
+                .list()?
+                .iter()
+                .map(|h| (h.name().to_string(), h.oid()))
+                .collect::<Vec<_>>()
+        };
+        let matching = advertised
+            .iter()
+            .filter(|(name, _)| wanted(name))
+            .cloned()
+            .collect::<Vec<_>>();
+        let download = !unchanged(&matching);
+        if download {
+            // Without refspecs, every advertised ref would be downloaded
+            let refspecs = if refs.is_empty() {
+                vec![]
+            } else {
+                matching
+                    .iter()
+                    .filter(|(name, _)| !name.ends_with("^{}"))
+                    .map(|(name, _)| name.as_str())
+                    .collect::<Vec<_>>()
+            };
+            if refs.is_empty() || !refspecs.is_empty() {
+                connection
+                    .remote()
+                    .download(&refspecs, Some(&mut fetch_options()))?;
+            }
+        }
+        Ok((advertised, download))
+    };
+
+    let mut attempt = 0;
+    let (advertised, downloaded) = loop {
+        match connect_and_download() {
+            Ok(result) => break result,
+            Err(e) if attempt < options.retries && transport::is_transient(&e) => {
+                attempt += 1;
+                let delay = transport::backoff(attempt);
+                progress
+                    .borrow_mut()
+                    .on_retry(url, &e, delay, attempt, options.retries);
+                std::thread::sleep(delay);
+            }
+            Err(e) => return Err(fetch_error(e)),
+        }
+    };
+    debug!(url, downloaded, elapsed = ?started.elapsed(), "fetched");
+    if advertised.is_empty() {
+        if options.strict {
+            return Err(anyhow::Error::msg(format!(
+                "{url} has no refs, is the repository empty?"
+            )));
+        }
+        warn!(url, "no refs");
+        progress.borrow_mut().on_empty(url);
+    }
+
+    if !downloaded {
+        info!(url, "unchanged");
+        return Ok(FetchedDependency {
+            heads: known.cloned().unwrap_or_default(),
+            commits: vec![],
+            downloaded,
+            source: Some(url.to_string()),
+        });
+    }
+    let advertised = advertised
+        .into_iter()
+        .filter(|(name, _)| wanted(name))
+        .collect::<Vec<_>>();
+
+    let started = Instant::now();
+    // Make sure every advertised object has arrived, asking for the missing ones explicitly
+    let missing = |advertised: &[(String, git2::Oid)]| {
+        advertised
+            .iter()
+            .filter(|(_, oid)| !odb.exists(*oid))
+            .cloned()
+            .collect::<Vec<_>>()
+    };
+    let missing_objects = missing(&advertised);
+    if !missing_objects.is_empty() {
+        debug!(
+            url,
+            missing = missing_objects.len(),
+            "fetching missing objects"
+        );
+        let oids = missing_objects
+            .iter()
+            .map(|(_, oid)| oid.to_string())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        remote
+            .fetch(&oids, Some(&mut fetch_options()), None)
+            .map_err(fetch_error)?;
+        if let Some((name, oid)) = missing(&advertised).first() {
+            return Err(anyhow::Error::msg(format!(
+                "{url} advertised {name} at {oid}, but didn't send it"
+            )));
+        }
+    }
+
+    // Peeled annotated tags are advertised as `<tag>^{}`, they are recorded in the tag's head
+    let heads = advertised
+        .iter()
+        .filter(|(name, _)| !name.ends_with("^{}"))
+        .map(|(name, oid)| {
+            let object = repository.find_object(*oid, None)?;
+            let peeled = if object.kind() == Some(ObjectType::Tag) {
+                Some(object.peel(ObjectType::Any)?.id().to_string())
+            } else {
+                None
+            };
+            Ok((
+                name.to_string(),
+                Head {
+                    commit: oid.to_string(),
+                    peeled,
+                },
+            ))
+        })
+        .collect::<Result<BTreeMap<_, _>, anyhow::Error>>()?;
+
+    // Annotated tags bring in the commits they point to, whether or not their peeled
+    // objects were advertised
+    let mut head_commits = vec![];
+    for head in heads.values() {
+        let oid = git2::Oid::from_str(head.target())?;
+        if odb.read_header(oid)?.1 == ObjectType::Commit {
+            head_commits.push(oid);
+        }
+    }
+    info!(url, refs = heads.len(), "received refs");
+    debug!(url, elapsed = ?started.elapsed(), "listed refs");
+
+    let started = Instant::now();
+    let pruned_head_commits = prune_head_commits(repository, &head_commits)
+        .map_err(|e| e.context(format!("failed to prune head commits of {url}")))?;
+
+    debug!(
+        url,
+        commits = pruned_head_commits.len(),
+        elapsed = ?started.elapsed(),
+        "pruned head commits"
+    );
+
+    Ok(FetchedDependency {
+        heads,
+        commits: pruned_head_commits,
+        downloaded,
+        source: Some(url.to_string()),
+    })
+}
+
+/// Drops duplicate commits and the ones that are in the history of other commits
+///
+/// Commits reachable from other commits are exactly those reachable from the parents
+/// of all commits, so a single walk from them finds all of them.
+fn prune_head_commits<'a>(
+    repository: &'a Repository,
+    head_commits: &[git2::Oid],
+) -> Result<Vec<git2::Commit<'a>>, anyhow::Error> {
+    let mut head_commits = head_commits.iter().copied().collect::<BTreeSet<_>>();
+    let mut revwalk = repository.revwalk()?;
+    for oid in &head_commits {
+        for parent in repository.find_commit(*oid)?.parent_ids() {
+            revwalk.push(parent)?;
+        }
+    }
+    for oid in revwalk {
+        head_commits.remove(&oid?);
+    }
+    head_commits
+        .into_iter()
+        .map(|oid| Ok(repository.find_commit(oid)?))
+        .collect()
+}
+
+/// Initializes paravendor in a repository, returning the paravendor branch tip
+///
+/// Unless `ignore_remote` is set, an existing remote paravendor branch is used, picked
+/// like [`ensure_initialized`] does: from `origin`, the remote of the current branch's
+/// upstream, or the only remote that has one. `local_branch` names the local paravendor
+/// branch (see [`branch_name`]).
+pub fn init(
+    repository: &Repository,
+    ignore_remote: bool,
+    local_branch: Option<&str>,
+) -> Result<git2::Oid, anyhow::Error> {
+    let _lock = lock::Lock::acquire(repository)?;
+    let name = local_branch_name(repository, local_branch)?;
+    if !ignore_remote {
+        if let Some(remote) = remote_with_paravendor(repository)? {
+            let tip = branch_from_remote(repository, &remote, &name)?;
+            record_local_branch(repository, &name)?;
+            return Ok(tip);
+        }
+    }
+
+    let config = Config::default();
+    let serialized_config = toml::to_string_pretty(&config)?;
+
+    // Prepare initial commit
+    let mut tree = repository.treebuilder(None)?;
+    let odb = repository.odb()?;
+    let blob = odb.write(ObjectType::Blob, serialized_config.as_bytes())?;
+    tree.insert("config", blob, 0o100644)?;
+    let tree_oid = tree.write()?;
+
+    // Commit it, creating the branch
+    let tip = create_commit(
+        repository,
+        &format!("refs/heads/{name}"),
+        "Initialize paravendor",
+        &repository.find_tree(tree_oid)?,
+        &[],
+        None,
+        false,
+    )?;
+    record_local_branch(repository, &name)?;
+    Ok(tip)
+}
+
+/// Initializes paravendor from the paravendor branch of `remote`, which has to be
+/// fetched already, returning the paravendor branch tip
+///
+/// `local_branch` names the local paravendor branch (see [`branch_name`]).
+pub fn init_from_remote(
+    repository: &Repository,
+    remote: &str,
+    local_branch: Option<&str>,
+) -> Result<git2::Oid, anyhow::Error> {
+    let _lock = lock::Lock::acquire(repository)?;
+    let name = local_branch_name(repository, local_branch)?;
+    let tip = branch_from_remote(repository, remote, &name)?;
+    record_local_branch(repository, &name)?;
+    Ok(tip)
+}
+
+/// Returns the name to create the local paravendor branch under, `local_branch` if given
+///
+/// Fails if the paravendor branch already exists, or if another name for it is already
+/// recorded, as the existing branch would be left behind.
+fn local_branch_name(
+    repository: &Repository,
+    local_branch: Option<&str>,
+) -> Result<String, anyhow::Error> {
+    let current = branch_name(repository)?;
+    if repository.find_branch(&current, BranchType::Local).is_ok() {
+        return Err(anyhow::Error::msg(format!(
+            "'{current}' branch already exists"
+        )));
+    }
+    let Some(name) = local_branch else {
+        return Ok(current);
+    };
+    if !git2::Branch::name_is_valid(name)? {
+        return Err(anyhow::Error::msg(format!(
+            "'{name}' is not a valid branch name"
+        )));
+    }
+    match repository.config()?.get_string(BRANCH_NAME_CONFIG) {
+        Ok(recorded) if recorded != name => {
+            return Err(anyhow::Error::msg(format!(
+                "the local paravendor branch is already set to '{recorded}' \
+                 ({BRANCH_NAME_CONFIG}), unset it first"
+            )));
+        }
+        Ok(_) => {}
+        Err(e) if e.code() == git2::ErrorCode::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    if repository.find_branch(name, BranchType::Local).is_ok() {
+        return Err(anyhow::Error::msg(format!(
+            "'{name}' branch already exists"
+        )));
+    }
+    Ok(name.to_string())
+}
+
+/// Records `name` as the name of the local paravendor branch, once it's been created
+fn record_local_branch(repository: &Repository, name: &str) -> Result<(), anyhow::Error> {
+    if name != branch_name(repository)? {
+        repository.config()?.set_str(BRANCH_NAME_CONFIG, name)?;
+    }
+    Ok(())
+}
+
+/// Vendorizes a new dependency, returning the new paravendor (or staged) commit
+pub fn add(
+    repository: &Repository,
+    name: &str,
+    url: &str,
+    options: &AddOptions,
+    progress: &mut dyn ProgressSink,
+) -> Result<git2::Oid, anyhow::Error> {
+    let _lock = lock::Lock::acquire(repository)?;
+    let (branch, committed_config) = ensure_initialized(repository)?;
+    let staged = if options.no_commit {
+        staged(repository)?
+    } else {
+        None
+    };
+    let (tip, mut config) = match staged {
+        Some(staged) => staged,
+        None => (
+            branch.into_reference().peel_to_commit()?,
+            committed_config.clone(),
+        ),
+    };
+    let branch_ref = branch_ref(repository)?;
+    let reference = if options.no_commit {
+        STAGED_REF
+    } else {
+        &branch_ref
+    };
+    if committed_config.dependencies.contains_key(name) || config.dependencies.contains_key(name) {
+        return Err(anyhow::Error::msg(format!(
+            "{name} has been already added, aborting"
+        )));
+    }
+
+    let FetchedDependency {
+        heads,
+        commits,
+        source,
+        ..
+    } = if options.no_fetch {
+        local_dependency(repository, url, &options.heads)?
+    } else {
+        sync_from_mirrors(
+            repository,
+            url,
+            &options.mirrors,
+            None,
+            &[],
+            &options.fetch,
+            progress,
+        )?
+    };
+    let pruned_head_commits = unreachable_from(repository, &tip, commits)?;
+
+    config.dependencies.insert(
+        name.to_string(),
+        Dependency {
+            url: url.to_string(),
+            mirrors: options.mirrors.clone(),
+            description: options.description.clone(),
+            disabled: false,
+            frozen: false,
+            groups: options.groups.clone(),
+            last_synced: Some(synced_at(repository)?),
+            last_source: source,
+            heads,
+        },
+    );
+
+    let message = match options.message {
+        Some(ref message) => message.clone(),
+        None if options.no_commit => format!("Stage {} from {}", name, url),
+        None => format!("Add {} from {}", name, url),
+    };
+    commit_config(
+        repository,
+        reference,
+        tip,
+        &config,
+        &message,
+        pruned_head_commits,
+        options.sign,
+    )
+}
+
+/// Heads of a dependency whose objects are already in the repository, either as given
+/// (ref names and revisions) or as tracked by a remote fetching from `url`
+fn local_dependency<'a>(
+    repository: &'a Repository,
+    url: &str,
+    given: &[(String, String)],
+) -> Result<FetchedDependency<'a>, anyhow::Error> {
+    url::validate(&url::expand(url, |var| std::env::var(var).ok())?)?;
+    let mut refs = vec![];
+    for (name, revision) in given {
+        let object = repository.revparse_single(revision).map_err(|e| {
+            anyhow::Error::new(e).context(format!("{revision} is not in the repository"))
+        })?;
+        refs.push((name.clone(), object.id()));
+    }
+    if given.is_empty() {
+        let remote = repository
+            .remotes()?
+            .iter()
+            .flatten()
+            .find(|remote| {
+                repository
+                    .find_remote(remote)
+                    .is_ok_and(|remote| remote.url() == Some(url))
+            })
+            .map(str::to_string)
+            .ok_or_else(|| {
+                anyhow::Error::msg(format!(
+                    "no remote fetches from {url}, specify the heads to record"
+                ))
+            })?;
+        let prefix = format!("refs/remotes/{remote}/");
+        for reference in repository.references_glob(&format!("{prefix}*"))? {
+            let reference = reference?;
+            let (Some(name), Ok(resolved)) = (reference.name(), reference.resolve()) else {
+                continue;
+            };
+            let Some(oid) = resolved.target() else {
+                continue;
+            };
+            let branch = &name[prefix.len()..];
+            refs.push(match branch {
+                "HEAD" => ("HEAD".to_string(), oid),
+                branch => (format!("refs/heads/{branch}"), oid),
+            });
+        }
+        if refs.is_empty() {
+            return Err(anyhow::Error::msg(format!(
+                "remote {remote} has no remote-tracking branches, fetch it first"
+            )));
+        }
+    }
+
+    let mut heads = BTreeMap::new();
+    let mut head_commits = vec![];
+    for (name, oid) in refs {
+        let object = repository.find_object(oid, None)?;
+        // Peeling anything but tags would go from commits to their trees
+        let peeled = match object.kind() {
+            Some(ObjectType::Tag) => Some(object.peel(ObjectType::Any)?),
+            _ => None,
+        };
+        let target = peeled.as_ref().unwrap_or(&object);
+        if target.kind() == Some(ObjectType::Commit) {
+            head_commits.push(target.id());
+        }
+        heads.insert(
+            name,
+            Head {
+                commit: oid.to_string(),
+                peeled: peeled.map(|peeled| peeled.id().to_string()),
+            },
+        );
+    }
+    Ok(FetchedDependency {
+        heads,
+        commits: prune_head_commits(repository, &head_commits)?,
+        downloaded: false,
+        source: None,
+    })
+}
+
+/// Commits dependencies staged with [`AddOptions::no_commit`], returning their names
+///
+/// Staged dependencies that have been added to the paravendor branch in the
+/// meantime are skipped.
+pub fn commit_staged(
+    repository: &Repository,
+    message: Option<&str>,
+    sign: bool,
+) -> Result<Vec<String>, anyhow::Error> {
+    let _lock = lock::Lock::acquire(repository)?;
+    let (branch, mut config) = ensure_initialized(repository)?;
+    let (staged, staged_config) = staged(repository)?
+        .ok_or_else(|| anyhow::Error::msg("nothing staged, use `add --no-commit`"))?;
+    let tip = branch.into_reference().peel_to_commit()?;
+
+    // Collect the dependency commits brought in by the staged commits,
+    // which are stacked on top of a (former) paravendor branch tip
+    let mut parents = vec![];
+    let mut commit = staged;
+    while commit.id() != tip.id() && !repository.graph_descendant_of(tip.id(), commit.id())? {
+        parents.extend(commit.parents().skip(1));
+        commit = commit.parent(0)?;
+    }
+
+    // Only bring in dependencies that are still missing on the branch
+    let mut added = vec![];
+    for (name, dependency) in staged_config.dependencies {
+        if !config.dependencies.contains_key(&name) {
+            config.dependencies.insert(name.clone(), dependency);
+            added.push(name);
+        }
+    }
+
+    if !added.is_empty() {
+        commit_config(
+            repository,
+            &branch_ref(repository)?,
+            tip,
+            &config,
+            &message
+                .map(str::to_string)
+                .unwrap_or_else(|| format!("Add {}", added.join(", "))),
+            parents,
+            sign,
+        )?;
+    }
+    repository.find_reference(STAGED_REF)?.delete()?;
+    Ok(added)
+}
+
+/// Timestamp for [`Dependency::last_synced`], the time of the commit recording it
+fn synced_at(repository: &Repository) -> Result<String, anyhow::Error> {
+    let author = identity::signature(repository, identity::Role::Author)?;
+    Ok(timestamp::rfc3339(author.when().seconds()))
+}
+
+/// Syncs vendorized dependencies (all of them if `names` is empty)
+///
+/// Naming a dependency that doesn't exist is an error, or with
+/// [`SyncOptions::keep_going`], a failure in the report.
+pub fn sync(
+    repository: &Repository,
+    names: &[String],
+    options: &SyncOptions,
+    progress: &mut dyn ProgressSink,
+) -> Result<SyncReport, anyhow::Error> {
+    let _lock = lock::Lock::acquire(repository)?;
+    let (branch, mut config) = ensure_initialized(repository)?;
+    let original_config = config.clone();
+
+    if !options.refs.is_empty() && (names.len() != 1 || !options.groups.is_empty()) {
+        return Err(anyhow::Error::msg(
+            "syncing specific refs requires naming a single dependency",
+        ));
+    }
+
+    let mut report = SyncReport::default();
+    for name in names {
+        if !config.dependencies.contains_key(name) {
+            let error = Error::DependencyNotFound(name.clone());
+            if !options.keep_going {
+                return Err(error.into());
+            }
+            report.failed.push((name.clone(), error.into()));
+        }
+    }
+    // Disabled dependencies are only synced when named explicitly
+    let everything = names.is_empty() && options.groups.is_empty();
+    let effective_dependencies = config
+        .dependencies
+        .iter_mut()
+        .filter(|(name, dependency)| {
+            let selected = everything
+                || names.contains(name)
+                || dependency.groups.iter().any(|g| options.groups.contains(g));
+            if selected && dependency.frozen {
+                report.frozen.push(name.to_string());
+                return false;
+            }
+            if names.contains(name) {
+                return true;
+            }
+            if selected && dependency.disabled {
+                report.skipped.push(name.to_string());
+                return false;
+            }
+            selected
+        })
+        .collect::<Vec<_>>();
+
+    let now = synced_at(repository)?;
+    // Dependencies are fetched (possibly in parallel) before any of them is updated,
+    // each thread with its own handle of the repository
+    let targets = effective_dependencies
+        .iter()
+        .map(|(name, dependency)| {
+            (
+                name.to_string(),
+                dependency.url.clone(),
+                dependency.mirrors.clone(),
+                dependency.heads.clone(),
+            )
+        })
+        .collect::<Vec<_>>();
+    let path = repository.path();
+    let throttle = transport::HostThrottle::new(options.fetch_delay);
+    // Serializes updates of the in-progress ref by the fetching threads
+    let in_progress = std::sync::Mutex::new(());
+    let fetched = jobs::run(
+        options.jobs,
+        &targets,
+        progress,
+        |result: &Result<_, anyhow::Error>| result.is_err() && !options.keep_going,
+        |(name, url, mirrors, heads), progress| {
+            let expanded_url = url::expand(url, |var| std::env::var(var).ok()).ok();
+            throttle.wait(expanded_url.as_deref().and_then(url::host));
+            let repository = Repository::open(path)?;
+            let fetched = sync_from_mirrors(
+                &repository,
+                url,
+                mirrors,
+                Some(heads),
+                &options.refs,
+                &options.fetch,
+                progress,
+            )?;
+            if fetched.downloaded && !fetched.commits.is_empty() {
+                let _guard = in_progress.lock().unwrap_or_else(|e| e.into_inner());
+                record_in_progress(&repository, name, &fetched.commits)?;
+            }
+            Ok((
+                fetched.heads,
+                fetched
+                    .commits
+                    .iter()
+                    .map(git2::Commit::id)
+                    .collect::<Vec<_>>(),
+                fetched.source,
+            ))
+        },
+    );
+
+    let mut synced = vec![];
+    let mut synced_refs = vec![];
+    let mut failovers = vec![];
+    let mut pruned_head_commits = Vec::new();
+    for ((name, dependency), fetched) in effective_dependencies.into_iter().zip(fetched) {
+        let (heads, commits, source) = match fetched {
+            Some(Ok(fetched)) => fetched,
+            Some(Err(e)) if options.keep_going => {
+                report.failed.push((name.to_string(), e));
+                continue;
+            }
+            Some(Err(e)) => return Err(e.context(format!("failed to sync {name}"))),
+            // Not fetched because an earlier dependency failed
+            None => continue,
+        };
+        let (heads, mut dependency_pruned_head_commits) = if options.refs.is_empty() {
+            let commits = commits
+                .into_iter()
+                .map(|oid| repository.find_commit(oid))
+                .collect::<Result<Vec<_>, _>>()?;
+            (heads, commits)
+        } else {
+            let (heads, updated) = narrow_heads(&dependency.heads, heads, &options.refs)
+                .map_err(|e| e.context(format!("failed to sync {name}")))?;
+            let mut head_commits = vec![];
+            for head in updated.iter().filter_map(|name| heads.get(name)) {
+                let oid = git2::Oid::from_str(head.target())?;
+                if repository.find_commit(oid).is_ok() {
+                    head_commits.push(oid);
+                }
+            }
+            synced_refs = updated;
+            (heads, prune_head_commits(repository, &head_commits)?)
+        };
+        let old_heads = dependency.heads.clone();
+        dependency.heads = heads;
+        // Nothing was fetched, so the dependency isn't any fresher
+        if !options.fetch.offline {
+            dependency.last_synced = Some(now.clone());
+            synced.push(name.to_string());
+        }
+        if let Some(source) = source {
+            if let Some(last_source) = dependency.last_source.as_ref().filter(|s| **s != source) {
+                info!(
+                    name,
+                    source, last_source, "fetched from a different URL than last time"
+                );
+            }
+            if source != dependency.url {
+                failovers.push(format!("{name} was fetched from {source}"));
+            }
+            dependency.last_source = Some(source);
+        }
+        pruned_head_commits.append(&mut dependency_pruned_head_commits);
+        for (head, new) in &dependency.heads {
+            match old_heads.get(head) {
+                Some(old) if old == new => trace!(name, head, commit = new.commit, "unchanged"),
+                Some(old) => info!(name, head, old = old.commit, new = new.commit, "changed"),
+                None => info!(name, head, commit = new.commit, "added"),
+            }
+        }
+        // Parents only come from the heads advertised now, so the tips of deleted refs
+        // are no longer kept reachable by new paravendor commits
+        for head in old_heads.keys() {
+            if !dependency.heads.contains_key(head) {
+                info!(name, head, "removed");
+                report.deleted.push((name.to_string(), head.to_string()));
+            }
+        }
+        if old_heads != dependency.heads {
+            report.changed.push(name.to_string());
+        }
+    }
+
+    report.refs = ConfigDiff::new(&original_config, &config).changed;
+
+    let commit = branch.into_reference().peel_to_commit()?;
+    // Dependencies may share commits (forks, mirrors)
+    let mut seen = BTreeSet::new();
+    pruned_head_commits.retain(|c| seen.insert(c.id()));
+    let pruned_head_commits = unreachable_from(repository, &commit, pruned_head_commits)?;
+    report.objects = objects::count_new(
+        repository,
+        commit.id(),
+        &pruned_head_commits
+            .iter()
+            .map(|c| c.id())
+            .collect::<Vec<_>>(),
+    )?;
+    debug!(objects = ?report.objects, "counted new objects");
+
+    // A new `last_synced` or `last_source` alone is still recorded
+    if original_config != config && !options.dry_run {
+        report.commit = Some(commit_config(
+            repository,
+            &branch_ref(repository)?,
+            commit,
+            &config,
+            &options.message.clone().unwrap_or_else(|| {
+                let refs = if synced_refs.is_empty() {
+                    String::new()
+                } else {
+                    format!(" ({})", synced_refs.join(", "))
+                };
+                let mut message = if report.changed.is_empty() {
+                    format!("Sync: {}{refs} (unchanged)", synced.join(", "))
+                } else {
+                    format!("Sync: {}{refs}", report.changed.join(", "))
+                };
+                // Failing over to mirrors shows in the history, even when nothing changed
+                if !failovers.is_empty() {
+                    message.push_str(&format!("\n\n{}", failovers.join("\n")));
+                }
+                message
+            }),
+            pruned_head_commits,
+            options.sign,
+        )?);
+    }
+
+    // Whatever was fetched is either committed now, or wasn't needed
+    if let Ok(mut reference) = repository.find_reference(IN_PROGRESS_REF) {
+        reference.delete()?;
+    }
+
+    Ok(report)
+}
+
+/// Keeps the commits fetched for a dependency by an unfinished sync reachable from
+/// [`IN_PROGRESS_REF`], with a commit on top of the ones recorded before
+///
+/// Its message lists all dependencies fetched so far, one `Fetched <name>` line each.
+fn record_in_progress(
+    repository: &Repository,
+    name: &str,
+    commits: &[git2::Commit],
+) -> Result<(), anyhow::Error> {
+    let previous = repository
+        .find_reference(IN_PROGRESS_REF)
+        .ok()
+        .and_then(|reference| reference.peel_to_commit().ok());
+    let mut message = previous
+        .as_ref()
+        .and_then(|previous| previous.message().map(str::to_string))
+        .unwrap_or_else(|| "Sync in progress\n\n".to_string());
+    message.push_str(&format!("Fetched {name}\n"));
+    let parents = previous.iter().chain(commits).collect::<Vec<_>>();
+    let tree = repository.find_tree(repository.treebuilder(None)?.write()?)?;
+    let signature = identity::signature(repository, identity::Role::Committer)?;
+    repository.commit(
+        Some(IN_PROGRESS_REF),
+        &signature,
+        &signature,
+        &message,
+        &tree,
+        &parents,
+    )?;
+    debug!(name, "recorded fetched commits as in progress");
+    Ok(())
+}
+
+/// A sync that fetched objects and was interrupted before committing them, see
+/// [`interrupted_sync`]
+#[derive(Debug, PartialEq, Serialize)]
+pub struct InterruptedSync {
+    /// Tip of [`IN_PROGRESS_REF`]
+    pub commit: String,
+    /// When the last dependency was fetched (RFC 3339)
+    pub fetched_at: String,
+    /// Dependencies whose fetched objects were kept, in the order they were fetched
+    pub dependencies: Vec<String>,
+}
+
+/// Returns the sync that was interrupted after fetching some dependencies, if any
+///
+/// The objects it fetched are kept reachable from [`IN_PROGRESS_REF`], so they aren't
+/// garbage collected. The next [`sync`] resumes it: objects that are already here
+/// aren't fetched again, so only refs that moved upstream since then (and the
+/// dependencies that weren't fetched yet) need downloading. The ref is deleted once a
+/// sync finishes, or by [`discard_interrupted_sync`].
+pub fn interrupted_sync(repository: &Repository) -> Result<Option<InterruptedSync>, anyhow::Error> {
+    let Ok(reference) = repository.find_reference(IN_PROGRESS_REF) else {
+        return Ok(None);
+    };
+    let tip = reference.peel_to_commit()?;
+    let dependencies = tip
+        .message()
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| line.strip_prefix("Fetched "))
+        .map(str::to_string)
+        .collect();
+    Ok(Some(InterruptedSync {
+        commit: tip.id().to_string(),
+        fetched_at: timestamp::rfc3339(tip.time().seconds()),
+        dependencies,
+    }))
+}
+
+/// Forgets an interrupted sync, leaving the objects it fetched to be garbage collected,
+/// and returns whether there was one
+pub fn discard_interrupted_sync(repository: &Repository) -> Result<bool, anyhow::Error> {
+    let _lock = lock::Lock::acquire(repository)?;
+    match repository.find_reference(IN_PROGRESS_REF) {
+        Ok(mut reference) => {
+            reference.delete()?;
+            Ok(true)
+        }
+        Err(e) if e.code() == git2::ErrorCode::NotFound => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Whether the ref `name` is `r`, given as a full or short name
+fn matches_ref(name: &str, r: &str) -> bool {
+    name == r || name == format!("refs/heads/{r}") || name == format!("refs/tags/{r}")
+}
+
+/// Takes the heads matching `refs` (full or short names) from `fetched`, keeping the
+/// rest of `old`
+///
+/// Returns the new heads, and the names of the updated ones (including those that
+/// were deleted upstream).
+fn narrow_heads(
+    old: &BTreeMap<String, Head>,
+    mut fetched: BTreeMap<String, Head>,
+    refs: &[String],
+) -> Result<(BTreeMap<String, Head>, Vec<String>), anyhow::Error> {
+    let updated = old
+        .keys()
+        .chain(fetched.keys())
+        .filter(|name| refs.iter().any(|r| matches_ref(name, r)))
+        .cloned()
+        .collect::<BTreeSet<_>>();
+    for r in refs {
+        if !updated.iter().any(|name| matches_ref(name, r)) {
+            return Err(anyhow::Error::msg(format!("ref {r} not found")));
+        }
+    }
+    let mut heads = old.clone();
+    for name in &updated {
+        match fetched.remove(name) {
+            Some(head) => heads.insert(name.clone(), head),
+            None => heads.remove(name),
+        };
+    }
+    Ok((heads, updated.into_iter().collect()))
+}
+
+/// Callbacks for talking to the repository's own remotes, authenticating like git does
+fn remote_callbacks<'a>(config: &'a git2::Config, options: &FetchOptions) -> RemoteCallbacks<'a> {
+    let mut cb = RemoteCallbacks::new();
+    let mut credentials = transport::Credentials::new(config, vec![]);
+    cb.credentials(move |url, username, allowed| credentials.get(url, username, allowed));
+    if options.insecure {
+        cb.certificate_check(|_, _| Ok(CertificateCheckStatus::CertificateOk));
+    }
+    cb
+}
+
+fn proxy_options(proxy_url: Option<&str>) -> ProxyOptions<'_> {
+    let mut proxy = ProxyOptions::new();
+    match proxy_url {
+        Some(proxy_url) => proxy.url(proxy_url),
+        None => proxy.auto(),
+    };
+    proxy
+}
+
+/// Removes a dependency from the config, returning the new paravendor commit
+///
+/// Its objects stay reachable from earlier paravendor commits.
+pub fn remove(repository: &Repository, name: &str) -> Result<git2::Oid, anyhow::Error> {
+    let _lock = lock::Lock::acquire(repository)?;
+    let (branch, mut config) = ensure_initialized(repository)?;
+    if config.dependencies.remove(name).is_none() {
+        return Err(Error::DependencyNotFound(name.to_string()).into());
+    }
+    commit_config(
+        repository,
+        &branch_ref(repository)?,
+        branch.into_reference().peel_to_commit()?,
+        &config,
+        &format!("Remove {name}"),
+        vec![],
+        false,
+    )
+}
+
+/// Brings back a removed dependency as it was in an earlier paravendor commit, returning
+/// the new paravendor commit
+///
+/// Without `revision`, the dependency is taken from the last commit on the first-parent
+/// history that has it. Its head commits become parents of the new commit again if
+/// [`clean`] made them unreachable, but they have to still be in the repository.
+pub fn restore(
+    repository: &Repository,
+    name: &str,
+    revision: Option<&str>,
+) -> Result<git2::Oid, anyhow::Error> {
+    let _lock = lock::Lock::acquire(repository)?;
+    let (branch, mut config) = ensure_initialized(repository)?;
+    if config.dependencies.contains_key(name) {
+        return Err(anyhow::Error::msg(format!("{name} is already there")));
+    }
+    let tip = branch.into_reference().peel_to_commit()?;
+    let (source, dependency) = match revision {
+        Some(revision) => {
+            let commit = repository.revparse_single(revision)?.peel_to_commit()?;
+            let dependency = Config::from_commit(repository, &commit)?
+                .dependencies
+                .remove(name)
+                .ok_or_else(|| anyhow::Error::msg(format!("{name} is not in {revision}")))?;
+            (commit, dependency)
+        }
+        None => {
+            let mut top = tip.parents().next();
+            loop {
+                let commit = top.ok_or_else(|| Error::DependencyNotFound(name.to_string()))?;
+                if let Some(dependency) = Config::from_commit(repository, &commit)?
+                    .dependencies
+                    .remove(name)
+                {
+                    break (commit, dependency);
+                }
+                top = commit.parents().next();
+            }
+        }
+    };
+
+    let odb = repository.odb()?;
+    let mut commits = vec![];
+    for (reference, head) in &dependency.heads {
+        let oid = git2::Oid::from_str(head.target())?;
+        if !odb.exists(oid) {
+            return Err(anyhow::Error::msg(format!(
+                "{reference} of {name} ({oid}) is no longer in the repository, it must have \
+                 been cleaned"
+            )));
+        }
+        if odb.read_header(oid)?.1 == ObjectType::Commit {
+            commits.push(oid);
+        }
+    }
+    let parents = unreachable_from(repository, &tip, prune_head_commits(repository, &commits)?)?;
+    info!(name, from = source.id().to_string(), "restored dependency");
+    config.dependencies.insert(name.to_string(), dependency);
+    commit_config(
+        repository,
+        &branch_ref(repository)?,
+        tip,
+        &config,
+        &format!("Restore {name} from {}", source.id()),
+        parents,
+        false,
+    )
+}
+
+/// Disables or enables syncing of a dependency, returning the new paravendor commit
+/// (if it changed anything)
+pub fn set_disabled(
+    repository: &Repository,
+    name: &str,
+    disabled: bool,
+) -> Result<Option<git2::Oid>, anyhow::Error> {
+    let _lock = lock::Lock::acquire(repository)?;
+    let (branch, mut config) = ensure_initialized(repository)?;
+    let dependency = config
+        .dependencies
+        .get_mut(name)
+        .ok_or_else(|| Error::DependencyNotFound(name.to_string()))?;
+    if dependency.disabled == disabled {
+        return Ok(None);
+    }
+    dependency.disabled = disabled;
+    let message = if disabled {
+        format!("Disable {name}")
+    } else {
+        format!("Enable {name}")
+    };
+    Ok(Some(commit_config(
+        repository,
+        &branch_ref(repository)?,
+        branch.into_reference().peel_to_commit()?,
+        &config,
+        &message,
+        vec![],
+        false,
+    )?))
+}
+
+/// Freezes or unfreezes a dependency, returning the new paravendor commit (if it changed
+/// anything)
+///
+/// Frozen dependencies are never synced, not even when named explicitly, so they stay
+/// vendored as they are.
+pub fn set_frozen(
+    repository: &Repository,
+    name: &str,
+    frozen: bool,
+) -> Result<Option<git2::Oid>, anyhow::Error> {
+    let _lock = lock::Lock::acquire(repository)?;
+    let (branch, mut config) = ensure_initialized(repository)?;
+    let dependency = config
+        .dependencies
+        .get_mut(name)
+        .ok_or_else(|| Error::DependencyNotFound(name.to_string()))?;
+    if dependency.frozen == frozen {
+        return Ok(None);
+    }
+    dependency.frozen = frozen;
+    let message = if frozen {
+        format!("Freeze {name}")
+    } else {
+        format!("Unfreeze {name}")
+    };
+    Ok(Some(commit_config(
+        repository,
+        &branch_ref(repository)?,
+        branch.into_reference().peel_to_commit()?,
+        &config,
+        &message,
+        vec![],
+        false,
+    )?))
+}
+
+/// Points a ref of a dependency to `revision` without syncing, returning the new
+/// paravendor commit (if it changed anything)
+///
+/// The revision has to be in the repository already, and its commit becomes a parent of
+/// the paravendor commit unless it is already reachable. The ref is added if it isn't
+/// recorded yet. Later syncs update it as usual.
+pub fn update_head(
+    repository: &Repository,
+    name: &str,
+    reference: &str,
+    revision: &str,
+) -> Result<Option<git2::Oid>, anyhow::Error> {
+    let _lock = lock::Lock::acquire(repository)?;
+    let (branch, mut config) = ensure_initialized(repository)?;
+    let dependency = config
+        .dependencies
+        .get_mut(name)
+        .ok_or_else(|| Error::DependencyNotFound(name.to_string()))?;
+    if reference != "HEAD" && !reference.starts_with("refs/") {
+        return Err(anyhow::Error::msg(format!(
+            "{reference} is not a full ref name (like refs/heads/{reference})"
+        )));
+    }
+    let object = repository.revparse_single(revision).map_err(|e| {
+        anyhow::Error::new(e).context(format!("{revision} is not in the repository"))
+    })?;
+    // Peeling anything but tags would go from commits to their trees
+    let peeled = match object.kind() {
+        Some(ObjectType::Tag) => Some(object.peel(ObjectType::Any)?),
+        _ => None,
+    };
+    let commit = peeled
+        .as_ref()
+        .unwrap_or(&object)
+        .as_commit()
+        .cloned()
+        .ok_or_else(|| anyhow::Error::msg(format!("{revision} is not a commit")))?;
+    let head = Head {
+        commit: object.id().to_string(),
+        peeled: peeled.map(|peeled| peeled.id().to_string()),
+    };
+    if dependency.heads.get(reference) == Some(&head) {
+        return Ok(None);
+    }
+    let old = dependency.heads.insert(reference.to_string(), head);
+    info!(
+        name,
+        reference,
+        old = old.map(|head| head.commit),
+        new = object.id().to_string(),
+        "updated head"
+    );
+    let tip = branch.into_reference().peel_to_commit()?;
+    let parents = unreachable_from(repository, &tip, vec![commit])?;
+    Ok(Some(commit_config(
+        repository,
+        &branch_ref(repository)?,
+        tip,
+        &config,
+        &format!("Update {reference} of {name} to {}", object.id()),
+        parents,
+        false,
+    )?))
+}
+
+/// Reads a config value by its dotted key, e.g. `dependencies.foo.url`
+pub fn config_get(repository: &Repository, key: &str) -> Result<toml::Value, anyhow::Error> {
+    let (_branch, config) = ensure_initialized(repository)?;
+    let value = toml::Value::try_from(config)?;
+    keys::get(&value, key)
+        .cloned()
+        .ok_or_else(|| anyhow::Error::msg(format!("{key} is not set")))
+}
+
+/// Sets a scalar config value by its dotted key, returning the new paravendor commit
+///
+/// The key has to exist already (or be an optional dependency field), and `value` is
+/// parsed as the type of its current value.
+pub fn config_set(
+    repository: &Repository,
+    key: &str,
+    value: &str,
+) -> Result<git2::Oid, anyhow::Error> {
+    let _lock = lock::Lock::acquire(repository)?;
+    let (branch, original) = ensure_initialized(repository)?;
+    if key == "version" {
+        return Err(anyhow::Error::msg(
+            "version can't be set, use `git paravendor migrate`",
+        ));
+    }
+    let mut table = toml::Value::try_from(&original)?;
+    // Optional fields are omitted when not set, but can still be set
+    if let Some((name, field)) = key
+        .strip_prefix("dependencies.")
+        .and_then(|key| key.rsplit_once('.'))
+    {
+        let unset = match field {
+            "description" => Some(toml::Value::String(String::new())),
+            "disabled" | "frozen" => Some(toml::Value::Boolean(false)),
+            _ => None,
+        };
+        if let (Some(unset), Some(toml::Value::Table(dependency))) = (
+            unset,
+            table
+                .get_mut("dependencies")
+                .and_then(|dependencies| dependencies.get_mut(name)),
+        ) {
+            dependency.entry(field).or_insert(unset);
+        }
+    }
+    keys::set(&mut table, key, value)?;
+    let config: Config = table
+        .try_into()
+        .map_err(|e| anyhow::Error::new(e).context(format!("invalid value for {key}")))?;
+    for (name, dependency) in &config.dependencies {
+        if original.dependency(name)?.url != dependency.url {
+            url::validate(&url::expand(&dependency.url, |var| {
+                std::env::var(var).ok()
+            })?)?;
+        }
+    }
+    commit_config(
+        repository,
+        &branch_ref(repository)?,
+        branch.into_reference().peel_to_commit()?,
+        &config,
+        &format!("Set {key} to {value}"),
+        vec![],
+        false,
+    )
+}
+
+/// Replaces the config with a hand-edited `source`, committing it if it changed
+///
+/// The new config has to be valid, new URLs have to look fetchable, and the objects
+/// of changed heads have to be in the repository already. Returns the new commit, or
+/// `None` if nothing changed.
+pub fn edit_config(
+    repository: &Repository,
+    source: &str,
+    sign: bool,
+) -> Result<Option<git2::Oid>, anyhow::Error> {
+    let _lock = lock::Lock::acquire(repository)?;
+    let (branch, original) = ensure_initialized(repository)?;
+    let config = migrate::migrate(source, false)
+        .map_err(|e| e.context("invalid paravendor config"))?
+        .config;
+    if config == original {
+        return Ok(None);
+    }
+
+    let mut head_commits = vec![];
+    for (name, dependency) in &config.dependencies {
+        let original = original.dependencies.get(name);
+        if original.map(|d| &d.url) != Some(&dependency.url) {
+            url::validate(&url::expand(&dependency.url, |var| {
+                std::env::var(var).ok()
+            })?)?;
+        }
+        for (head_name, head) in &dependency.heads {
+            if original.and_then(|d| d.heads.get(head_name)) == Some(head) {
+                continue;
+            }
+            let oid = git2::Oid::from_str(head.target())?;
+            match repository.find_object(oid, None) {
+                Ok(object) if object.kind() == Some(ObjectType::Commit) => {
+                    head_commits.push(repository.find_commit(oid)?)
+                }
+                Ok(_) => {}
+                Err(_) => {
+                    return Err(anyhow::Error::msg(format!(
+                        "{head_name} of {name} points to {oid}, which is not in the repository"
+                    )))
+                }
+            }
+        }
+    }
+
+    let tip = branch.into_reference().peel_to_commit()?;
+    // Commits the heads were changed to have to stay reachable
+    let parents = unreachable_from(repository, &tip, head_commits)?;
+    Ok(Some(commit_config(
+        repository,
+        &branch_ref(repository)?,
+        tip,
+        &config,
+        "Edit config",
+        parents,
+        sign,
+    )?))
+}
+
+/// Pushes the paravendor branch (and with it, all vendored objects) to `remote`,
+/// returning the number of objects sent
+///
+/// Proxy and certificate settings from `options` apply, retries don't.
+pub fn push(
+    repository: &Repository,
+    remote: &str,
+    options: &FetchOptions,
+) -> Result<usize, anyhow::Error> {
+    ensure_initialized(repository)?;
+    if options.offline {
+        return Err(anyhow::Error::msg(format!(
+            "can't push to {remote} in offline mode"
+        )));
+    }
+    let mut remote = repository.find_remote(remote)?;
+    let remote_name = remote.name().unwrap_or_default().to_string();
+    let url = remote
+        .pushurl()
+        .or_else(|| remote.url())
+        .ok_or_else(|| anyhow::Error::msg(format!("remote {remote_name} has no URL")))?
+        .to_string();
+    let proxy_url = configure_transport(repository, &url, options)?;
+    let config = repository.config()?;
+
+    let objects = std::cell::Cell::new(0);
+    let rejected = std::cell::RefCell::new(None);
+    let mut cb = remote_callbacks(&config, options);
+    cb.push_transfer_progress(|_current, total, _bytes| objects.set(total));
+    cb.push_update_reference(|reference, status| {
+        if let Some(status) = status {
+            *rejected.borrow_mut() = Some(format!("{remote_name} rejected {reference}: {status}"));
+        }
+        Ok(())
+    });
+    let mut push_options = git2::PushOptions::new();
+    push_options
+        .proxy_options(proxy_options(proxy_url.as_deref()))
+        .remote_callbacks(cb);
+
+    info!(remote = remote_name, url, "pushing");
+    match remote.push(
+        &[format!("{}:refs/heads/paravendor", branch_ref(repository)?)],
+        Some(&mut push_options),
+    ) {
+        Ok(()) => {}
+        Err(e) if e.code() == git2::ErrorCode::NotFastForward => {
+            return Err(anyhow::Error::msg(format!(
+                "paravendor branch on {remote_name} has diverged, \
+                 run `git paravendor pull {remote_name}` first"
+            )));
+        }
+        Err(e) => {
+            let context = match transport::hint(&e) {
+                Some(hint) => format!("failed to push to {url}, {hint}"),
+                None => format!("failed to push to {url}"),
+            };
+            return Err(anyhow::Error::new(e).context(context));
+        }
+    }
+    if let Some(rejected) = rejected.take() {
+        return Err(anyhow::Error::msg(rejected));
+    }
+    Ok(objects.get())
+}
+
+/// Fetches the paravendor branch from `remote` and fast-forwards the local one to it,
+/// returning the new tip (if it moved) and the resulting config
+///
+/// A missing local branch is created, a diverged one is an error.
+pub fn pull(
+    repository: &Repository,
+    remote: &str,
+    options: &FetchOptions,
+) -> Result<(Option<git2::Oid>, Config), anyhow::Error> {
+    if options.offline {
+        return Err(anyhow::Error::msg(format!(
+            "can't pull from {remote} in offline mode"
+        )));
+    }
+    let lock = lock::Lock::acquire(repository)?;
+    let mut remote = repository.find_remote(remote)?;
+    let remote_name = remote.name().unwrap_or_default().to_string();
+    let url = remote
+        .url()
+        .ok_or_else(|| anyhow::Error::msg(format!("remote {remote_name} has no URL")))?
+        .to_string();
+    let proxy_url = configure_transport(repository, &url, options)?;
+    let config = repository.config()?;
+
+    let mut fetch_options = git2::FetchOptions::new();
+    fetch_options
+        .download_tags(AutotagOption::None)
+        .proxy_options(proxy_options(proxy_url.as_deref()))
+        .remote_callbacks(remote_callbacks(&config, options));
+    let tracking = format!("refs/remotes/{remote_name}/paravendor");
+    info!(remote = remote_name, url, "pulling");
+    remote
+        .fetch(
+            &[format!("+refs/heads/paravendor:{tracking}")],
+            Some(&mut fetch_options),
+            None,
+        )
+        .map_err(|e| {
+            let context = match transport::hint(&e) {
+                Some(hint) => format!("failed to fetch {url}, {hint}"),
+                None => format!("failed to fetch {url}"),
+            };
+            anyhow::Error::new(e).context(context)
+        })?;
+    let theirs = repository
+        .find_reference(&tracking)
+        .map_err(|_| anyhow::Error::msg(format!("{remote_name} has no paravendor branch")))?
+        .peel_to_commit()?;
+
+    let moved = fast_forward(repository, &theirs, &format!("{remote_name}/paravendor"))?;
+    drop(lock);
+
+    let (_branch, config) = ensure_initialized(repository)?;
+    Ok((moved, config))
+}
+
+/// Fast-forwards the paravendor branch (creating it if necessary) to `theirs`, which
+/// came from `source`
+///
+/// Returns the new tip, or `None` if the branch already contains `theirs`.
+fn fast_forward(
+    repository: &Repository,
+    theirs: &git2::Commit,
+    source: &str,
+) -> Result<Option<git2::Oid>, anyhow::Error> {
+    let name = branch_name(repository)?;
+    match repository.find_branch(&name, BranchType::Local) {
+        Err(e) if e.code() == git2::ErrorCode::NotFound => {
+            repository.branch(&name, theirs, false)?;
+            Ok(Some(theirs.id()))
+        }
+        Err(e) => Err(e.into()),
+        Ok(branch) => {
+            let mut reference = branch.into_reference();
+            let ours = reference.peel_to_commit()?;
+            if ours.id() == theirs.id() || repository.graph_descendant_of(ours.id(), theirs.id())? {
+                Ok(None)
+            } else if repository.graph_descendant_of(theirs.id(), ours.id())? {
+                reference.set_target(theirs.id(), &format!("{name}: fast-forward to {source}"))?;
+                Ok(Some(theirs.id()))
+            } else {
+                Err(anyhow::Error::msg(format!(
+                    "paravendor branch has diverged from {source}, either reset it with \
+                     `git branch -f {name} {source}` and sync again, or merge them manually"
+                )))
+            }
+        }
+    }
+}
+
+/// Writes a git bundle of the paravendor branch (with all objects reachable from it),
+/// returning the branch tip
+///
+/// Uses `git bundle` if `git` is available, and writes the bundle itself otherwise.
+pub fn bundle_create(repository: &Repository, path: &Path) -> Result<git2::Oid, anyhow::Error> {
+    bundle_create_with(repository, path, has_git())
+}
+
+fn bundle_create_with(
+    repository: &Repository,
+    path: &Path,
+    use_git: bool,
+) -> Result<git2::Oid, anyhow::Error> {
+    let (branch, _config) = ensure_initialized(repository)?;
+    let tip = branch.into_reference().peel_to_commit()?.id();
+    // `git bundle` names the branch in the bundle like the local one
+    if use_git && branch_name(repository)? == "paravendor" {
+        git(
+            repository,
+            &[
+                "bundle",
+                "create",
+                "--quiet",
+                &path.to_string_lossy(),
+                "refs/heads/paravendor",
+            ],
+            "",
+        )?;
+    } else {
+        let mut walk = repository.revwalk()?;
+        walk.push(tip)?;
+        let mut builder = repository.packbuilder()?;
+        builder.insert_walk(&mut walk)?;
+        let mut pack = git2::Buf::new();
+        builder.write_buf(&mut pack)?;
+        bundle::write(path, tip, "refs/heads/paravendor", &pack)?;
+    }
+    Ok(tip)
+}
+
+/// Checks that a bundle of the paravendor branch is complete and can be applied to the
+/// repository, returning the branch tip in it
+///
+/// Uses `git bundle verify` if `git` is available.
+pub fn bundle_verify(repository: &Repository, path: &Path) -> Result<git2::Oid, anyhow::Error> {
+    bundle_verify_with(repository, path, has_git())
+}
+
+fn bundle_verify_with(
+    repository: &Repository,
+    path: &Path,
+    use_git: bool,
+) -> Result<git2::Oid, anyhow::Error> {
+    let contents = std::fs::read(path)?;
+    let (header, pack) = bundle::parse(&contents)
+        .map_err(|e| e.context(format!("can't read {}", path.display())))?;
+    let tip = header.find("refs/heads/paravendor").ok_or_else(|| {
+        anyhow::Error::msg(format!("{} has no paravendor branch", path.display()))
+    })?;
+    if use_git {
+        git(
+            repository,
+            &["bundle", "verify", "--quiet", &path.to_string_lossy()],
+            "",
+        )?;
+        return Ok(tip);
+    }
+
+    let odb = repository.odb()?;
+    if let Some(missing) = header.prerequisites.iter().find(|oid| !odb.exists(**oid)) {
+        return Err(anyhow::Error::msg(format!(
+            "bundle requires commit {missing}, which is not in the repository"
+        )));
+    }
+    // Indexing the pack checks it, without adding its objects to the repository yet
+    let scratch_path = repository.path().join("paravendor-bundle");
+    if scratch_path.exists() {
+        std::fs::remove_dir_all(&scratch_path)?;
+    }
+    let result = (|| -> Result<(), anyhow::Error> {
+        let scratch = Repository::init_bare(&scratch_path)?;
+        let scratch_odb = scratch.odb()?;
+        scratch_odb.add_disk_alternate(&objects_dir(repository).to_string_lossy())?;
+        let mut writer = scratch_odb.packwriter()?;
+        std::io::Write::write_all(&mut writer, pack)?;
+        writer.commit()?;
+        if !scratch_odb.exists(tip) {
+            return Err(anyhow::Error::msg(format!(
+                "bundle doesn't contain its paravendor branch {tip}"
+            )));
+        }
+        Ok(())
+    })();
+    std::fs::remove_dir_all(&scratch_path)?;
+    result.map_err(|e| e.context(format!("{} is damaged", path.display())))?;
+    Ok(tip)
+}
+
+/// Adds the objects of a bundle (see [`bundle_create`]) to the repository and
+/// fast-forwards (or creates) the paravendor branch to its tip
+///
+/// The bundle is verified first (see [`bundle_verify`]). Returns the new tip, or `None`
+/// if the branch already contained it.
+pub fn bundle_apply(
+    repository: &Repository,
+    path: &Path,
+) -> Result<Option<git2::Oid>, anyhow::Error> {
+    bundle_apply_with(repository, path, has_git())
+}
+
+fn bundle_apply_with(
+    repository: &Repository,
+    path: &Path,
+    use_git: bool,
+) -> Result<Option<git2::Oid>, anyhow::Error> {
+    let tip = bundle_verify_with(repository, path, use_git)?;
+    let lock = lock::Lock::acquire(repository)?;
+    if use_git {
+        git(
+            repository,
+            &["bundle", "unbundle", &path.to_string_lossy()],
+            "",
+        )?;
+    } else {
+        let contents = std::fs::read(path)?;
+        let (_header, pack) = bundle::parse(&contents)?;
+        let odb = repository.odb()?;
+        let mut writer = odb.packwriter()?;
+        std::io::Write::write_all(&mut writer, pack)?;
+        writer.commit()?;
+    }
+    let moved = fast_forward(repository, &repository.find_commit(tip)?, &tip.to_string())?;
+    drop(lock);
+    ensure_initialized(repository)?;
+    Ok(moved)
+}
+
+/// Pack written by [`export_pack`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ExportedPack {
+    /// Tip of the paravendor branch, to point the branch to after [`import_pack`]
+    pub tip: git2::Oid,
+    pub objects: usize,
+}
+
+/// Writes all objects reachable from the paravendor branch (including the full history
+/// of every dependency) to a packfile at `path`
+pub fn export_pack(repository: &Repository, path: &Path) -> Result<ExportedPack, anyhow::Error> {
+    let (branch, _config) = ensure_initialized(repository)?;
+    let tip = branch.into_reference().peel_to_commit()?.id();
+    let mut walk = repository.revwalk()?;
+    walk.push(tip)?;
+    let mut builder = repository.packbuilder()?;
+    builder.insert_walk(&mut walk)?;
+
+    let mut file = std::fs::File::create(path)?;
+    let mut result = Ok(());
+    builder.foreach(|chunk| {
+        result = std::io::Write::write_all(&mut file, chunk);
+        result.is_ok()
+    })?;
+    result?;
+    Ok(ExportedPack {
+        tip,
+        objects: builder.object_count(),
+    })
+}
+
+/// Adds the objects of a packfile (such as one written by [`export_pack`]) to the
+/// repository, returning the number of objects in it
+///
+/// With `tip`, the paravendor branch is then fast-forwarded (or created) to it.
+pub fn import_pack(
+    repository: &Repository,
+    path: &Path,
+    tip: Option<&str>,
+) -> Result<(usize, Option<git2::Oid>), anyhow::Error> {
+    let pack = std::fs::read(path)?;
+    if pack.len() < 12 || &pack[..4] != b"PACK" {
+        return Err(anyhow::Error::msg(format!(
+            "{} is not a packfile",
+            path.display()
+        )));
+    }
+    let objects = u32::from_be_bytes(pack[8..12].try_into()?) as usize;
+
+    let lock = lock::Lock::acquire(repository)?;
+    let odb = repository.odb()?;
+    let mut writer = odb.packwriter()?;
+    std::io::Write::write_all(&mut writer, &pack)?;
+    writer.commit()?;
+
+    let moved = match tip {
+        Some(tip) => {
+            let theirs = repository.revparse_single(tip)?.peel_to_commit()?;
+            let moved = fast_forward(repository, &theirs, tip)?;
+            drop(lock);
+            ensure_initialized(repository)?;
+            moved
+        }
+        None => None,
+    };
+    Ok((objects, moved))
+}
+
+/// Lists vendorized dependencies
+pub fn list(repository: &Repository) -> Result<BTreeMap<String, Dependency>, anyhow::Error> {
+    let (_branch, config) = ensure_initialized(repository)?;
+    Ok(config.dependencies)
+}
+
+/// Lists all refs of a vendorized dependency
+pub fn show_refs(repository: &Repository, name: &str) -> Result<Vec<String>, anyhow::Error> {
+    let (_branch, config) = ensure_initialized(repository)?;
+    Ok(config.dependency(name)?.heads.keys().cloned().collect())
+}
+
+/// Resolves a ref (or an abbreviated commit id) in a vendorized dependency
+///
+/// See [`Dependency::resolve`] for the meaning of `peel`.
+pub fn show_ref(
+    repository: &Repository,
+    name: &str,
+    reference: &str,
+    peel: bool,
+) -> Result<String, anyhow::Error> {
+    let (_branch, config) = ensure_initialized(repository)?;
+    Ok(config
+        .dependency(name)?
+        .resolve(reference, peel)?
+        .to_string())
+}
+
+/// Returns the refs of a dependency whose history includes `commit` (empty if none do)
+///
+/// `commit` can be anything `git rev-parse` understands, such as an abbreviated id.
+pub fn contains(
+    repository: &Repository,
+    name: &str,
+    commit: &str,
+) -> Result<Vec<String>, anyhow::Error> {
+    let (_branch, config) = ensure_initialized(repository)?;
+    let dependency = config.dependency(name)?;
+    let commit = find_commit(repository, commit)?;
+    heads_containing(repository, dependency, commit)
+}
+
+/// Finds the dependencies whose history includes `commit`, with their refs containing it
+pub fn which(
+    repository: &Repository,
+    commit: &str,
+) -> Result<BTreeMap<String, Vec<String>>, anyhow::Error> {
+    let (_branch, config) = ensure_initialized(repository)?;
+    let commit = find_commit(repository, commit)?;
+    let mut dependencies = BTreeMap::new();
+    for (name, dependency) in &config.dependencies {
+        let heads = heads_containing(repository, dependency, commit)?;
+        if !heads.is_empty() {
+            dependencies.insert(name.clone(), heads);
+        }
+    }
+    Ok(dependencies)
+}
+
+/// How much the histories of two dependencies overlap, as found by [`compare`]
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Comparison {
+    /// Commits reachable only from the first dependency's heads
+    pub only_first: usize,
+    /// Commits reachable only from the second dependency's heads
+    pub only_second: usize,
+    /// Commits reachable from both
+    pub shared: usize,
+    /// Newest shared commits, which none of the other shared commits descend from
+    pub merge_bases: Vec<String>,
+}
+
+/// Compares the commits reachable from the heads of two dependencies
+pub fn compare(
+    repository: &Repository,
+    first: &str,
+    second: &str,
+) -> Result<Comparison, anyhow::Error> {
+    let (_branch, config) = ensure_initialized(repository)?;
+    let odb = repository.odb()?;
+    let reachable = |name: &str| -> Result<HashSet<git2::Oid>, anyhow::Error> {
+        let mut walk = repository.revwalk()?;
+        for head in config.dependency(name)?.heads.values() {
+            let target = git2::Oid::from_str(head.target())?;
+            if odb.read_header(target)?.1 == ObjectType::Commit {
+                walk.push(target)?;
+            }
+        }
+        Ok(walk.collect::<Result<_, _>>()?)
+    };
+    let (first, second) = (reachable(first)?, reachable(second)?);
+    let shared = first.intersection(&second).copied().collect::<HashSet<_>>();
+
+    // History is closed under ancestry, so shared commits that aren't parents of other
+    // shared commits are the newest ones
+    let mut parents = HashSet::new();
+    for id in &shared {
+        parents.extend(repository.find_commit(*id)?.parent_ids());
+    }
+    let mut merge_bases = shared
+        .difference(&parents)
+        .map(|id| id.to_string())
+        .collect::<Vec<_>>();
+    merge_bases.sort();
+    Ok(Comparison {
+        only_first: first.len() - shared.len(),
+        only_second: second.len() - shared.len(),
+        shared: shared.len(),
+        merge_bases,
+    })
+}
+
+/// Resolves `commit` to a commit present in the repository
+fn find_commit(repository: &Repository, commit: &str) -> Result<git2::Oid, anyhow::Error> {
+    let object = repository.revparse_single(commit).map_err(|e| {
+        if e.code() == git2::ErrorCode::NotFound {
+            anyhow::Error::msg(format!("commit {commit} is not present in the repository"))
+        } else {
+            e.into()
+        }
+    })?;
+    Ok(object.peel_to_commit()?.id())
+}
+
+/// Names of the dependency's heads that have `commit` in their history
+fn heads_containing(
+    repository: &Repository,
+    dependency: &Dependency,
+    commit: git2::Oid,
+) -> Result<Vec<String>, anyhow::Error> {
+    let odb = repository.odb()?;
+    let mut heads = vec![];
+    for (name, head) in &dependency.heads {
+        let target = git2::Oid::from_str(head.target())?;
+        if odb.read_header(target)?.1 == ObjectType::Commit
+            && (target == commit || repository.graph_descendant_of(target, commit)?)
+        {
+            heads.push(name.clone());
+        }
+    }
+    Ok(heads)
+}
+
+/// Upgrades the config to the current format version, including major upgrades
+///
+/// Returns the version it was upgraded from, if an upgrade was necessary.
+pub fn migrate(repository: &Repository) -> Result<Option<String>, anyhow::Error> {
+    let _lock = lock::Lock::acquire(repository)?;
+    let name = branch_name(repository)?;
+    let branch = repository
+        .find_branch(&name, BranchType::Local)
+        .map_err(|_| Error::NotInitialized)?;
+    let obj = repository.revparse_single(&format!("refs/heads/{name}:config"))?;
+    let blob = obj
+        .as_blob()
+        .ok_or_else(|| anyhow::Error::msg("paravendor config not found"))?;
+    let migrated = migrate::migrate(std::str::from_utf8(blob.content())?, true)?;
+    if let Some(ref from) = migrated.from {
+        let tip = branch.into_reference().peel_to_commit()?;
+        commit_config(
+            repository,
+            &branch_ref(repository)?,
+            tip,
+            &migrated.config,
+            &format!("Migrate config from {from} to {}", migrated.config.version),
+            vec![],
+            false,
+        )?;
+    }
+    Ok(migrated.from)
+}
+
+/// Counts the objects of a dependency (or all of them) and the size of their blobs
+///
+/// History shared with other dependencies (e.g. forks) isn't attributed to either
+/// of them, but is included in the total.
+pub fn size(repository: &Repository, name: Option<&str>) -> Result<SizeReport, anyhow::Error> {
+    let (_branch, config) = ensure_initialized(repository)?;
+    if let Some(name) = name {
+        config.dependency(name)?;
+    }
+    let head_commits = head_commits(repository, &config)?;
+
+    let mut report = SizeReport::default();
+    let selected = |dependency_name: &str| name.is_none_or(|name| name == dependency_name);
+    for (dependency_name, commits) in &head_commits {
+        if !selected(dependency_name) {
+            continue;
+        }
+        let others = head_commits
+            .iter()
+            .filter(|(other, _)| *other != dependency_name)
+            .flat_map(|(_, commits)| commits.iter().copied())
+            .collect::<Vec<_>>();
+        let mut blob_bytes = Some(0);
+        let objects = objects::count(repository, &others, commits, &mut blob_bytes)?;
+        report.dependencies.insert(
+            dependency_name.to_string(),
+            Footprint {
+                objects,
+                blob_bytes: blob_bytes.unwrap_or_default(),
+            },
+        );
+    }
+    let all = head_commits
+        .iter()
+        .filter(|(dependency_name, _)| selected(dependency_name))
+        .flat_map(|(_, commits)| commits.iter().copied())
+        .collect::<Vec<_>>();
+    let mut blob_bytes = Some(0);
+    report.total = Footprint {
+        objects: objects::count(repository, &[], &all, &mut blob_bytes)?,
+        blob_bytes: blob_bytes.unwrap_or_default(),
+    };
+    Ok(report)
+}
+
+/// Compares the objects of all dependencies, each counted once, with their sum
+///
+/// The difference is what sharing history (between forks, for example) saves.
+pub fn stats(repository: &Repository) -> Result<StatsReport, anyhow::Error> {
+    let (_branch, config) = ensure_initialized(repository)?;
+    let head_commits = head_commits(repository, &config)?;
+    let footprint = |commits: &[git2::Oid]| -> Result<Footprint, anyhow::Error> {
+        let mut blob_bytes = Some(0);
+        Ok(Footprint {
+            objects: objects::count(repository, &[], commits, &mut blob_bytes)?,
+            blob_bytes: blob_bytes.unwrap_or_default(),
+        })
+    };
+    let mut report = StatsReport::default();
+    for (name, commits) in &head_commits {
+        let dependency = footprint(commits)?;
+        report.sum.objects.commits += dependency.objects.commits;
+        report.sum.objects.trees += dependency.objects.trees;
+        report.sum.objects.blobs += dependency.objects.blobs;
+        report.sum.blob_bytes += dependency.blob_bytes;
+        report.dependencies.insert(name.to_string(), dependency);
+    }
+    report.unique = footprint(&head_commits.into_values().flatten().collect::<Vec<_>>())?;
+    Ok(report)
+}
+
+/// Counts the objects of a dependency, telling those no other dependency has apart from
+/// the shared ones
+///
+/// Unique objects are what dropping the dependency (and running [`clean`]) would free.
+pub fn objects(repository: &Repository, name: &str) -> Result<ObjectsReport, anyhow::Error> {
+    let (_branch, config) = ensure_initialized(repository)?;
+    config.dependency(name)?;
+    let head_commits = head_commits(repository, &config)?;
+    let others = head_commits
+        .iter()
+        .filter(|(other, _)| **other != name)
+        .flat_map(|(_, commits)| commits.iter().copied())
+        .collect::<Vec<_>>();
+    let commits = &head_commits[name];
+    let footprint = |hidden: &[git2::Oid]| -> Result<Footprint, anyhow::Error> {
+        let mut blob_bytes = Some(0);
+        Ok(Footprint {
+            objects: objects::count(repository, hidden, commits, &mut blob_bytes)?,
+            blob_bytes: blob_bytes.unwrap_or_default(),
+        })
+    };
+    let total = footprint(&[])?;
+    let unique = footprint(&others)?;
+    Ok(ObjectsReport {
+        total,
+        unique,
+        shared: Footprint {
+            objects: ObjectCounts {
+                commits: total.objects.commits - unique.objects.commits,
+                trees: total.objects.trees - unique.objects.trees,
+                blobs: total.objects.blobs - unique.objects.blobs,
+            },
+            blob_bytes: total.blob_bytes - unique.blob_bytes,
+        },
+    })
+}
+
+/// Outcome of [`repack`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct RepackReport {
+    /// Size of the object store (loose objects and packs) before repacking, in bytes
+    pub before: u64,
+    /// Size of the object store after repacking, in bytes
+    pub after: u64,
+}
+
+/// Packs the objects of the repository with `git`
+///
+/// Like `git repack -a -d`, all objects end up in a single pack. With `paravendor_only`,
+/// only the loose objects reachable from the paravendor branch (and its notes) are
+/// packed (and removed), leaving everything else as it was.
+pub fn repack(
+    repository: &Repository,
+    paravendor_only: bool,
+) -> Result<RepackReport, anyhow::Error> {
+    ensure_initialized(repository)?;
+    let objects = objects_dir(repository);
+    let before = directory_size(&objects)?;
+    if paravendor_only {
+        let mut revisions = format!("{}\n", branch_ref(repository)?);
+        if repository.find_reference(NOTES_REF).is_ok() {
+            revisions.push_str(&format!("{NOTES_REF}\n"));
+        }
+        let pack = objects.join("pack").join("pack");
+        git(
+            repository,
+            &[
+                "pack-objects",
+                "--revs",
+                "--unpacked",
+                "-q",
+                &pack.to_string_lossy(),
+            ],
+            &revisions,
+        )?;
+        git(repository, &["prune-packed", "-q"], "")?;
+    } else {
+        git(repository, &["repack", "-a", "-d", "-q"], "")?;
+    }
+    Ok(RepackReport {
+        before,
+        after: directory_size(&objects)?,
+    })
+}
+
+/// Options for [`prune`]
+#[derive(Clone, Debug, Default)]
+pub struct PruneOptions {
+    /// Number of most recent commits on the first-parent paravendor history to keep
+    ///
+    /// Everything older is squashed into a single base commit. At least one has to be
+    /// kept.
+    pub keep: Option<usize>,
+    /// Age of the commits to squash, by committer date
+    ///
+    /// With [`PruneOptions::keep`], commits are only squashed if both would squash them.
+    /// The tip is always kept. If neither is set, everything is kept.
+    pub older_than: Option<Duration>,
+    /// Only report what would be pruned
+    pub dry_run: bool,
+}
+
+/// Outcome of [`prune`]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PruneReport {
+    /// Commits that were kept (rewritten on top of the base commit)
+    pub kept: usize,
+    /// Commits that were squashed into the base commit
+    pub squashed: usize,
+    /// New tip of the paravendor branch, if it was rewritten (never set with
+    /// [`PruneOptions::dry_run`])
+    pub commit: Option<git2::Oid>,
+}
+
+/// Compacts the first-parent history of the paravendor branch, squashing older commits
+/// into a single base commit
+///
+/// The base commit is a root commit with the config of the last squashed commit. Kept
+/// commits are recreated on top of it with their own trees, messages, authors and extra
+/// parents (and notes); signatures are only recreated if `commit.gpgSign` is set. The
+/// oldest of them also gets the head commits of the base config and of every kept
+/// config as parents, so everything the kept configs refer to stays reachable. Like
+/// other commits, the branch is only updated if it hasn't moved in the meantime.
+///
+/// The branch is rewritten, so pushing it afterwards requires a force push, and clones
+/// will have to reset their paravendor branch. The squashed objects stay in the object
+/// store until the reflog expires and `git gc` removes them.
+pub fn prune(
+    repository: &Repository,
+    options: &PruneOptions,
+) -> Result<PruneReport, anyhow::Error> {
+    let _lock = lock::Lock::acquire(repository)?;
+    let (branch, _config) = ensure_initialized(repository)?;
+    if staged(repository)?.is_some() {
+        return Err(anyhow::Error::msg(
+            "can't prune with a staged dependency, commit it with `commit-staged` first",
+        ));
+    }
+    if options.keep == Some(0) {
+        return Err(anyhow::Error::msg("at least one commit has to be kept"));
+    }
+    let tip = branch.into_reference().peel_to_commit()?;
+    let mut history = vec![tip.clone()];
+    while let Some(parent) = history.last().unwrap().parents().next() {
+        history.push(parent);
+    }
+    let recent = options.older_than.map(|age| {
+        let cutoff = std::time::SystemTime::now()
+            .checked_sub(age)
+            .and_then(|cutoff| cutoff.duration_since(std::time::UNIX_EPOCH).ok())
+            .map_or(i64::MIN, |cutoff| cutoff.as_secs() as i64);
+        history
+            .iter()
+            .take_while(|commit| commit.committer().when().seconds() >= cutoff)
+            .count()
+            .max(1)
+    });
+    let keep = match (options.keep, recent) {
+        (None, None) => history.len(),
+        (keep, recent) => keep.unwrap_or(0).max(recent.unwrap_or(0)),
+    }
+    .min(history.len());
+    // Squashing a single commit would leave it as it is
+    if history.len() - keep < 2 {
+        return Ok(PruneReport {
+            kept: history.len(),
+            ..Default::default()
+        });
+    }
+    let mut report = PruneReport {
+        kept: keep,
+        squashed: history.len() - keep,
+        commit: None,
+    };
+    if options.dry_run {
+        return Ok(report);
+    }
+
+    let kept = history.drain(..keep).rev().collect::<Vec<_>>();
+    let last_squashed = &history[0];
+    let mut heads = BTreeSet::new();
+    for commit in std::iter::once(last_squashed).chain(&kept) {
+        let config = Config::from_commit(repository, commit)?;
+        heads.extend(head_commits(repository, &config)?.into_values().flatten());
+    }
+    let heads = prune_head_commits(repository, &heads.into_iter().collect::<Vec<_>>())?;
+
+    let checksum = sha256(config_blob(repository, last_squashed)?.content());
+    let message = format!(
+        "Squash {} commits up to {}\n\n{CONFIG_SHA256_TRAILER}: {checksum}",
+        history.len(),
+        last_squashed.id()
+    );
+    let author = identity::signature(repository, identity::Role::Author)?;
+    let committer = identity::signature(repository, identity::Role::Committer)?;
+    let mut top = repository.find_commit(write_commit(
+        repository,
+        &author,
+        &committer,
+        &message,
+        &last_squashed.tree()?,
+        &[],
+        false,
+    )?)?;
+    let mut heads = Some(heads);
+    let mut notes = vec![];
+    for commit in &kept {
+        let mut parents = vec![top];
+        parents.extend(commit.parents().skip(1));
+        for head in heads.take().into_iter().flatten() {
+            if parents.iter().all(|parent| parent.id() != head.id()) {
+                parents.push(head);
+            }
+        }
+        top = rewrite_commit(repository, commit, &parents, &mut notes)?;
+    }
+    update_reference(
+        repository,
+        &branch_ref(repository)?,
+        top.id(),
+        Some(tip.id()),
+        &format!("prune: squash {} commits", history.len()),
+    )?;
+    copy_notes(repository, notes)?;
+    info!(squashed = history.len(), kept = keep, "pruned history");
+    report.commit = Some(top.id());
+    Ok(report)
+}
+
+/// Outcome of [`clean`]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CleanReport {
+    /// Removed dependencies whose objects were dropped
+    pub dependencies: Vec<String>,
+    /// Head commits that are no longer reachable from the paravendor branch
+    pub commits: Vec<git2::Oid>,
+    /// Objects that are no longer reachable from the paravendor branch
+    pub objects: ObjectCounts,
+    /// Total size of the blobs among `objects`, in bytes
+    pub blob_bytes: u64,
+    /// New tip of the paravendor branch, if it was rewritten (never set with `dry_run`)
+    pub commit: Option<git2::Oid>,
+    /// Outcome of repacking afterwards (only if `git` is available)
+    pub repack: Option<RepackReport>,
+}
+
+/// Drops the objects of removed dependencies from the paravendor branch
+///
+/// Head commits of dependencies that are no longer in the config are removed from the
+/// parents of the paravendor commits that brought them in, unless a dependency that
+/// is still there refers to them (or to their descendants) in any of its configs.
+/// Heads that were only reachable through them are added back as parents, so every
+/// config on the branch keeps its objects. The first-parent history is rewritten from
+/// the first affected commit on (like [`prune`], so pushing it requires a force push),
+/// and the repository is repacked if `git` is available.
+///
+/// Until the reflog of the branch expires, the dropped objects are still kept by it.
+pub fn clean(repository: &Repository, dry_run: bool) -> Result<CleanReport, anyhow::Error> {
+    let _lock = lock::Lock::acquire(repository)?;
+    let (branch, config) = ensure_initialized(repository)?;
+    if staged(repository)?.is_some() {
+        return Err(anyhow::Error::msg(
+            "can't clean with a staged dependency, commit it with `commit-staged` first",
+        ));
+    }
+    let tip = branch.into_reference().peel_to_commit()?;
+    let mut history = vec![tip.clone()];
+    while let Some(parent) = history.last().unwrap().parents().next() {
+        history.push(parent);
+    }
+    history.reverse();
+
+    // Heads of every config, and those of removed dependencies
+    let mut configs = vec![];
+    let mut present = BTreeSet::new();
+    let mut removed = BTreeMap::new();
+    for commit in &history {
+        let commit_config = Config::from_commit(repository, commit)?;
+        let mut heads = BTreeSet::new();
+        for (name, commits) in head_commits(repository, &commit_config)? {
+            if config.dependencies.contains_key(name) {
+                present.extend(commits.iter().copied());
+            } else {
+                for commit in &commits {
+                    removed
+                        .entry(*commit)
+                        .or_insert_with(BTreeSet::new)
+                        .insert(name.to_string());
+                }
+            }
+            heads.extend(commits);
+        }
+        configs.push(heads);
+    }
+    let mut dropped = BTreeMap::new();
+    for (commit, names) in removed {
+        let mut referenced = present.contains(&commit);
+        for head in &present {
+            if referenced {
+                break;
+            }
+            referenced = repository.graph_descendant_of(*head, commit)?;
+        }
+        if !referenced {
+            dropped.insert(commit, names);
+        }
+    }
+
+    // Parents of every commit on the rewritten history, from the first affected one on
+    let mut kept = vec![];
+    let mut rewritten = vec![];
+    let mut dropped_parents = BTreeMap::new();
+    for (commit, heads) in history.iter().zip(configs) {
+        let mut parents = vec![];
+        for parent in commit.parent_ids().skip(1) {
+            match dropped.get(&parent) {
+                Some(names) => {
+                    dropped_parents.insert(parent, names.clone());
+                }
+                None => parents.push(parent),
+            }
+        }
+        if !rewritten.is_empty() || parents.len() + 1 < commit.parent_count() {
+            for head in heads.into_iter().filter(|head| !dropped.contains_key(head)) {
+                if !parents.contains(&head) && !reachable(repository, &kept, &parents, head)? {
+                    parents.push(head);
+                }
+            }
+            rewritten.push((commit, parents.clone()));
+        }
+        kept.extend(parents);
+    }
+
+    let mut report = CleanReport {
+        dependencies: dropped_parents
+            .values()
+            .flatten()
+            .cloned()
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect(),
+        commits: dropped_parents.keys().copied().collect(),
+        ..Default::default()
+    };
+    if rewritten.is_empty() {
+        return Ok(report);
+    }
+    let mut blob_bytes = Some(0);
+    report.objects = objects::count(repository, &kept, &report.commits, &mut blob_bytes)?;
+    report.blob_bytes = blob_bytes.unwrap_or_default();
+    if dry_run {
+        return Ok(report);
+    }
+
+    let mut notes = vec![];
+    let mut top = rewritten[0].0.parent(0).ok();
+    for (commit, parents) in &rewritten {
+        let parents = top
+            .into_iter()
+            .map(Ok)
+            .chain(parents.iter().map(|oid| repository.find_commit(*oid)))
+            .collect::<Result<Vec<_>, _>>()?;
+        top = Some(rewrite_commit(repository, commit, &parents, &mut notes)?);
+    }
+    let top = top.unwrap();
+    update_reference(
+        repository,
+        &branch_ref(repository)?,
+        top.id(),
+        Some(tip.id()),
+        &format!("clean: drop {}", report.dependencies.join(", ")),
+    )?;
+    copy_notes(repository, notes)?;
+    info!(
+        dependencies = report.dependencies.join(", "),
+        commits = report.commits.len(),
+        "cleaned history"
+    );
+    report.commit = Some(top.id());
+    if has_git() {
+        report.repack = Some(repack(repository, false)?);
+    }
+    Ok(report)
+}
+
+/// Whether `head` is one of `commits` or `parents`, or reachable from any of them
+fn reachable(
+    repository: &Repository,
+    commits: &[git2::Oid],
+    parents: &[git2::Oid],
+    head: git2::Oid,
+) -> Result<bool, anyhow::Error> {
+    for commit in commits.iter().chain(parents) {
+        if *commit == head || repository.graph_descendant_of(*commit, head)? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Recreates `commit` with other `parents`, keeping its tree, message, author and
+/// committer, and remembering its note (if any) in `notes` for [`copy_notes`]
+fn rewrite_commit<'a>(
+    repository: &'a Repository,
+    commit: &git2::Commit,
+    parents: &[git2::Commit],
+    notes: &mut Vec<(git2::Oid, String)>,
+) -> Result<git2::Commit<'a>, anyhow::Error> {
+    let message = String::from_utf8_lossy(commit.message_raw_bytes()).into_owned();
+    let oid = write_commit(
+        repository,
+        &commit.author(),
+        &commit.committer(),
+        &message,
+        &commit.tree()?,
+        &parents.iter().collect::<Vec<_>>(),
+        false,
+    )?;
+    if let Ok(note) = repository.find_note(Some(NOTES_REF), commit.id()) {
+        notes.extend(note.message().map(|content| (oid, content.to_string())));
+    }
+    Ok(repository.find_commit(oid)?)
+}
+
+/// Attaches notes remembered by [`rewrite_commit`] to the rewritten commits
+fn copy_notes(
+    repository: &Repository,
+    notes: Vec<(git2::Oid, String)>,
+) -> Result<(), anyhow::Error> {
+    let author = identity::signature(repository, identity::Role::Author)?;
+    let committer = identity::signature(repository, identity::Role::Committer)?;
+    for (oid, content) in notes {
+        repository.note(&author, &committer, Some(NOTES_REF), oid, &content, false)?;
+    }
+    Ok(())
+}
+
+/// Makes the repository use the objects in another object store (`objects` directory),
+/// returning whether it wasn't already using it
+///
+/// The store is recorded in `objects/info/alternates`, as with `git clone --reference`,
+/// so git uses it, too. Objects that are in there aren't fetched again by [`add`] and
+/// [`sync`], but newly fetched objects are still stored in the repository itself. The
+/// store has to stay available (at the same path) for as long as the repository uses it.
+pub fn add_alternate(repository: &Repository, store: &Path) -> Result<bool, anyhow::Error> {
+    let store = store
+        .canonicalize()
+        .map_err(|e| anyhow::Error::new(e).context(format!("{} not found", store.display())))?;
+    // Loose objects are in fan-out directories, and packs in `pack`
+    if !store.join("pack").is_dir() {
+        return Err(anyhow::Error::msg(format!(
+            "{} is not an object store (the objects directory of a repository)",
+            store.display()
+        )));
+    }
+    let store = store
+        .to_str()
+        .ok_or_else(|| anyhow::Error::msg("object store path is not valid UTF-8"))?;
+    let info = objects_dir(repository).join("info");
+    let alternates = info.join("alternates");
+    let mut existing = match std::fs::read_to_string(&alternates) {
+        Ok(existing) => existing,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e.into()),
+    };
+    if existing.lines().any(|line| line == store) {
+        return Ok(false);
+    }
+    if !existing.is_empty() && !existing.ends_with('\n') {
+        existing.push('\n');
+    }
+    existing.push_str(store);
+    existing.push('\n');
+    std::fs::create_dir_all(&info)?;
+    std::fs::write(&alternates, existing)?;
+    // The file is only read when the object database is opened
+    repository.odb()?.add_disk_alternate(store)?;
+    info!(store, "added alternate object store");
+    Ok(true)
+}
+
+/// Object store of the repository, which worktrees share with the main repository
+fn objects_dir(repository: &Repository) -> PathBuf {
+    let common = match std::fs::read_to_string(repository.path().join("commondir")) {
+        Ok(common) => repository.path().join(common.trim()),
+        Err(_) => repository.path().to_path_buf(),
+    };
+    common.join("objects")
+}
+
+fn has_git() -> bool {
+    which::which("git").is_ok()
+}
+
+/// Runs `git` on the repository, with `input` on its stdin
+fn git(repository: &Repository, args: &[&str], input: &str) -> Result<(), anyhow::Error> {
+    let mut child = std::process::Command::new("git")
+        .arg("--git-dir")
+        .arg(repository.path())
+        .args(args)
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .map_err(|e| anyhow::Error::new(e).context("can't run git"))?;
+    std::io::Write::write_all(&mut child.stdin.take().unwrap(), input.as_bytes())?;
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(anyhow::Error::msg(format!(
+            "git {} failed: {}",
+            args[0],
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+/// Total size of the files in `path` and its subdirectories
+fn directory_size(path: &Path) -> Result<u64, anyhow::Error> {
+    let mut size = 0;
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        size += if metadata.is_dir() {
+            directory_size(&entry.path())?
+        } else {
+            metadata.len()
+        };
+    }
+    Ok(size)
+}
+
+/// Commits the heads of each dependency point to, leaving out heads pointing to other
+/// objects
+fn head_commits<'a>(
+    repository: &Repository,
+    config: &'a Config,
+) -> Result<BTreeMap<&'a str, Vec<git2::Oid>>, anyhow::Error> {
+    let odb = repository.odb()?;
+    let mut head_commits = BTreeMap::new();
+    for (name, dependency) in &config.dependencies {
+        let mut commits = vec![];
+        for head in dependency.heads.values() {
+            let oid = git2::Oid::from_str(head.target())?;
+            if odb.read_header(oid)?.1 == ObjectType::Commit {
+                commits.push(oid);
+            }
+        }
+        head_commits.insert(name.as_str(), commits);
+    }
+    Ok(head_commits)
+}
+
+/// Compares the configs stored in two paravendor revisions
+pub fn diff(repository: &Repository, old: &str, new: &str) -> Result<ConfigDiff, anyhow::Error> {
+    Ok(ConfigDiff::new(
+        &config_at(repository, old)?,
+        &config_at(repository, new)?,
+    ))
+}
+
+/// Returns the config as of a paravendor `revision` (such as `paravendor~2`)
+pub fn config_at(repository: &Repository, revision: &str) -> Result<Config, anyhow::Error> {
+    ensure_initialized(repository)?;
+    let commit = repository.revparse_single(revision)?.peel_to_commit()?;
+    Config::from_commit(repository, &commit)
+}
+
+/// A value of a dependency ref, as found by [`ref_history`]
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RefHistoryEntry {
+    /// Object id the ref pointed to, `None` once it (or the dependency) was removed
+    pub target: Option<String>,
+    /// Paravendor commit that recorded it
+    pub commit: String,
+    /// Commit time of the paravendor commit (RFC 3339)
+    pub date: String,
+}
+
+/// Returns the values a dependency ref (full or short name) had on the first-parent
+/// paravendor history, oldest first, each with the commit that recorded it
+pub fn ref_history(
+    repository: &Repository,
+    name: &str,
+    reference: &str,
+) -> Result<Vec<RefHistoryEntry>, anyhow::Error> {
+    let (branch, _config) = ensure_initialized(repository)?;
+    let mut commits = vec![];
+    let mut top = branch.into_reference().peel_to_commit()?;
+    loop {
+        let parent = top.parents().next();
+        commits.push(top);
+        match parent {
+            Some(parent) => top = parent,
+            None => break,
+        }
+    }
+
+    let mut found = false;
+    let mut history: Vec<RefHistoryEntry> = vec![];
+    for commit in commits.iter().rev() {
+        let config = Config::from_commit(repository, commit)?;
+        let dependency = config.dependencies.get(name);
+        found |= dependency.is_some();
+        let target = dependency.and_then(|dependency| {
+            dependency
+                .heads
+                .get(reference)
+                .or_else(|| dependency.heads.get(&format!("refs/heads/{reference}")))
+                .or_else(|| dependency.heads.get(&format!("refs/tags/{reference}")))
+                .map(|head| head.commit.clone())
+        });
+        let previous = history.last().and_then(|entry| entry.target.as_ref());
+        if target.as_ref() != previous && (target.is_some() || previous.is_some()) {
+            history.push(RefHistoryEntry {
+                target,
+                commit: commit.id().to_string(),
+                date: timestamp::rfc3339(commit.time().seconds()),
+            });
+        }
+    }
+    if !found {
+        return Err(Error::DependencyNotFound(name.to_string()).into());
+    }
+    if history.is_empty() {
+        return Err(anyhow::Error::msg(format!("ref {reference} not found")));
+    }
+    Ok(history)
+}
+
+/// Returns what a paravendor commit changed, as recorded in its note on [`NOTES_REF`]
+///
+/// Commits without a note (made before notes were written, or that didn't change any
+/// refs) return `None`.
+pub fn note(repository: &Repository, revision: &str) -> Result<Option<ConfigDiff>, anyhow::Error> {
+    let commit = repository.revparse_single(revision)?.peel_to_commit()?;
+    let note = match repository.find_note(Some(NOTES_REF), commit.id()) {
+        Ok(note) => note,
+        Err(e) if e.code() == git2::ErrorCode::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let message = note
+        .message()
+        .ok_or_else(|| anyhow::Error::msg("note is not valid UTF-8"))?;
+    Ok(Some(toml::from_str(message)?))
+}
+
+/// Checks the configs on the first-parent paravendor history against the checksums in
+/// their commits' [`CONFIG_SHA256_TRAILER`], returning the commits where they differ
+///
+/// Commits without the trailer (such as those made by earlier versions) are skipped.
+pub fn verify_config(repository: &Repository) -> Result<Vec<git2::Oid>, anyhow::Error> {
+    let (branch, _config) = ensure_initialized(repository)?;
+    let mut mismatches = vec![];
+    let mut top = branch.into_reference().peel_to_commit()?;
+    loop {
+        let trailers = git2::message_trailers_strs(top.message().unwrap_or_default())?;
+        let expected = trailers
+            .iter()
+            .filter(|(key, _)| *key == CONFIG_SHA256_TRAILER)
+            .map(|(_, value)| value)
+            .next_back();
+        if let Some(expected) = expected {
+            let actual = config_blob(repository, &top).map(|blob| sha256(blob.content()));
+            if actual.ok().as_deref() != Some(expected) {
+                mismatches.push(top.id());
+            }
+        }
+        match top.parents().next() {
+            Some(parent) => top = parent,
+            None => break,
+        }
+    }
+    Ok(mismatches)
+}
+
+/// Object of a dependency that isn't in the repository, as found by [`verify`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct MissingObject {
+    /// Head the object was reached from
+    pub head: String,
+    pub id: git2::Oid,
+    /// Type the object was expected to be of
+    pub kind: ObjectType,
+}
+
+/// Checks that the objects of every dependency's heads are in the repository,
+/// returning the first missing object of each dependency that has any
+///
+/// With `deep`, the whole history of the heads is checked, including trees and blobs.
+pub fn verify(
+    repository: &Repository,
+    deep: bool,
+) -> Result<BTreeMap<String, MissingObject>, anyhow::Error> {
+    let (_, config) = ensure_initialized(repository)?;
+    let odb = repository.odb()?;
+    let mut missing = BTreeMap::new();
+    for (name, dependency) in &config.dependencies {
+        let mut seen = std::collections::HashSet::new();
+        for (head_name, head) in &dependency.heads {
+            let missing_object = |id, kind| MissingObject {
+                head: head_name.clone(),
+                id,
+                kind,
+            };
+            let id = git2::Oid::from_str(&head.commit)?;
+            let target = git2::Oid::from_str(head.target())?;
+            let found = if !odb.exists(id) {
+                // Without the object its type is unknown, but heads are mostly commits
+                let kind = if head.peeled.is_some() {
+                    ObjectType::Tag
+                } else {
+                    ObjectType::Commit
+                };
+                Some(missing_object(id, kind))
+            } else if !odb.exists(target) {
+                Some(missing_object(target, ObjectType::Commit))
+            } else if deep && odb.read_header(target)?.1 == ObjectType::Commit {
+                objects::first_missing(repository, target, &mut seen)?
+                    .map(|(id, kind)| missing_object(id, kind))
+            } else {
+                None
+            };
+            if let Some(found) = found {
+                missing.insert(name.clone(), found);
+                break;
+            }
+        }
+    }
+    Ok(missing)
+}
+
+/// Outcome of [`repair`]
+#[derive(Debug, Default)]
+pub struct RepairReport {
+    /// Dependencies whose missing objects were fetched again
+    pub repaired: Vec<String>,
+    /// Dependencies still missing objects, which upstream didn't have (anymore)
+    pub unrepaired: BTreeMap<String, MissingObject>,
+    /// New paravendor commit keeping the heads of repaired dependencies reachable, if
+    /// any of them weren't
+    pub commit: Option<git2::Oid>,
+}
+
+/// Fetches objects missing from the history of dependencies (as found by a deep
+/// [`verify`]) again
+///
+/// Dependencies are fetched into a scratch repository, so that objects this repository
+/// claims to have are sent, too, and only the missing objects are copied over. Heads
+/// that aren't reachable from the paravendor branch are made its parents. Complete
+/// dependencies are left alone, so repairing a complete repository does nothing.
+pub fn repair(
+    repository: &Repository,
+    options: &FetchOptions,
+    progress: &mut dyn ProgressSink,
+) -> Result<RepairReport, anyhow::Error> {
+    let _lock = lock::Lock::acquire(repository)?;
+    let (branch, config) = ensure_initialized(repository)?;
+    let mut report = RepairReport::default();
+    let missing = verify(repository, true)?;
+    if missing.is_empty() {
+        return Ok(report);
+    }
+
+    let scratch_path = repository.path().join("paravendor-repair");
+    if scratch_path.exists() {
+        std::fs::remove_dir_all(&scratch_path)?;
+    }
+    let odb = repository.odb()?;
+    let result = (|| -> Result<(), anyhow::Error> {
+        for name in missing.keys() {
+            let dependency = config.dependency(name)?;
+            let copied = {
+                let scratch = Repository::init_bare(&scratch_path)?;
+                sync_from_mirrors(
+                    &scratch,
+                    &dependency.url,
+                    &dependency.mirrors,
+                    None,
+                    &[],
+                    options,
+                    progress,
+                )
+                .map_err(|e| e.context(format!("failed to repair {name}")))?;
+                let scratch_odb = scratch.odb()?;
+                let mut copied = 0;
+                for head in dependency.heads.values() {
+                    // Annotated tags themselves, besides the history of what they point to
+                    let tag = git2::Oid::from_str(&head.commit)?;
+                    if head.peeled.is_some() && !odb.exists(tag) && scratch_odb.exists(tag) {
+                        let object = scratch_odb.read(tag)?;
+                        odb.write(object.kind(), object.data())?;
+                        copied += 1;
+                    }
+                    let target = git2::Oid::from_str(head.target())?;
+                    copied += objects::copy_missing(&scratch, &odb, target)?;
+                }
+                copied
+            };
+            std::fs::remove_dir_all(&scratch_path)?;
+            info!(name, copied, "copied missing objects");
+        }
+        Ok(())
+    })();
+    if scratch_path.exists() {
+        std::fs::remove_dir_all(&scratch_path)?;
+    }
+    result?;
+
+    let still_missing = verify(repository, true)?;
+    let tip = branch.into_reference().peel_to_commit()?;
+    let mut head_commits = vec![];
+    for name in missing.keys() {
+        if let Some(object) = still_missing.get(name) {
+            report.unrepaired.insert(name.clone(), object.clone());
+            continue;
+        }
+        report.repaired.push(name.clone());
+        for head in config.dependency(name)?.heads.values() {
+            if let Ok(commit) = repository.find_commit(git2::Oid::from_str(head.target())?) {
+                head_commits.push(commit);
+            }
+        }
+    }
+    let parents = unreachable_from(repository, &tip, head_commits)?;
+    if !parents.is_empty() {
+        report.commit = Some(commit_config(
+            repository,
+            &branch_ref(repository)?,
+            tip,
+            &config,
+            &format!("Repair: {}", report.repaired.join(", ")),
+            parents,
+            false,
+        )?);
+    }
+    Ok(report)
+}
+
+/// Returns the config stored in a paravendor revision exactly as it was committed
+pub fn cat_config(repository: &Repository, revision: &str) -> Result<Vec<u8>, anyhow::Error> {
+    ensure_initialized(repository)?;
+    let commit = repository.revparse_single(revision)?.peel_to_commit()?;
+    Ok(config_blob(repository, &commit)?.content().to_vec())
+}
+
+fn config_blob<'a>(
+    repository: &'a Repository,
+    commit: &git2::Commit,
+) -> Result<git2::Blob<'a>, anyhow::Error> {
+    let entry = commit.tree()?.get_name("config").map(|entry| entry.id());
+    let id = entry.ok_or_else(|| {
+        anyhow::Error::msg(format!("paravendor config not found in {}", commit.id()))
+    })?;
+    Ok(repository.find_blob(id)?)
+}
+
+/// How [`cargo_patch`] presents vendored dependencies to Cargo
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum CargoFormat {
+    /// `[patch."<url>"]` tables for `Cargo.toml`
+    #[default]
+    Patch,
+    /// `[source]` replacements for `.cargo/config.toml`
+    SourceReplacement,
+}
+
+/// Renders Cargo configuration making it use the vendored commits of `reference`
+/// (resolved in each dependency) from this repository instead of the network
+///
+/// Dependencies without `reference` are listed as comments.
+pub fn cargo_patch(
+    repository: &Repository,
+    reference: &str,
+    format: CargoFormat,
+) -> Result<String, anyhow::Error> {
+    let (_branch, config) = ensure_initialized(repository)?;
+    let path = repository
+        .path()
+        .canonicalize()?
+        .to_string_lossy()
+        .trim_end_matches('/')
+        .to_string();
+    let local = toml::Value::String(format!("file://{path}"));
+
+    let mut output = String::new();
+    for (name, dependency) in &config.dependencies {
+        let Ok(commit) = dependency.resolve(reference, true) else {
+            output.push_str(&format!("# {name}: {reference} not found\n\n"));
+            continue;
+        };
+        let url = toml::Value::String(dependency.url.clone());
+        let rev = toml::Value::String(commit.to_string());
+        match format {
+            CargoFormat::Patch => output.push_str(&format!(
+                "[patch.{url}]\n{} = {{ git = {local}, rev = {rev} }}\n\n",
+                toml::Value::String(name.to_string())
+            )),
+            CargoFormat::SourceReplacement => {
+                let replacement = toml::Value::String(format!("paravendor-{name}"));
+                output.push_str(&format!(
+                    "[source.{url}]\ngit = {url}\nreplace-with = {replacement}\n\n\
+                     [source.{replacement}]\ngit = {local}\nrev = {rev}\n\n"
+                ));
+            }
+        }
+    }
+    Ok(output.trim_end().to_string())
+}
+
+/// What is vendored, independently of the vendored objects, as exported by [`manifest`]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub dependencies: BTreeMap<String, ManifestDependency>,
+}
+
+/// A dependency in a [`Manifest`]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ManifestDependency {
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub disabled: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub frozen: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mirrors: Vec<String>,
+    /// Recorded refs and the objects they point to, only included on request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refs: Option<BTreeMap<String, String>>,
+}
+
+/// Describes the vendored dependencies, optionally `with_refs` they are at
+pub fn manifest(repository: &Repository, with_refs: bool) -> Result<Manifest, anyhow::Error> {
+    let (_branch, config) = ensure_initialized(repository)?;
+    let dependencies = config
+        .dependencies
+        .into_iter()
+        .map(|(name, dependency)| {
+            let refs = with_refs.then(|| {
+                dependency
+                    .heads
+                    .into_iter()
+                    .map(|(name, head)| (name, head.commit))
+                    .collect()
+            });
+            let dependency = ManifestDependency {
+                url: dependency.url,
+                description: dependency.description,
+                disabled: dependency.disabled,
+                frozen: dependency.frozen,
+                groups: dependency.groups,
+                mirrors: dependency.mirrors,
+                refs,
+            };
+            (name, dependency)
+        })
+        .collect();
+    Ok(Manifest { dependencies })
+}
+
+/// A dependency exported by [`export_submodules`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Submodule {
+    pub name: String,
+    /// Path relative to the working directory
+    pub path: String,
+    /// Pinned commit, `None` if the dependency has no default branch
+    pub commit: Option<git2::Oid>,
+}
+
+/// Writes `.gitmodules` entries for all dependencies under `dir` (relative to the
+/// working directory), pinned to their default branch commits
+///
+/// With `gitlinks`, the submodules (and `.gitmodules`) are also added to the index,
+/// ready to be committed. Dependencies without a default branch are skipped there.
+pub fn export_submodules(
+    repository: &Repository,
+    dir: &str,
+    gitlinks: bool,
+) -> Result<Vec<Submodule>, anyhow::Error> {
+    let (_branch, config) = ensure_initialized(repository)?;
+    let workdir = repository
+        .workdir()
+        .ok_or_else(|| anyhow::Error::msg("exporting submodules requires a working directory"))?;
+    let mut gitmodules = git2::Config::open(&workdir.join(".gitmodules"))?;
+    let mut index = repository.index()?;
+
+    let dir = dir.trim_end_matches('/');
+    let mut submodules = vec![];
+    for (name, dependency) in &config.dependencies {
+        let path = if dir.is_empty() {
+            name.to_string()
+        } else {
+            format!("{dir}/{name}")
+        };
+        gitmodules.set_str(&format!("submodule.{name}.path"), &path)?;
+        gitmodules.set_str(&format!("submodule.{name}.url"), &dependency.url)?;
+        let commit = dependency
+            .resolve("HEAD", true)
+            .ok()
+            .map(git2::Oid::from_str)
+            .transpose()?;
+        if let (true, Some(commit)) = (gitlinks, commit) {
+            index.add(&git2::IndexEntry {
+                ctime: git2::IndexTime::new(0, 0),
+                mtime: git2::IndexTime::new(0, 0),
+                dev: 0,
+                ino: 0,
+                mode: 0o160000,
+                uid: 0,
+                gid: 0,
+                file_size: 0,
+                id: commit,
+                flags: 0,
+                flags_extended: 0,
+                path: path.as_bytes().to_vec(),
+            })?;
+        }
+        submodules.push(Submodule {
+            name: name.to_string(),
+            path,
+            commit,
+        });
+    }
+    if gitlinks {
+        index.add_path(Path::new(".gitmodules"))?;
+        index.write()?;
+    }
+    Ok(submodules)
+}
+
+/// JSON Schema describing [`Config`]
+pub fn schema() -> schemars::schema::RootSchema {
+    schemars::schema_for!(Config)
+}
+
+/// Returns the first-parent history of the paravendor branch, newest first
+pub fn log(repository: &Repository, options: &LogOptions) -> Result<Vec<LogEntry>, anyhow::Error> {
+    let (branch, _config) = ensure_initialized(repository)?;
+    let mut entries = vec![];
+    let mut top = branch.into_reference().peel_to_commit()?;
+    let mut config = None;
+    while options.max_count != Some(entries.len()) {
+        let parent = top.parents().next();
+        let mut entry = LogEntry {
+            id: top.id(),
+            summary: top
+                .message()
+                .unwrap_or("")
+                .lines()
+                .next()
+                .unwrap_or("")
+                .to_string(),
+            merged: vec![],
+            changes: None,
+        };
+        let mut included = true;
+        if options.changes || options.dependency.is_some() {
+            let new = match config.take() {
+                Some(config) => config,
+                None => Config::from_commit(repository, &top)?,
+            };
+            let old = match &parent {
+                Some(parent) => Config::from_commit(repository, parent)?,
+                None => Config::default(),
+            };
+            if let Some(name) = &options.dependency {
+                // `last_synced` changes for every dependency fetched along with a change,
+                // and `last_source` whenever fetching fails over to a mirror
+                let dependency = |config: &Config| {
+                    config.dependencies.get(name).map(|dependency| Dependency {
+                        last_synced: None,
+                        last_source: None,
+                        ..dependency.clone()
+                    })
+                };
+                included = dependency(&old) != dependency(&new);
+            }
+            if options.changes && included {
+                entry.merged = top
+                    .parent_ids()
+                    .skip(1)
+                    .map(|id| MergedCommit {
+                        id,
+                        refs: new
+                            .dependencies
+                            .iter()
+                            .flat_map(|(name, dependency)| {
+                                dependency
+                                    .heads
+                                    .iter()
+                                    .filter(|(_, head)| head.target() == id.to_string())
+                                    .map(|(reference, _)| (name.to_string(), reference.to_string()))
+                            })
+                            .collect(),
+                    })
+                    .collect();
+                entry.changes = Some(ConfigDiff::new(&old, &new));
+            }
+            config = Some(old);
+        }
+        if included {
+            entries.push(entry);
+        }
+        if let Some(parent) = parent {
+            top = parent;
+        } else {
+            break;
+        }
+    }
+    if options.reverse {
+        entries.reverse();
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::ops::{Deref, DerefMut};
+    use std::process::{ExitCode, Termination};
+    use tempfile::*;
+
+    struct TempRepository {
+        repository: Repository,
+        dir: TempDir,
+        dependencies: BTreeMap<String, TempRepository>,
+    }
+
+    impl TempRepository {
+        fn new() -> Result<Self, anyhow::Error> {
+            let dir = tempdir()?;
+            let repository = Repository::init(dir.as_ref())?;
+            Ok(Self {
+                repository,
+                dir,
+                dependencies: BTreeMap::new(),
+            })
+        }
+
+        fn new_bare() -> Result<Self, anyhow::Error> {
+            let dir = tempdir()?;
+            let repository = Repository::init_bare(dir.as_ref())?;
+            Ok(Self {
+                repository,
+                dir,
+                dependencies: BTreeMap::new(),
+            })
+        }
+
+        fn depends_on(&mut self, name: &str, repository: TempRepository) {
+            self.dependencies.insert(name.to_string(), repository);
+        }
+
+        fn get_dependency(&self, name: &str) -> Option<&TempRepository> {
+            self.dependencies.get(name)
+        }
+
+        fn get_mut_dependency(&mut self, name: &str) -> Option<&mut TempRepository> {
+            self.dependencies.get_mut(name)
+        }
+    }
+
+    impl Deref for TempRepository {
+        type Target = Repository;
+
+        fn deref(&self) -> &Self::Target {
+            &self.repository
+        }
+    }
+
+    impl DerefMut for TempRepository {
+        fn deref_mut(&mut self) -> &mut Self::Target {
+            &mut self.repository
+        }
+    }
+
+    impl Termination for TempRepository {
+        fn report(self) -> ExitCode {
+            ExitCode::SUCCESS
+        }
+    }
+
+    /// Don't wait for retries in tests
+    fn fetch_options() -> FetchOptions {
+        FetchOptions {
+            retries: 0,
+            ..Default::default()
+        }
+    }
+
+    fn add_options() -> AddOptions {
+        AddOptions {
+            fetch: fetch_options(),
+            ..Default::default()
+        }
+    }
+
+    fn sync_options() -> SyncOptions {
+        SyncOptions {
+            fetch: fetch_options(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn init_clean() -> Result<TempRepository, anyhow::Error> {
+        let repo = TempRepository::new()?;
+        {
+            assert!(repo.find_branch("paravendor", BranchType::Local).is_err());
+
+            init(&repo, false, None)?;
+            let (_branch, config) = ensure_initialized(&repo)?;
+            assert_eq!(config.version, "1.2");
+        }
+        Ok(repo)
+    }
+
+    #[test]
+    fn init_without_identity() -> Result<(), anyhow::Error> {
+        let repo = TempRepository::new()?;
+        // Empty values are as good as missing ones
+        let mut config = repo.config()?;
+        config.set_str("user.name", "")?;
+        config.set_str("user.email", "")?;
+        assert!(repo.signature().is_err());
+
+        let commit = repo.find_commit(init(&repo, false, None)?)?;
+        assert!(commit.author().name().is_some());
+        assert!(commit.committer().email().is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn log_bare() -> Result<(), anyhow::Error> {
+        let repo = TempRepository::new_bare()?;
+        assert!(repo.is_bare());
+
+        let tip = init(&repo, false, None)?;
+
+        let entries = log(&repo, &LogOptions::default())?;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].id, tip);
+        assert_eq!(entries[0].summary, "Initialize paravendor");
+        Ok(())
+    }
+
+    #[test]
+    fn log_options() -> Result<(), anyhow::Error> {
+        let repo = add()?;
+        let ids = |options: LogOptions| -> Result<Vec<git2::Oid>, anyhow::Error> {
+            Ok(log(&repo, &options)?
+                .into_iter()
+                .map(|entry| entry.id)
+                .collect())
+        };
+        let all = ids(LogOptions::default())?;
+        assert_eq!(all.len(), 2);
+        let (added, initialized) = (all[0], all[1]);
+        assert_eq!(
+            ids(LogOptions {
+                max_count: Some(1),
+                ..Default::default()
+            })?,
+            vec![added]
+        );
+        assert!(ids(LogOptions {
+            max_count: Some(0),
+            ..Default::default()
+        })?
+        .is_empty());
+        assert_eq!(
+            ids(LogOptions {
+                reverse: true,
+                ..Default::default()
+            })?,
+            vec![initialized, added]
+        );
+        // The newest commit, not the oldest one
+        assert_eq!(
+            ids(LogOptions {
+                max_count: Some(1),
+                reverse: true,
+                ..Default::default()
+            })?,
+            vec![added]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn log_changes() -> Result<(), anyhow::Error> {
+        let repo = add()?;
+        let old = repo
+            .get_dependency("dep")
+            .unwrap()
+            .head()?
+            .target()
+            .unwrap();
+        let repo = repo_with_changed_dependency("dep", repo)?;
+        // Annotated tags are matched by the commit they point to
+        {
+            let dep = repo.get_dependency("dep").unwrap();
+            let commit = dep.head()?.peel_to_commit()?;
+            dep.tag("v1", commit.as_object(), &dep.signature()?, "v1", false)?;
+        }
+        sync(&repo, &[], &sync_options(), &mut NoProgress)?;
+        let new = repo
+            .get_dependency("dep")
+            .unwrap()
+            .head()?
+            .target()
+            .unwrap();
+
+        let entries = log(
+            &repo,
+            &LogOptions {
+                changes: true,
+                ..Default::default()
+            },
+        )?;
+        let [synced, added, initialized] = &entries[..] else {
+            panic!("unexpected log {entries:?}");
+        };
+        let head = repo
+            .get_dependency("dep")
+            .unwrap()
+            .head()?
+            .name()
+            .unwrap()
+            .to_string();
+        assert_eq!(
+            synced.merged,
+            vec![MergedCommit {
+                id: new,
+                refs: vec![
+                    ("dep".to_string(), "HEAD".to_string()),
+                    ("dep".to_string(), head.clone()),
+                    ("dep".to_string(), "refs/tags/v1".to_string()),
+                ],
+            }]
+        );
+        assert_eq!(
+            synced.changes.as_ref().unwrap().changed["dep"][&head],
+            RefChange {
+                old: Some(old.to_string()),
+                new: Some(new.to_string()),
+            }
+        );
+        assert_eq!(added.merged[0].id, old);
+        assert_eq!(added.changes.as_ref().unwrap().added, vec!["dep"]);
+        assert!(initialized.merged.is_empty());
+        assert_eq!(initialized.changes, Some(ConfigDiff::default()));
+
+        // Not looked into unless asked for
+        let entries = log(&repo, &LogOptions::default())?;
+        assert!(entries[0].merged.is_empty() && entries[0].changes.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn notes() -> Result<(), anyhow::Error> {
+        let repo = add()?;
+        let added = note(&repo, "paravendor")?.unwrap();
+        assert_eq!(added.added, vec!["dep"]);
+        assert_eq!(note(&repo, "paravendor~1")?, None);
+
+        let old = repo
+            .get_dependency("dep")
+            .unwrap()
+            .head()?
+            .target()
+            .unwrap();
+        let repo = repo_with_changed_dependency("dep", repo)?;
+        sync(&repo, &[], &sync_options(), &mut NoProgress)?;
+        let new = repo
+            .get_dependency("dep")
+            .unwrap()
+            .head()?
+            .target()
+            .unwrap();
+        let synced = note(&repo, "paravendor")?.unwrap();
+        assert_eq!(synced, crate::diff(&repo, "paravendor~1", "paravendor")?);
+        assert_eq!(
+            synced.changed["dep"]["HEAD"],
+            RefChange {
+                old: Some(old.to_string()),
+                new: Some(new.to_string()),
+            }
+        );
+
+        // Nothing to note
+        set_disabled(&repo, "dep", true)?;
+        assert_eq!(note(&repo, "paravendor")?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn config_at_revision() -> Result<(), anyhow::Error> {
+        let repo = add()?;
+        let old = repo
+            .get_dependency("dep")
+            .unwrap()
+            .head()?
+            .target()
+            .unwrap();
+        let repo = repo_with_changed_dependency("dep", repo)?;
+        sync(&repo, &[], &sync_options(), &mut NoProgress)?;
+
+        let before = config_at(&repo, "paravendor~1")?;
+        assert_eq!(
+            before.dependency("dep")?.resolve("master", true)?,
+            old.to_string()
+        );
+        assert_eq!(config_at(&repo, "paravendor")?.dependencies, list(&repo)?);
+        assert_ne!(before.dependencies, list(&repo)?);
+        assert!(config_at(&repo, "paravendor~2")?.dependencies.is_empty());
+        assert!(config_at(&repo, "nonexistent").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn compare() -> Result<(), anyhow::Error> {
+        // Both start from the same commit
+        let repo = add_dependency_to_repo(add()?, "other")?;
+        let base = show_ref(&repo, "dep", "master", true)?;
+        assert_eq!(show_ref(&repo, "other", "master", true)?, base);
+        let repo = repo_with_changed_dependency("dep", repo)?;
+        sync(
+            &repo,
+            &["dep".to_string()],
+            &sync_options(),
+            &mut NoProgress,
+        )?;
+
+        assert_eq!(
+            crate::compare(&repo, "dep", "other")?,
+            Comparison {
+                only_first: 1,
+                only_second: 0,
+                shared: 1,
+                merge_bases: vec![base.clone()],
+            }
+        );
+        let reversed = crate::compare(&repo, "other", "dep")?;
+        assert_eq!((reversed.only_first, reversed.only_second), (0, 1));
+        assert!(crate::compare(&repo, "dep", "nonexistent").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn ref_history() -> Result<(), anyhow::Error> {
+        let repo = add()?;
+        let old = repo
+            .get_dependency("dep")
+            .unwrap()
+            .head()?
+            .target()
+            .unwrap();
+        let repo = repo_with_changed_dependency("dep", repo)?;
+        sync(&repo, &[], &sync_options(), &mut NoProgress)?;
+        let new = repo
+            .get_dependency("dep")
+            .unwrap()
+            .head()?
+            .target()
+            .unwrap();
+        // Not a change of the ref
+        set_disabled(&repo, "dep", true)?;
+        crate::remove(&repo, "dep")?;
+
+        let history = crate::ref_history(&repo, "dep", "master")?;
+        assert_eq!(
+            history
+                .iter()
+                .map(|entry| entry.target.clone())
+                .collect::<Vec<_>>(),
+            vec![Some(old.to_string()), Some(new.to_string()), None]
+        );
+        assert_eq!(
+            history[0].commit,
+            repo.revparse_single("paravendor~3")?.id().to_string()
+        );
+        assert_eq!(
+            history[1].commit,
+            repo.revparse_single("paravendor~2")?.id().to_string()
+        );
+        assert_eq!(
+            history[2].commit,
+            repo.revparse_single("paravendor")?.id().to_string()
+        );
+        assert_eq!(
+            crate::ref_history(&repo, "dep", "refs/heads/master")?,
+            history
+        );
+
+        assert!(crate::ref_history(&repo, "dep", "nonexistent").is_err());
+        let err = crate::ref_history(&repo, "nonexistent", "master").unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::DependencyNotFound(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn log_dependency() -> Result<(), anyhow::Error> {
+        let repo = add_dependency_to_repo(add()?, "other")?;
+        let repo = repo_with_changed_dependency("other", repo)?;
+        // Only `last_synced` of dep changes
+        sync(&repo, &[], &sync_options(), &mut NoProgress)?;
+        set_disabled(&repo, "dep", true)?;
+
+        let summaries = |name: &str| -> Result<Vec<String>, anyhow::Error> {
+            let options = LogOptions {
+                dependency: Some(name.to_string()),
+                ..Default::default()
+            };
+            Ok(log(&repo, &options)?
+                .into_iter()
+                .map(|entry| entry.summary)
+                .collect())
+        };
+        let dep = summaries("dep")?;
+        assert_eq!(dep.len(), 2);
+        assert!(dep[1].starts_with("Add dep "));
+        let other = summaries("other")?;
+        assert_eq!(other.len(), 2);
+        assert_eq!(other[0], "Sync: other");
+        assert!(other[1].starts_with("Add other "));
+        assert!(summaries("nonexistent")?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn migrate_on_load() -> Result<(), anyhow::Error> {
+        let repo = init_clean()?;
+        let (branch, _config) = ensure_initialized(&repo)?;
+        let tip = branch.into_reference().peel_to_commit()?;
+
+        // Write a 1.0 config to the branch
+        let blob = repo.blob(b"version = \"1.0\"\n[dependencies]\n")?;
+        let mut tree = TreeUpdateBuilder::new();
+        tree.upsert("config", blob, FileMode::Blob);
+        let tree = repo.find_tree(tree.create_updated(&repo, &tip.tree()?)?)?;
+        let sig = repo.signature()?;
+        let old_commit = repo.commit(
+            Some("refs/heads/paravendor"),
+            &sig,
+            &sig,
+            "Downgrade",
+            &tree,
+            &[&tip],
+        )?;
+
+        // Loading upgrades the config in memory only
+        let (branch, config) = ensure_initialized(&repo)?;
+        assert_eq!(config.version, migrate::CURRENT_VERSION);
+        assert_eq!(branch.into_reference().peel_to_commit()?.id(), old_commit);
+
+        assert_eq!(crate::migrate(&repo)?.as_deref(), Some("1.0"));
+        let (branch, config) = ensure_initialized(&repo)?;
+        assert_eq!(config.version, migrate::CURRENT_VERSION);
+        let commit = branch.into_reference().peel_to_commit()?;
+        assert_eq!(commit.parent_id(0)?, old_commit);
+        assert_eq!(
+            commit.summary(),
+            Some(format!("Migrate config from 1.0 to {}", migrate::CURRENT_VERSION).as_str())
+        );
+
+        // Nothing is left to migrate
+        assert_eq!(crate::migrate(&repo)?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn load_newer_version() -> Result<(), anyhow::Error> {
+        let repo = init_clean()?;
+        let (branch, _config) = ensure_initialized(&repo)?;
+        let tip = branch.into_reference().peel_to_commit()?;
+
+        // A config written by a future version, with fields this one doesn't know
+        let blob = repo.blob(b"version = \"1.9\"\nfuture = true\n[dependencies]\n")?;
+        let mut tree = TreeUpdateBuilder::new();
+        tree.upsert("config", blob, FileMode::Blob);
+        let tree = repo.find_tree(tree.create_updated(&repo, &tip.tree()?)?)?;
+        let sig = repo.signature()?;
+        let future = repo.commit(
+            Some("refs/heads/paravendor"),
+            &sig,
+            &sig,
+            "From the future",
+            &tree,
+            &[&tip],
+        )?;
+
+        let err = ensure_initialized(&repo).err().unwrap().to_string();
+        assert!(err.contains("1.9 is newer than"), "{err}");
+        assert!(err.contains("upgrade paravendor"), "{err}");
+        // Nothing is migrated
+        assert_eq!(repo.refname_to_id("refs/heads/paravendor")?, future);
+        Ok(())
+    }
+
+    #[test]
+    fn commit_on_moved_branch() -> Result<(), anyhow::Error> {
+        let repo = add()?;
+        let (branch, mut config) = ensure_initialized(&repo)?;
+        let tip = branch.into_reference().peel_to_commit()?;
+
+        // Another process commits after this one has read the tip
+        let moved = crate::remove(&repo, "dep")?;
+
+        config.dependencies.get_mut("dep").unwrap().description = Some("stale".to_string());
+        let err = commit_config(
+            &repo,
+            "refs/heads/paravendor",
+            tip.clone(),
+            &config,
+            "Describe dep",
+            vec![],
+            false,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("moved"), "{err}");
+        assert_eq!(repo.refname_to_id("refs/heads/paravendor")?, moved);
+
+        // Other refs are updated regardless
+        commit_config(&repo, STAGED_REF, tip, &config, "Stage", vec![], false)?;
+        Ok(())
+    }
+
+    fn demo_repo_with_one_commit() -> Result<TempRepository, anyhow::Error> {
+        let repo = TempRepository::new()?;
+        let sig = git2::Signature::new("John Doe", "john@doe.com", &git2::Time::new(0, 0))?;
+
+        // Prepare initial commit
+        let tree_oid = repo.treebuilder(None)?.write()?;
+
+        let _commit = repo.commit(
+            Some("refs/heads/master"),
+            &sig,
+            &sig,
+            "init",
+            &repo.find_tree(tree_oid)?,
+            &[],
+        )?;
+        Ok(repo)
+    }
+
+    fn add_dependency_to_repo(
+        mut repo: TempRepository,
+        name: &str,
+    ) -> Result<TempRepository, anyhow::Error> {
+        let dep = demo_repo_with_one_commit()?;
+        let dep_repo_commit = dep.head()?.peel_to_commit()?.id();
+
+        {
+            let init_commit = dep.head()?.peel_to_commit()?;
+            crate::add(
+                &repo,
+                name,
+                &dep.dir.as_ref().to_string_lossy(),
+                &add_options(),
+                &mut NoProgress,
+            )?;
+            let (branch, config) = ensure_initialized(&repo)?;
+
+            let dep = config.dependencies.get(name).unwrap();
+            for head_name in ["HEAD", "refs/heads/master"] {
+                let head = dep.heads.get(head_name).unwrap();
+                assert_eq!(head.commit, dep_repo_commit.to_string());
+
+                // It's either a new parent, or has already been reachable
+                let commit = branch.get().peel_to_commit()?;
+                assert!(repo.graph_descendant_of(commit.id(), dep_repo_commit)?);
+                assert!(repo.graph_descendant_of(commit.id(), init_commit.id())?);
+            }
+        }
+
+        repo.depends_on(name, dep);
+
+        Ok(repo)
+    }
+
+    #[test]
+    fn add() -> Result<TempRepository, anyhow::Error> {
+        add_dependency_to_repo(init_clean()?, "dep")
+    }
+
+    #[test]
+    fn sync_no_changes() -> Result<(), anyhow::Error> {
+        let repo = add()?;
+
+        let (original_branch, original_config) = ensure_initialized(&repo)?;
+
+        // Only `last_synced` changes (once a second has passed)
+        std::thread::sleep(std::time::Duration::from_secs(1));
+        let report = sync(&repo, &[], &sync_options(), &mut NoProgress)?;
+        assert!(report.changed.is_empty());
+
+        let (branch, config) = ensure_initialized(&repo)?;
+        let tip = branch.get().peel_to_commit()?;
+        assert_eq!(
+            tip.parent_id(0)?,
+            original_branch.get().peel_to_commit()?.id()
+        );
+        assert_eq!(tip.summary(), Some("Sync: dep (unchanged)"));
+        assert_eq!(
+            config.dependency("dep")?.heads,
+            original_config.dependency("dep")?.heads
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn show_ref_abbreviated() -> Result<(), anyhow::Error> {
+        let repo = add()?;
+        let (_branch, config) = ensure_initialized(&repo)?;
+        let dep = config.dependencies.get("dep").unwrap();
+        let commit = repo
+            .get_dependency("dep")
+            .unwrap()
+            .head()?
+            .peel_to_commit()?
+            .id()
+            .to_string();
+
+        assert_eq!(dep.resolve("master", true)?, commit);
+        assert_eq!(dep.resolve(&commit[..7], true)?, commit);
+        assert_eq!(dep.resolve(&commit[..7].to_uppercase(), true)?, commit);
+        assert!(dep.resolve(&commit[..3], true).is_err());
+        assert!(dep.resolve("nonexistent", true).is_err());
+
+        let mut ambiguous = dep.clone();
+        ambiguous.heads.insert(
+            "refs/heads/other".to_string(),
+            Head {
+                commit: format!("{}{}", &commit[..7], "0".repeat(33)),
+                peeled: None,
+            },
+        );
+        let err = ambiguous
+            .resolve(&commit[..7], true)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("ambiguous"));
+        assert!(err.contains(&commit));
+
+        Ok(())
+    }
+
+    #[test]
+    fn annotated_tags_are_peeled() -> Result<(), anyhow::Error> {
+        let repo = init_clean()?;
+        let dep = demo_repo_with_one_commit()?;
+        let commit = dep.head()?.peel_to_commit()?;
+        let tag = dep.tag("v1", commit.as_object(), &dep.signature()?, "v1", false)?;
+        dep.tag_lightweight("light", commit.as_object(), false)?;
+        crate::add(
+            &repo,
+            "dep",
+            &dep.dir.as_ref().to_string_lossy(),
+            &add_options(),
+            &mut NoProgress,
+        )?;
+
+        let (_branch, config) = ensure_initialized(&repo)?;
+        let heads = &config.dependency("dep")?.heads;
+        assert!(!heads.keys().any(|name| name.ends_with("^{}")));
+        let head = heads.get("refs/tags/v1").unwrap();
+        assert_eq!(head.commit, tag.to_string());
+        assert_eq!(head.peeled, Some(commit.id().to_string()));
+        assert_eq!(heads.get("refs/tags/light").unwrap().peeled, None);
+
+        assert_eq!(show_ref(&repo, "dep", "v1", true)?, commit.id().to_string());
+        assert_eq!(show_ref(&repo, "dep", "v1", false)?, tag.to_string());
+        assert_eq!(
+            show_ref(&repo, "dep", "light", false)?,
+            commit.id().to_string()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn annotated_tag_history() -> Result<(), anyhow::Error> {
+        let repo = add()?;
+        // Tagged commits that no branch points to, one through a tag of a tag
+        let (tagged, nested) = {
+            let dep = repo.get_dependency("dep").unwrap();
+            let head = dep.head()?.peel_to_commit()?;
+            let sig = dep.signature()?;
+            let mut commits = vec![];
+            for message in ["tagged", "nested"] {
+                let oid = dep.commit(None, &sig, &sig, message, &head.tree()?, &[&head])?;
+                commits.push(dep.find_commit(oid)?);
+            }
+            dep.tag("v2", commits[0].as_object(), &sig, "v2", false)?;
+            let inner = dep.tag("inner", commits[1].as_object(), &sig, "inner", false)?;
+            dep.tag(
+                "outer",
+                &dep.find_object(inner, None)?,
+                &sig,
+                "outer",
+                false,
+            )?;
+            (commits[0].id(), commits[1].id())
+        };
+        let report = sync(&repo, &[], &sync_options(), &mut NoProgress)?;
+        let tip = report.commit.unwrap();
+
+        let heads = &list(&repo)?["dep"].heads;
+        assert_eq!(heads["refs/tags/v2"].peeled, Some(tagged.to_string()));
+        assert_eq!(heads["refs/tags/outer"].peeled, Some(nested.to_string()));
+        for commit in [tagged, nested] {
+            assert!(repo.graph_descendant_of(tip, commit)?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn push() -> Result<(), anyhow::Error> {
+        let repo = add()?;
+        let remote = TempRepository::new_bare()?;
+        repo.remote("origin", &remote.dir.as_ref().to_string_lossy())?;
+
+        let objects = crate::push(&repo, "origin", &fetch_options())?;
+        assert!(objects > 0);
+        let tip = {
+            let (branch, _config) = ensure_initialized(&repo)?;
+            branch.into_reference().peel_to_commit()?.id()
+        };
+        assert_eq!(
+            remote.find_reference("refs/heads/paravendor")?.target(),
+            Some(tip)
+        );
+        // vendored objects are pushed, too
+        let dep = repo.get_dependency("dep").unwrap();
+        assert!(remote
+            .find_commit(dep.head()?.peel_to_commit()?.id())
+            .is_ok());
+
+        // Diverge the remote branch
+        let sig = remote.signature()?;
+        let remote_tip = remote.find_commit(tip)?;
+        remote.commit(
+            Some("refs/heads/paravendor"),
+            &sig,
+            &sig,
+            "Diverged",
+            &remote_tip.tree()?,
+            &[&remote_tip],
+        )?;
+        let repo = repo_with_changed_dependency("dep", repo)?;
+        sync(&repo, &[], &sync_options(), &mut NoProgress)?;
+        let err = crate::push(&repo, "origin", &fetch_options())
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("diverged"), "{err}");
+        Ok(())
+    }
+
+    #[test]
+    fn pull() -> Result<(), anyhow::Error> {
+        let repo = add()?;
+        let remote = TempRepository::new_bare()?;
+        let remote_url = remote.dir.as_ref().to_string_lossy().to_string();
+        repo.remote("origin", &remote_url)?;
+        crate::push(&repo, "origin", &fetch_options())?;
+
+        let other = TempRepository::new()?;
+        other.remote("origin", &remote_url)?;
+        let (moved, config) = crate::pull(&other, "origin", &fetch_options())?;
+        assert!(moved.is_some());
+        assert!(config.dependencies.contains_key("dep"));
+        let (moved, _config) = crate::pull(&other, "origin", &fetch_options())?;
+        assert!(moved.is_none());
+
+        // Fast-forward
+        let repo = repo_with_changed_dependency("dep", repo)?;
+        let tip = sync(&repo, &[], &sync_options(), &mut NoProgress)?
+            .commit
+            .unwrap();
+        crate::push(&repo, "origin", &fetch_options())?;
+        let (moved, config) = crate::pull(&other, "origin", &fetch_options())?;
+        assert_eq!(moved, Some(tip));
+        assert_eq!(
+            config.dependency("dep")?.heads["refs/heads/master"].commit,
+            repo.get_dependency("dep")
+                .unwrap()
+                .head()?
+                .target()
+                .unwrap()
+                .to_string()
+        );
+
+        // Diverged
+        let ours = other.find_branch("paravendor", BranchType::Local)?;
+        commit_config(
+            &other,
+            "refs/heads/paravendor",
+            ours.into_reference().peel_to_commit()?,
+            &config,
+            "Local change",
+            vec![],
+            false,
+        )?;
+        let repo = repo_with_changed_dependency("dep", repo)?;
+        sync(&repo, &[], &sync_options(), &mut NoProgress)?;
+        crate::push(&repo, "origin", &fetch_options())?;
+        let err = crate::pull(&other, "origin", &fetch_options())
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("diverged"), "{err}");
+        Ok(())
+    }
+
+    #[test]
+    fn paravendor_from_remotes() -> Result<(), anyhow::Error> {
+        let repo = add()?;
+        let tip = ensure_initialized(&repo)?.0.get().peel_to_commit()?.id();
+        let url = repo.dir.as_ref().to_string_lossy().to_string();
+        let with_remotes = |names: &[&str]| -> Result<TempRepository, anyhow::Error> {
+            let other = TempRepository::new()?;
+            for name in names {
+                other.remote(name, &url)?.fetch::<&str>(&[], None, None)?;
+            }
+            Ok(other)
+        };
+
+        // There's no telling which one to use
+        let other = with_remotes(&["first", "second"])?;
+        let err = ensure_initialized(&other).err().unwrap().to_string();
+        assert!(err.contains("first, second"), "{err}");
+        assert!(paravendor_from_remote(&other, "third").is_err());
+        assert_eq!(paravendor_from_remote(&other, "second")?, tip);
+        assert!(ensure_initialized(&other)?
+            .1
+            .dependencies
+            .contains_key("dep"));
+
+        // Unless one of them is `origin`, or it's the only one
+        for names in [&["first", "origin"][..], &["only"]] {
+            let other = with_remotes(names)?;
+            assert_eq!(ensure_initialized(&other)?.0.get().target(), Some(tip));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn init_from_other_remote() -> Result<(), anyhow::Error> {
+        let repo = add()?;
+        let tip = ensure_initialized(&repo)?.0.get().peel_to_commit()?.id();
+        let url = repo.dir.as_ref().to_string_lossy().to_string();
+        let other = TempRepository::new()?;
+        for name in ["origin", "upstream"] {
+            other.remote(name, &url)?;
+        }
+        other
+            .find_remote("upstream")?
+            .fetch::<&str>(&[], None, None)?;
+
+        assert!(init_from_remote(&other, "origin", None).is_err());
+        assert_eq!(init_from_remote(&other, "upstream", None)?, tip);
+        assert!(init_from_remote(&other, "upstream", None).is_err());
+        let upstream = other
+            .find_branch("paravendor", BranchType::Local)?
+            .upstream()?;
+        assert_eq!(upstream.name()?, Some("upstream/paravendor"));
+        assert_eq!(
+            other
+                .branch_upstream_remote("refs/heads/paravendor")?
+                .as_str(),
+            Some("upstream")
+        );
+
+        // `origin` doesn't have it, so it's taken from the only remote that does
+        let other = TempRepository::new()?;
+        other.remote(
+            "origin",
+            &TempRepository::new()?.dir.as_ref().to_string_lossy(),
+        )?;
+        other
+            .remote("upstream", &url)?
+            .fetch::<&str>(&[], None, None)?;
+        assert_eq!(init(&other, false, None)?, tip);
+        Ok(())
+    }
+
+    #[test]
+    fn init_local_branch() -> Result<(), anyhow::Error> {
+        let repo = add()?;
+        let remote = TempRepository::new_bare()?;
+        let url = remote.dir.as_ref().to_string_lossy().to_string();
+        repo.remote("origin", &url)?;
+        crate::push(&repo, "origin", &fetch_options())?;
+        let tip = ensure_initialized(&repo)?.0.get().peel_to_commit()?.id();
+
+        let other = TempRepository::new()?;
+        other
+            .remote("origin", &url)?
+            .fetch::<&str>(&[], None, None)?;
+        assert!(init(&other, false, Some("not..valid")).is_err());
+        assert_eq!(init(&other, false, Some("vendor"))?, tip);
+        assert_eq!(branch_name(&other)?, "vendor");
+        assert!(other.find_branch("paravendor", BranchType::Local).is_err());
+        let upstream = other.find_branch("vendor", BranchType::Local)?.upstream()?;
+        assert_eq!(upstream.name()?, Some("origin/paravendor"));
+        assert!(init(&other, false, None).is_err());
+
+        // The branch is used under its name, and pushed as `paravendor`
+        let removed = crate::remove(&other, "dep")?;
+        assert_eq!(other.refname_to_id("refs/heads/vendor")?, removed);
+        crate::push(&other, "origin", &fetch_options())?;
+        assert_eq!(remote.refname_to_id("refs/heads/paravendor")?, removed);
+
+        // An existing paravendor branch isn't left behind
+        assert!(init(&repo, false, Some("vendor")).is_err());
+        assert_eq!(branch_name(&repo)?, "paravendor");
+
+        // Nothing is recorded unless the branch is created
+        let unfetched = TempRepository::new()?;
+        unfetched.remote("origin", &url)?;
+        assert!(init_from_remote(&unfetched, "origin", Some("vendor")).is_err());
+        assert_eq!(branch_name(&unfetched)?, "paravendor");
+
+        // Nor is a different name than the one already recorded
+        unfetched.config()?.set_str(BRANCH_NAME_CONFIG, "vendor")?;
+        assert!(init(&unfetched, true, Some("other")).is_err());
+        assert_eq!(branch_name(&unfetched)?, "vendor");
+        Ok(())
+    }
+
+    #[test]
+    fn diff() -> Result<(), anyhow::Error> {
+        let repo = add()?;
+        let added = crate::diff(&repo, "paravendor~1", "paravendor")?;
+        assert_eq!(added.added, vec!["dep"]);
+        assert!(added.removed.is_empty() && added.changed.is_empty());
+        let removed = crate::diff(&repo, "paravendor", "paravendor~1")?;
+        assert_eq!(removed.removed, vec!["dep"]);
+
+        let old = repo
+            .get_dependency("dep")
+            .unwrap()
+            .head()?
+            .target()
+            .unwrap()
+            .to_string();
+        let repo = repo_with_changed_dependency("dep", repo)?;
+        sync(&repo, &[], &sync_options(), &mut NoProgress)?;
+        let new = repo
+            .get_dependency("dep")
+            .unwrap()
+            .head()?
+            .target()
+            .unwrap()
+            .to_string();
+        let changed = crate::diff(&repo, "paravendor~1", "paravendor")?;
+        assert!(changed.added.is_empty() && changed.removed.is_empty());
+        let changes = &changed.changed["dep"];
+        assert_eq!(
+            changes["refs/heads/master"],
+            RefChange {
+                old: Some(old),
+                new: Some(new)
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn cargo_patch() -> Result<(), anyhow::Error> {
+        let repo = add()?;
+        let url = repo
+            .get_dependency("dep")
+            .unwrap()
+            .dir
+            .as_ref()
+            .to_string_lossy()
+            .to_string();
+        let commit = repo
+            .get_dependency("dep")
+            .unwrap()
+            .head()?
+            .target()
+            .unwrap()
+            .to_string();
+        let local = format!("file://{}", repo.path().canonicalize()?.display());
+        let local = local.trim_end_matches('/');
+
+        let patch: toml::Table =
+            toml::from_str(&crate::cargo_patch(&repo, "HEAD", CargoFormat::Patch)?)?;
+        let entry = &patch["patch"][&url]["dep"];
+        assert_eq!(entry["git"].as_str(), Some(local));
+        assert_eq!(entry["rev"].as_str(), Some(commit.as_str()));
+
+        let source: toml::Table = toml::from_str(&crate::cargo_patch(
+            &repo,
+            "master",
+            CargoFormat::SourceReplacement,
+        )?)?;
+        let source = &source["source"];
+        assert_eq!(source[&url]["git"].as_str(), Some(url.as_str()));
+        assert_eq!(
+            source[&url]["replace-with"].as_str(),
+            Some("paravendor-dep")
+        );
+        assert_eq!(source["paravendor-dep"]["git"].as_str(), Some(local));
+        assert_eq!(
+            source["paravendor-dep"]["rev"].as_str(),
+            Some(commit.as_str())
+        );
+
+        let missing = crate::cargo_patch(&repo, "nonexistent", CargoFormat::Patch)?;
+        assert_eq!(missing, "# dep: nonexistent not found");
+        Ok(())
+    }
+
+    #[test]
+    fn export_submodules() -> Result<(), anyhow::Error> {
+        let repo = add()?;
+        let dep = repo.get_dependency("dep").unwrap();
+        let commit = dep.head()?.target().unwrap();
+
+        let submodules = crate::export_submodules(&repo, "vendor/", true)?;
+        assert_eq!(
+            submodules,
+            vec![Submodule {
+                name: "dep".to_string(),
+                path: "vendor/dep".to_string(),
+                commit: Some(commit),
+            }]
+        );
+
+        let gitmodules = git2::Config::open(&repo.workdir().unwrap().join(".gitmodules"))?;
+        assert_eq!(gitmodules.get_string("submodule.dep.path")?, "vendor/dep");
+        assert_eq!(
+            gitmodules.get_string("submodule.dep.url")?,
+            dep.dir.as_ref().to_string_lossy()
+        );
+        let index = repo.index()?;
+        let entry = index.get_path(Path::new("vendor/dep"), 0).unwrap();
+        assert_eq!(entry.mode, 0o160000);
+        assert_eq!(entry.id, commit);
+        assert!(index.get_path(Path::new(".gitmodules"), 0).is_some());
+
+        let bare = TempRepository::new_bare()?;
+        init(&bare, false, None)?;
+        let err = crate::export_submodules(&bare, "vendor", false)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("working directory"), "{err}");
+        Ok(())
+    }
+
+    #[test]
+    fn custom_messages() -> Result<(), anyhow::Error> {
+        let repo = init_clean()?;
+        let dep = demo_repo_with_one_commit()?;
+        crate::add(
+            &repo,
+            "dep",
+            &dep.dir.as_ref().to_string_lossy(),
+            &AddOptions {
+                message: Some("TICKET-1: add dep".to_string()),
+                ..add_options()
+            },
+            &mut NoProgress,
+        )?;
+        {
+            let (branch, _config) = ensure_initialized(&repo)?;
+            assert_eq!(
+                branch.get().peel_to_commit()?.summary(),
+                Some("TICKET-1: add dep")
+            );
+        }
+
+        let mut repo = repo;
+        repo.depends_on("dep", dep);
+        let repo = repo_with_changed_dependency("dep", repo)?;
+        sync(
+            &repo,
+            &[],
+            &SyncOptions {
+                message: Some("TICKET-2: sync\n\nRefs: TICKET-2\n".to_string()),
+                ..sync_options()
+            },
+            &mut NoProgress,
+        )?;
+        let (branch, _config) = ensure_initialized(&repo)?;
+        let commit = branch.get().peel_to_commit()?;
+        let message = commit.message().unwrap();
+        // The checksum joins the message's own trailers
+        assert!(message.starts_with("TICKET-2: sync\n\nRefs: TICKET-2\n"));
+        let trailers = git2::message_trailers_strs(message)?;
+        assert_eq!(
+            trailers.iter().map(|(key, _)| key).collect::<Vec<_>>(),
+            vec!["Refs", CONFIG_SHA256_TRAILER]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn add_heads_are_reachable() -> Result<(), anyhow::Error> {
+        let repo = init_clean()?;
+        let dep = demo_repo_with_one_commit()?;
+        let head = dep.head()?.peel_to_commit()?;
+        let tree = head.tree()?;
+        let sig = dep.signature()?;
+        let branch = dep.commit(
+            Some("refs/heads/branch"),
+            &sig,
+            &sig,
+            "branch",
+            &tree,
+            &[&head],
+        )?;
+        let branch = dep.find_commit(branch)?;
+        dep.tag("annotated", branch.as_object(), &sig, "annotated", false)?;
+        dep.tag_lightweight("lightweight", head.as_object(), false)?;
+        crate::add(
+            &repo,
+            "dep",
+            &dep.dir.as_ref().to_string_lossy(),
+            &add_options(),
+            &mut NoProgress,
+        )?;
+
+        let (branch, config) = ensure_initialized(&repo)?;
+        let tip = branch.into_reference().peel_to_commit()?.id();
+        let heads = &config.dependency("dep")?.heads;
+        assert_eq!(heads.len(), 5);
+        for (name, head) in heads {
+            let commit = repo.find_commit(git2::Oid::from_str(head.target())?)?;
+            assert!(repo.graph_descendant_of(tip, commit.id())?, "{name}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn ssh_signed_commits() -> Result<(), anyhow::Error> {
+        let keys = tempdir()?;
+        let Some(key) = sign::generate_ssh_key(keys.path()) else {
+            eprintln!("ssh-keygen is not available, skipping");
+            return Ok(());
+        };
+        let repo = init_clean()?;
+        let tip = repo.revparse_single("paravendor")?.id();
+        assert!(repo.extract_signature(&tip, None).is_err());
+
+        let mut config = repo.config()?;
+        config.set_str("gpg.format", "ssh")?;
+        config.set_str("user.signingKey", &key.to_string_lossy())?;
+        let dep = demo_repo_with_one_commit()?;
+        let commit = crate::add(
+            &repo,
+            "dep",
+            &dep.dir.as_ref().to_string_lossy(),
+            &AddOptions {
+                sign: true,
+                ..add_options()
+            },
+            &mut NoProgress,
+        )?;
+        assert_eq!(repo.revparse_single("paravendor")?.id(), commit);
+        let (signature, data) = repo.extract_signature(&commit, None)?;
+        let signature = signature.as_str().unwrap();
+        assert!(signature.starts_with("-----BEGIN SSH SIGNATURE-----"));
+
+        // The signature is valid
+        let signature_file = keys.path().join("signature");
+        std::fs::write(&signature_file, signature)?;
+        let mut check = std::process::Command::new("ssh-keygen")
+            .args(["-Y", "check-novalidate", "-n", "git", "-s"])
+            .arg(&signature_file)
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::null())
+            .spawn()?;
+        std::io::Write::write_all(&mut check.stdin.take().unwrap(), &data)?;
+        assert!(check.wait()?.success());
+
+        // commit.gpgSign signs without asking
+        config.set_bool("commit.gpgSign", true)?;
+        let commit = crate::remove(&repo, "dep")?;
+        assert!(repo.extract_signature(&commit, None).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn add_with_description() -> Result<(), anyhow::Error> {
+        let repo = init_clean()?;
+        let dep = demo_repo_with_one_commit()?;
+        crate::add(
+            &repo,
+            "dep",
+            &dep.dir.as_ref().to_string_lossy(),
+            &AddOptions {
+                description: Some("Needed for tests".to_string()),
+                ..add_options()
+            },
+            &mut NoProgress,
+        )?;
+        let config = Config::from_commit(
+            &repo,
+            &repo.revparse_single("paravendor")?.peel_to_commit()?,
+        )?;
+        assert_eq!(
+            config.dependency("dep")?.description.as_deref(),
+            Some("Needed for tests")
+        );
+        let blob = repo.revparse_single("paravendor:config")?;
+        let source = std::str::from_utf8(blob.as_blob().unwrap().content())?;
+        assert!(source.contains("description = \"Needed for tests\""));
+
+        // It's omitted when not set
+        let repo = add()?;
+        let blob = repo.revparse_single("paravendor:config")?;
+        let source = std::str::from_utf8(blob.as_blob().unwrap().content())?;
+        assert!(!source.contains("description"));
+        Ok(())
+    }
+
+    #[test]
+    fn add_from_mirror() -> Result<(), anyhow::Error> {
+        let repo = init_clean()?;
+        let dep = demo_repo_with_one_commit()?;
+        let missing = tempdir()?;
+        let primary = missing.path().join("missing").to_string_lossy().to_string();
+        let mirror = format!("file://{}", dep.dir.as_ref().to_string_lossy());
+        crate::add(
+            &repo,
+            "dep",
+            &primary,
+            &AddOptions {
+                mirrors: vec!["/nonexistent/paravendor/mirror".to_string(), mirror.clone()],
+                ..add_options()
+            },
+            &mut NoProgress,
+        )?;
+        let (_, config) = ensure_initialized(&repo)?;
+        let dependency = config.dependency("dep")?;
+        assert_eq!(dependency.url, primary);
+        assert_eq!(
+            dependency.mirrors,
+            vec!["/nonexistent/paravendor/mirror".to_string(), mirror.clone()]
+        );
+        assert_eq!(dependency.last_source.as_ref(), dependency.mirrors.get(1));
+        let commit = dep.head()?.peel_to_commit()?.id().to_string();
+        assert_eq!(dependency.heads["HEAD"].commit, commit);
+
+        // Syncing falls back to the mirror, too
+        let report = sync(&repo, &[], &sync_options(), &mut NoProgress)?;
+        assert!(report.failed.is_empty());
+
+        // Without mirrors, the primary URL's error is returned
+        assert!(crate::add(&repo, "other", &primary, &add_options(), &mut NoProgress).is_err());
+
+        // Once the primary URL works again, it's used
+        Repository::clone(&mirror, &primary)?;
+        sync(&repo, &[], &sync_options(), &mut NoProgress)?;
+        let (_, config) = ensure_initialized(&repo)?;
+        assert_eq!(config.dependency("dep")?.last_source, Some(primary.clone()));
+        Ok(())
+    }
+
+    #[test]
+    fn sync_failover() -> Result<(), anyhow::Error> {
+        let repo = init_clean()?;
+        let dep = demo_repo_with_one_commit()?;
+        let dir = tempdir()?;
+        // Exact copies of the dependency, advertising the same heads
+        let copy = |name: &str| -> Result<String, anyhow::Error> {
+            let path = dir.path().join(name);
+            Repository::init_bare(&path)?
+                .remote_anonymous(&dep.dir.as_ref().to_string_lossy())?
+                .fetch(&["+refs/*:refs/*"], None, None)?;
+            Ok(path.to_string_lossy().to_string())
+        };
+        let primary = copy("primary")?;
+        let mirror = copy("mirror")?;
+        crate::add(
+            &repo,
+            "dep",
+            &primary,
+            &AddOptions {
+                mirrors: vec![mirror.clone()],
+                ..add_options()
+            },
+            &mut NoProgress,
+        )?;
+        assert_eq!(list(&repo)?["dep"].last_source, Some(primary.clone()));
+
+        // Failing over is recorded, even though the mirror has the same heads
+        std::fs::remove_dir_all(&primary)?;
+        let report = sync(&repo, &[], &sync_options(), &mut NoProgress)?;
+        assert!(report.changed.is_empty());
+        let message = repo
+            .find_commit(report.commit.unwrap())?
+            .message()
+            .map(str::to_string);
+        assert!(message
+            .unwrap()
+            .contains(&format!("dep was fetched from {mirror}")));
+        assert_eq!(list(&repo)?["dep"].last_source, Some(mirror));
+        Ok(())
+    }
+
+    #[test]
+    fn health_check() -> Result<(), anyhow::Error> {
+        let repo = init_clean()?;
+        let dep = demo_repo_with_one_commit()?;
+        let url = dep.dir.as_ref().to_string_lossy().to_string();
+        crate::add(
+            &repo,
+            "dep",
+            &url,
+            &AddOptions {
+                mirrors: vec!["/nonexistent/paravendor/mirror".to_string()],
+                ..add_options()
+            },
+            &mut NoProgress,
+        )?;
+        let checks = health(&repo, &fetch_options())?;
+        assert_eq!(checks.len(), 2);
+        assert_eq!(
+            (checks[0].url.as_str(), checks[0].mirror),
+            (url.as_str(), false)
+        );
+        assert!(checks[0].reachable);
+        assert!(checks[0].error.is_none());
+        assert!(checks[1].mirror);
+        assert!(!checks[1].reachable);
+        assert!(checks[1].error.is_some());
+
+        assert!(health(
+            &repo,
+            &FetchOptions {
+                offline: true,
+                ..fetch_options()
+            }
+        )
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn update_head_to_commit() -> Result<(), anyhow::Error> {
+        let repo = add()?;
+        let sig = git2::Signature::new("John Doe", "john@doe.com", &git2::Time::new(0, 0))?;
+        let tree = repo.find_tree(repo.treebuilder(None)?.write()?)?;
+        let commit = repo.commit(None, &sig, &sig, "recovered", &tree, &[])?;
+
+        let updated = update_head(&repo, "dep", "refs/heads/master", &commit.to_string())?;
+        let tip = repo.find_commit(updated.unwrap())?;
+        assert!(tip.parent_ids().any(|parent| parent == commit));
+        let (_, config) = ensure_initialized(&repo)?;
+        let dependency = config.dependency("dep")?;
+        assert_eq!(
+            dependency.heads["refs/heads/master"].commit,
+            commit.to_string()
+        );
+        // Other refs are left alone
+        assert_ne!(dependency.heads["HEAD"].commit, commit.to_string());
+        assert!(crate::verify(&repo, true)?.is_empty());
+
+        // Nothing changes the second time
+        assert_eq!(
+            update_head(&repo, "dep", "refs/heads/master", &commit.to_string())?,
+            None
+        );
+        assert!(update_head(&repo, "dep", "master", &commit.to_string()).is_err());
+        assert!(update_head(&repo, "dep", "refs/heads/master", &"1".repeat(40)).is_err());
+        assert!(update_head(&repo, "nope", "refs/heads/master", &commit.to_string()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn add_no_commit() -> Result<(), anyhow::Error> {
+        let mut repo = init_clean()?;
+        let (branch, _config) = ensure_initialized(&repo)?;
+        let original_tip = branch.into_reference().peel_to_commit()?.id();
+
+        for name in ["dep1", "dep2"] {
+            let dep = demo_repo_with_one_commit()?;
+            crate::add(
+                &repo,
+                name,
+                &dep.dir.as_ref().to_string_lossy(),
+                &AddOptions {
+                    no_commit: true,
+                    ..add_options()
+                },
+                &mut NoProgress,
+            )?;
+            repo.depends_on(name, dep);
+        }
+
+        // Nothing has been committed yet
+        {
+            let (branch, config) = ensure_initialized(&repo)?;
+            assert_eq!(branch.into_reference().peel_to_commit()?.id(), original_tip);
+            assert!(config.dependencies.is_empty());
+            let (_staged, staged_config) = staged(&repo)?.unwrap();
+            assert_eq!(staged_config.dependencies.len(), 2);
+        }
+
+        // Staged dependencies can't be staged again
+        assert!(crate::add(
+            &repo,
+            "dep1",
+            "/nonexistent",
+            &AddOptions {
+                no_commit: true,
+                ..add_options()
+            },
+            &mut NoProgress
+        )
+        .is_err());
+
+        assert_eq!(commit_staged(&repo, None, false)?, vec!["dep1", "dep2"]);
+
+        let (branch, config) = ensure_initialized(&repo)?;
+        let commit = branch.into_reference().peel_to_commit()?;
+        assert_eq!(commit.summary(), Some("Add dep1, dep2"));
+        assert_eq!(commit.parent_id(0)?, original_tip);
+        for name in ["dep1", "dep2"] {
+            assert!(config.dependencies.contains_key(name));
+            let dep_commit = repo.get_dependency(name).unwrap().head()?.target().unwrap();
+            assert!(commit.parent_ids().any(|p| p == dep_commit));
+        }
+        assert!(staged(&repo)?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn sync_keep_going() -> Result<(), anyhow::Error> {
+        for keep_going in [false, true] {
+            let repo = add_dependency_to_repo(add()?, "broken")?;
+            let original_commit = {
+                let (branch, _config) = ensure_initialized(&repo)?;
+                branch.into_reference().peel_to_commit()?.id()
+            };
+            let mut repo = repo_with_changed_dependency("dep", repo)?;
+            // Removes the dependency repository
+            repo.dependencies.remove("broken");
+
+            let result = sync(
+                &repo,
+                &[],
+                &SyncOptions {
+                    keep_going,
+                    ..sync_options()
+                },
+                &mut NoProgress,
+            );
+
+            let (branch, config) = ensure_initialized(&repo)?;
+            let commit = branch.into_reference().peel_to_commit()?;
+            if keep_going {
+                let report = result?;
+                assert_eq!(report.changed, vec!["dep"]);
+                assert_eq!(report.failed.len(), 1);
+                assert_eq!(report.failed[0].0, "broken");
+                assert!(report.failed[0].1.to_string().contains("does not exist"));
+                assert_eq!(report.commit, Some(commit.id()));
+                assert_eq!(commit.summary(), Some("Sync: dep"));
+                assert_eq!(
+                    config.dependencies["dep"].heads["refs/heads/master"].commit,
+                    repo.get_dependency("dep")
+                        .unwrap()
+                        .head()?
+                        .target()
+                        .unwrap()
+                        .to_string()
+                );
+            } else {
+                let err = format!("{:#}", result.err().unwrap());
+                assert!(err.starts_with("failed to sync broken: invalid URL"));
+                assert_eq!(commit.id(), original_commit);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn lock_prevents_concurrent_mutations() -> Result<(), anyhow::Error> {
+        let repo = add()?;
+        let lock = lock::Lock::acquire(&repo)?;
+
+        let err = sync(&repo, &[], &sync_options(), &mut NoProgress)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("paravendor.lock"));
+
+        // Read-only commands don't take the lock
+        list(&repo)?;
+
+        drop(lock);
+        sync(&repo, &[], &sync_options(), &mut NoProgress)?;
+        Ok(())
+    }
+
+    #[test]
+    fn config_schema() -> Result<(), anyhow::Error> {
+        let schema = serde_json::to_value(schema())?;
+        assert_eq!(schema["title"], "Config");
+        assert_eq!(
+            schema["required"],
+            serde_json::json!(["dependencies", "version"])
+        );
+        assert_eq!(schema["properties"]["version"]["type"], "string");
+        let dependency = &schema["definitions"]["Dependency"];
+        assert_eq!(dependency["required"], serde_json::json!(["heads", "url"]));
+        assert_eq!(
+            dependency["properties"]["heads"]["additionalProperties"]["$ref"],
+            "#/definitions/Head"
+        );
+        assert_eq!(
+            schema["definitions"]["Head"]["required"],
+            serde_json::json!(["commit"])
+        );
+        Ok(())
+    }
+
+    fn repo_with_changed_dependency(
+        name: &str,
+        mut repo: TempRepository,
+    ) -> Result<TempRepository, anyhow::Error> {
+        {
+            let dep = repo
+                .get_mut_dependency(name)
+                .ok_or_else(|| anyhow::Error::msg(format!("{name} dependency not found")))?;
+
+            let tree = dep.repository.treebuilder(None)?.write()?;
+            let tree = dep.find_tree(tree)?;
+
+            let sig = git2::Signature::new("John Doe", "john@doe.com", &git2::Time::new(0, 0))?;
+            // Prepare a commit
+            let _commit = dep.commit(
+                Some("refs/heads/master"),
+                &sig,
+                &sig,
+                "update",
+                &tree,
+                &[&dep.head()?.peel_to_commit()?],
+            )?;
+        }
+        Ok(repo)
+    }
+
+    #[test]
+    fn sync_deleted_upstream_ref() -> Result<(), anyhow::Error> {
+        let repo = add()?;
+        let feature = {
+            let dep = repo.get_dependency("dep").unwrap();
+            let tree = dep.find_tree(dep.treebuilder(None)?.write()?)?;
+            let sig = git2::Signature::new("John Doe", "john@doe.com", &git2::Time::new(0, 0))?;
+            dep.commit(
+                Some("refs/heads/feature"),
+                &sig,
+                &sig,
+                "feature",
+                &tree,
+                &[&dep.head()?.peel_to_commit()?],
+            )?
+        };
+        sync(&repo, &[], &sync_options(), &mut NoProgress)?;
+        let (_branch, config) = ensure_initialized(&repo)?;
+        assert!(config
+            .dependency("dep")?
+            .heads
+            .contains_key("refs/heads/feature"));
+
+        repo.get_dependency("dep")
+            .unwrap()
+            .find_reference("refs/heads/feature")?
+            .delete()?;
+        let report = sync(&repo, &[], &sync_options(), &mut NoProgress)?;
+        assert_eq!(
+            report.deleted,
+            vec![("dep".to_string(), "refs/heads/feature".to_string())]
+        );
+        assert_eq!(report.changed, vec!["dep".to_string()]);
+        assert_eq!(
+            report.refs["dep"]["refs/heads/feature"],
+            RefChange {
+                old: Some(feature.to_string()),
+                new: None,
+            }
+        );
+
+        let (branch, config) = ensure_initialized(&repo)?;
+        assert!(!config
+            .dependency("dep")?
+            .heads
+            .contains_key("refs/heads/feature"));
+        let commit = branch.into_reference().peel_to_commit()?;
+        assert_eq!(report.commit, Some(commit.id()));
+        assert!(!commit.parent_ids().any(|id| id == feature));
+        Ok(())
+    }
+
+    #[test]
+    fn verify() -> Result<(), anyhow::Error> {
+        let repo = init_clean()?;
+        // Loose objects, so that they can be removed one by one
+        let blob = repo.blob(b"content")?;
+        let mut tree = repo.treebuilder(None)?;
+        tree.insert("file", blob, 0o100644)?;
+        let tree = repo.find_tree(tree.write()?)?;
+        let sig = repo.signature()?;
+        let commit = repo.commit(None, &sig, &sig, "dep", &tree, &[])?;
+        let dep = demo_repo_with_one_commit()?;
+        let options = AddOptions {
+            no_fetch: true,
+            heads: vec![("refs/heads/master".to_string(), commit.to_string())],
+            ..add_options()
+        };
+        let url = dep.dir.as_ref().to_string_lossy().to_string();
+        crate::add(&repo, "dep", &url, &options, &mut NoProgress)?;
+        assert!(crate::verify(&repo, true)?.is_empty());
+
+        let remove = |oid: git2::Oid| {
+            let hex = oid.to_string();
+            std::fs::remove_file(repo.path().join("objects").join(&hex[..2]).join(&hex[2..]))
+        };
+        remove(blob)?;
+        assert!(crate::verify(&repo, false)?.is_empty());
+        let missing = crate::verify(&repo, true)?;
+        assert_eq!(
+            missing["dep"],
+            MissingObject {
+                head: "refs/heads/master".to_string(),
+                id: blob,
+                kind: ObjectType::Blob,
+            }
+        );
+
+        remove(commit)?;
+        assert_eq!(crate::verify(&repo, false)?["dep"].id, commit);
+        Ok(())
+    }
+
+    #[test]
+    fn repair() -> Result<(), anyhow::Error> {
+        let repo = init_clean()?;
+        let dep = demo_repo_with_one_commit()?;
+        let commit = {
+            let blob = dep.blob(b"content")?;
+            let mut tree = dep.treebuilder(None)?;
+            tree.insert("file", blob, 0o100644)?;
+            let tree = dep.find_tree(tree.write()?)?;
+            let parent = dep.head()?.peel_to_commit()?;
+            let sig = dep.signature()?;
+            dep.commit(Some("HEAD"), &sig, &sig, "file", &tree, &[&parent])?
+        };
+        // Copied as loose objects, so that they can be removed one by one
+        objects::copy_missing(&dep, &repo.odb()?, commit)?;
+        let options = AddOptions {
+            no_fetch: true,
+            heads: vec![("refs/heads/master".to_string(), commit.to_string())],
+            ..add_options()
+        };
+        let url = dep.dir.as_ref().to_string_lossy().to_string();
+        crate::add(&repo, "dep", &url, &options, &mut NoProgress)?;
+
+        let report = crate::repair(&repo, &fetch_options(), &mut NoProgress)?;
+        assert!(report.repaired.is_empty());
+
+        let tree = repo.find_commit(commit)?.tree()?;
+        let blob = tree.get_name("file").unwrap().id();
+        let hex = blob.to_string();
+        std::fs::remove_file(repo.path().join("objects").join(&hex[..2]).join(&hex[2..]))?;
+        assert!(crate::verify(&repo, true)?.contains_key("dep"));
+
+        let report = crate::repair(&repo, &fetch_options(), &mut NoProgress)?;
+        assert_eq!(report.repaired, vec!["dep".to_string()]);
+        assert!(report.unrepaired.is_empty());
+        // The head was already a parent of the paravendor branch
+        assert_eq!(report.commit, None);
+        assert!(crate::verify(&repo, true)?.is_empty());
+        assert!(!repo.path().join("paravendor-repair").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn cat_config_verbatim() -> Result<(), anyhow::Error> {
+        let repo = add()?;
+        let commit = ensure_initialized(&repo)?.0.get().peel_to_commit()?;
+        let source = "# kept as is\nversion = \"1.2\"\n\n[dependencies]\n";
+        let blob = repo.blob(source.as_bytes())?;
+        let mut tree = TreeUpdateBuilder::new();
+        tree.upsert("config", blob, FileMode::Blob);
+        let tree = repo.find_tree(tree.create_updated(&repo, &commit.tree()?)?)?;
+        let sig = repo.signature()?;
+        repo.commit(
+            Some("refs/heads/paravendor"),
+            &sig,
+            &sig,
+            "Edit",
+            &tree,
+            &[&commit],
+        )?;
+
+        assert_eq!(cat_config(&repo, "paravendor")?, source.as_bytes());
+        let previous = cat_config(&repo, "paravendor~1")?;
+        assert!(std::str::from_utf8(&previous)?.contains("[dependencies.dep]"));
+        Ok(())
+    }
+
+    #[test]
+    fn edit_config() -> Result<(), anyhow::Error> {
+        let repo = add()?;
+        let source = String::from_utf8(cat_config(&repo, "paravendor")?)?;
+        assert_eq!(crate::edit_config(&repo, &source, false)?, None);
+
+        let edited = source.replace(
+            "[dependencies.dep]",
+            "[dependencies.dep]\ndescription = \"edited\"",
+        );
+        let commit = crate::edit_config(&repo, &edited, false)?.unwrap();
+        assert_eq!(repo.find_commit(commit)?.summary(), Some("Edit config"));
+        let (_branch, config) = ensure_initialized(&repo)?;
+        assert_eq!(
+            config.dependency("dep")?.description.as_deref(),
+            Some("edited")
+        );
+
+        assert!(crate::edit_config(&repo, "version = ", false).is_err());
+        let head = config.dependency("dep")?.heads["HEAD"].commit.clone();
+        let missing = edited.replace(&head, &"1".repeat(40));
+        let err = crate::edit_config(&repo, &missing, false).unwrap_err();
+        assert!(err.to_string().contains("not in the repository"));
+        assert_eq!(ensure_initialized(&repo)?.1, config);
+        Ok(())
+    }
+
+    #[test]
+    fn sync_ref() -> Result<(), anyhow::Error> {
+        let repo = add()?;
+        let dep = repo.get_dependency("dep").unwrap();
+        let sig = git2::Signature::new("John Doe", "john@doe.com", &git2::Time::new(0, 0))?;
+        let tree = dep.find_tree(dep.treebuilder(None)?.write()?)?;
+        let master = dep.head()?.peel_to_commit()?;
+        let feature = dep.commit(
+            Some("refs/heads/feature"),
+            &sig,
+            &sig,
+            "feature",
+            &tree,
+            &[&master],
+        )?;
+        sync(&repo, &[], &sync_options(), &mut NoProgress)?;
+
+        // Both branches move upstream
+        let commit = |reference, parent| -> Result<git2::Oid, anyhow::Error> {
+            let parent = dep.find_commit(parent)?;
+            Ok(dep.commit(Some(reference), &sig, &sig, "moved", &tree, &[&parent])?)
+        };
+        let new_master = commit("refs/heads/master", master.id())?;
+        commit("refs/heads/feature", feature)?;
+
+        let (_branch, original_config) = ensure_initialized(&repo)?;
+        let options = SyncOptions {
+            refs: vec!["master".to_string()],
+            ..sync_options()
+        };
+        assert!(sync(&repo, &[], &options, &mut NoProgress).is_err());
+        let report = sync(&repo, &["dep".to_string()], &options, &mut NoProgress)?;
+        assert_eq!(report.changed, vec!["dep".to_string()]);
+
+        let (branch, config) = ensure_initialized(&repo)?;
+        let heads = &config.dependency("dep")?.heads;
+        let original_heads = &original_config.dependency("dep")?.heads;
+        assert_eq!(heads["refs/heads/master"].commit, new_master.to_string());
+        assert_eq!(
+            heads["refs/heads/feature"],
+            original_heads["refs/heads/feature"]
+        );
+        assert_eq!(heads["HEAD"], original_heads["HEAD"]);
+        let commit = branch.into_reference().peel_to_commit()?;
+        assert_eq!(commit.summary(), Some("Sync: dep (refs/heads/master)"));
+        assert!(commit.parent_ids().any(|id| id == new_master));
+
+        let options = SyncOptions {
+            refs: vec!["nonexistent".to_string()],
+            ..sync_options()
+        };
+        assert!(sync(&repo, &["dep".to_string()], &options, &mut NoProgress).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn sync_ref_downloads_only_matching_refs() -> Result<(), anyhow::Error> {
+        // libgit2 sends everything over local transports, so the dependency is served by
+        // `git daemon`
+        if !has_git() {
+            return Ok(());
+        }
+        let dep = demo_repo_with_one_commit()?;
+        let sig = git2::Signature::new("John Doe", "john@doe.com", &git2::Time::new(0, 0))?;
+        let tree = dep.find_tree(dep.treebuilder(None)?.write()?)?;
+        let master = dep.head()?.peel_to_commit()?;
+        dep.branch("feature", &master, false)?;
+
+        let port = std::net::TcpListener::bind("127.0.0.1:0")?
+            .local_addr()?
+            .port();
+        let path = dep.dir.as_ref();
+        // Stopped even if an assertion fails
+        struct Daemon(std::process::Child);
+        impl Drop for Daemon {
+            fn drop(&mut self) {
+                let _ = self.0.kill();
+                let _ = self.0.wait();
+            }
+        }
+        let _daemon = Daemon(
+            std::process::Command::new("git")
+                .arg("daemon")
+                .arg("--export-all")
+                .arg("--listen=127.0.0.1")
+                .arg(format!("--port={port}"))
+                .arg(format!("--base-path={}", path.parent().unwrap().display()))
+                .arg(path)
+                .stdout(std::process::Stdio::null())
+                .stderr(std::process::Stdio::null())
+                .spawn()?,
+        );
+        let started = Instant::now();
+        while std::net::TcpStream::connect(("127.0.0.1", port)).is_err() {
+            assert!(started.elapsed() < Duration::from_secs(10), "git daemon");
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        let url = format!(
+            "git://127.0.0.1:{port}/{}",
+            path.file_name().unwrap().to_string_lossy()
+        );
+        let repo = init_clean()?;
+        crate::add(&repo, "dep", &url, &add_options(), &mut NoProgress)?;
+
+        // Both branches move upstream
+        let commit = |reference: &str| -> Result<git2::Oid, anyhow::Error> {
+            Ok(dep.commit(Some(reference), &sig, &sig, reference, &tree, &[&master])?)
+        };
+        let new_master = commit("refs/heads/master")?;
+        let new_feature = commit("refs/heads/feature")?;
+
+        let options = SyncOptions {
+            refs: vec!["master".to_string()],
+            ..sync_options()
+        };
+        let report = sync(&repo, &["dep".to_string()], &options, &mut NoProgress)?;
+        assert_eq!(report.changed, vec!["dep".to_string()]);
+        let odb = repo.odb()?;
+        assert!(odb.exists(new_master));
+        assert!(!odb.exists(new_feature));
+        Ok(())
+    }
+
+    #[test]
+    fn progress_sink() -> Result<(), anyhow::Error> {
+        #[derive(Default)]
+        struct Recorded {
+            fetched: Vec<String>,
+            transfers: Vec<Transfer>,
+        }
+        impl ProgressSink for Recorded {
+            fn on_fetch(&mut self, url: &str) {
+                self.fetched.push(url.to_string());
+            }
+            fn on_transfer(&mut self, transfer: Transfer) {
+                self.transfers.push(transfer);
+            }
+        }
+
+        let repo = init_clean()?;
+        let dep = demo_repo_with_one_commit()?;
+        let url = dep.dir.as_ref().to_string_lossy().to_string();
+        let mut recorded = Recorded::default();
+        crate::add(&repo, "dep", &url, &add_options(), &mut recorded)?;
+        assert_eq!(recorded.fetched, vec![url]);
+        let last = recorded.transfers.last().unwrap();
+        assert!(last.total_objects > 0);
+        assert_eq!(last.received_objects, last.total_objects);
+        Ok(())
+    }
+
+    #[test]
+    fn add_no_fetch() -> Result<(), anyhow::Error> {
+        let repo = init_clean()?;
+        let dep = demo_repo_with_one_commit()?;
+        let url = dep.dir.as_ref().to_string_lossy().to_string();
+        let options = AddOptions {
+            no_fetch: true,
+            ..add_options()
+        };
+        // Nothing to take the heads from yet
+        assert!(crate::add(&repo, "dep", &url, &options, &mut NoProgress).is_err());
+
+        let mut remote = repo.remote("upstream", &url)?;
+        remote.fetch::<&str>(&[], None, None)?;
+        crate::add(&repo, "dep", &url, &options, &mut NoProgress)?;
+        let head = dep.head()?.peel_to_commit()?.id().to_string();
+        let recorded = &list(&repo)?["dep"].heads;
+        assert_eq!(recorded["refs/heads/master"].commit, head);
+        let tip = ensure_initialized(&repo)?.0.get().peel_to_commit()?;
+        assert!(tip.parent_ids().any(|id| id.to_string() == head));
+
+        let options = AddOptions {
+            heads: vec![("refs/heads/main".to_string(), head.clone())],
+            ..options
+        };
+        crate::add(&repo, "copy", &url, &options, &mut NoProgress)?;
+        let recorded = &list(&repo)?["copy"].heads;
+        assert_eq!(recorded.keys().collect::<Vec<_>>(), vec!["refs/heads/main"]);
+
+        let options = AddOptions {
+            heads: vec![("refs/heads/main".to_string(), "1".repeat(40))],
+            ..options
+        };
+        let err = crate::add(&repo, "missing", &url, &options, &mut NoProgress).unwrap_err();
+        assert!(err.to_string().contains("not in the repository"));
+        Ok(())
+    }
+
+    #[test]
+    fn offline() -> Result<(), anyhow::Error> {
+        let offline = FetchOptions {
+            offline: true,
+            ..fetch_options()
+        };
+        let repo = init_clean()?;
+        let dep = demo_repo_with_one_commit()?;
+        let url = dep.dir.as_ref().to_string_lossy().to_string();
+        let options = AddOptions {
+            fetch: offline.clone(),
+            ..add_options()
+        };
+        let err = crate::add(&repo, "dep", &url, &options, &mut NoProgress).unwrap_err();
+        assert!(err.to_string().contains("offline"));
+        drop(repo);
+
+        // Upstream changes aren't picked up
+        let repo = repo_with_changed_dependency("dep", add()?)?;
+        let tip = ensure_initialized(&repo)?.0.get().peel_to_commit()?.id();
+        let options = SyncOptions {
+            fetch: offline.clone(),
+            ..sync_options()
+        };
+        let report = sync(&repo, &[], &options, &mut NoProgress)?;
+        assert!(report.changed.is_empty());
+        assert_eq!(report.commit, None);
+        assert_eq!(
+            ensure_initialized(&repo)?.0.get().peel_to_commit()?.id(),
+            tip
+        );
+
+        assert!(crate::push(&repo, "origin", &offline).is_err());
+        assert!(crate::pull(&repo, "origin", &offline).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn add_empty_repository() -> Result<(), anyhow::Error> {
+        #[derive(Default)]
+        struct Empty(Vec<String>);
+        impl ProgressSink for Empty {
+            fn on_empty(&mut self, url: &str) {
+                self.0.push(url.to_string());
+            }
+        }
+
+        let repo = init_clean()?;
+        let dir = tempfile::tempdir()?;
+        Repository::init_bare(dir.path())?;
+        let url = dir.path().to_string_lossy().to_string();
+
+        let options = AddOptions {
+            fetch: FetchOptions {
+                strict: true,
+                ..fetch_options()
+            },
+            ..add_options()
+        };
+        let err = crate::add(&repo, "empty", &url, &options, &mut NoProgress).unwrap_err();
+        assert!(err.to_string().contains("has no refs"));
+
+        let mut empty = Empty::default();
+        crate::add(&repo, "empty", &url, &add_options(), &mut empty)?;
+        assert_eq!(empty.0, vec![url]);
+        assert!(list(&repo)?["empty"].heads.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn unchanged_dependency_is_not_downloaded() -> Result<(), anyhow::Error> {
+        let repo = add()?;
+        let dependency = list(&repo)?.remove("dep").unwrap();
+        {
+            let fetched = sync_dependency(
+                &repo,
+                &dependency.url,
+                Some(&dependency.heads),
+                &[],
+                &fetch_options(),
+                &mut NoProgress,
+            )?;
+            assert!(!fetched.downloaded);
+            assert!(fetched.commits.is_empty());
+            assert_eq!(fetched.heads, dependency.heads);
+        }
+
+        let repo = repo_with_changed_dependency("dep", repo)?;
+        let fetched = sync_dependency(
+            &repo,
+            &dependency.url,
+            Some(&dependency.heads),
+            &[],
+            &fetch_options(),
+            &mut NoProgress,
+        )?;
+        assert!(fetched.downloaded);
+        assert_ne!(fetched.heads, dependency.heads);
+        Ok(())
+    }
+
+    #[test]
+    fn sync_dry_run() -> Result<(), anyhow::Error> {
+        let repo = add()?;
+        let original = ensure_initialized(&repo)?
+            .0
+            .into_reference()
+            .peel_to_commit()?
+            .id();
+        let mut repo = repo_with_changed_dependency("dep", repo)?;
+        {
+            // Add a file to the dependency's change
+            let dep = repo.get_mut_dependency("dep").unwrap();
+            let blob = dep.blob(b"hello")?;
+            let mut tree = dep.treebuilder(None)?;
+            tree.insert("hello", blob, 0o100644)?;
+            let tree = dep.find_tree(tree.write()?)?;
+            let sig = dep.signature()?;
+            dep.commit(
+                Some("refs/heads/master"),
+                &sig,
+                &sig,
+                "hello",
+                &tree,
+                &[&dep.head()?.peel_to_commit()?],
+            )?;
+        }
+
+        let expected = ObjectCounts {
+            commits: 2,
+            trees: 1,
+            blobs: 1,
+        };
+        let report = sync(
+            &repo,
+            &[],
+            &SyncOptions {
+                dry_run: true,
+                ..sync_options()
+            },
+            &mut NoProgress,
+        )?;
+        assert_eq!(report.changed, vec!["dep"]);
+        assert_eq!(report.commit, None);
+        assert_eq!(report.objects, expected);
+        let (branch, _config) = ensure_initialized(&repo)?;
+        assert_eq!(branch.into_reference().peel_to_commit()?.id(), original);
+
+        let report = sync(&repo, &[], &sync_options(), &mut NoProgress)?;
+        assert!(report.commit.is_some());
+        assert_eq!(report.objects, expected);
+
+        let report = sync(&repo, &[], &sync_options(), &mut NoProgress)?;
+        assert_eq!(report.objects, ObjectCounts::default());
+        Ok(())
+    }
+
+    #[test]
+    fn remove() -> Result<(), anyhow::Error> {
+        let repo = add()?;
+        let commit = crate::remove(&repo, "dep")?;
+        assert!(list(&repo)?.is_empty());
+        assert_eq!(repo.find_commit(commit)?.summary(), Some("Remove dep"));
+        assert!(crate::remove(&repo, "dep").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn config_get_set() -> Result<(), anyhow::Error> {
+        let repo = add()?;
+        let url = config_get(&repo, "dependencies.dep.url")?;
+        let other = demo_repo_with_one_commit()?;
+        let other_url = other.dir.as_ref().to_string_lossy().to_string();
+        assert_ne!(url.as_str(), Some(other_url.as_str()));
+
+        let commit = config_set(&repo, "dependencies.dep.url", &other_url)?;
+        assert_eq!(
+            config_get(&repo, "dependencies.dep.url")?.as_str(),
+            Some(other_url.as_str())
+        );
+        assert_eq!(
+            repo.find_commit(commit)?.summary(),
+            Some(format!("Set dependencies.dep.url to {other_url}").as_str())
+        );
+        // Optional fields can be set, even though they are omitted while unset
+        config_set(&repo, "dependencies.dep.disabled", "true")?;
+        assert!(list(&repo)?["dep"].disabled);
+        config_set(&repo, "dependencies.dep.description", "For tests")?;
+        assert_eq!(
+            list(&repo)?["dep"].description.as_deref(),
+            Some("For tests")
+        );
+
+        assert!(config_set(&repo, "dependencies.dep.url", "/nonexistent").is_err());
+        assert!(config_set(&repo, "dependencies.dep.disabled", "maybe").is_err());
+        assert!(config_set(&repo, "dependencies.other.url", &other_url).is_err());
+        assert!(config_set(&repo, "dependencies.dep.unknown", "x").is_err());
+        assert!(config_set(&repo, "version", "2.0").is_err());
+        assert!(config_get(&repo, "dependencies.other").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn last_synced() -> Result<(), anyhow::Error> {
+        let now = || {
+            let since_epoch = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap();
+            timestamp::rfc3339(since_epoch.as_secs() as i64)
+        };
+        let before = now();
+        let repo = add_dependency_to_repo(add()?, "other")?;
+        let added = list(&repo)?["dep"].last_synced.clone().unwrap();
+        assert!(before <= added && added <= now());
+
+        // Recorded even if nothing changed, but not by a dry run
+        std::thread::sleep(std::time::Duration::from_secs(1));
+        let dry_run = SyncOptions {
+            dry_run: true,
+            ..sync_options()
+        };
+        assert_eq!(sync(&repo, &[], &dry_run, &mut NoProgress)?.commit, None);
+        let report = sync(&repo, &[], &sync_options(), &mut NoProgress)?;
+        assert!(report.changed.is_empty());
+        let summary = repo
+            .find_commit(report.commit.unwrap())?
+            .summary()
+            .map(str::to_string);
+        assert_eq!(summary.as_deref(), Some("Sync: dep, other (unchanged)"));
+        let synced = list(&repo)?["dep"].last_synced.clone().unwrap();
+        assert!(added < synced);
+
+        // It's recorded for every fetched dependency, changed or not
+        std::thread::sleep(std::time::Duration::from_secs(1));
+        let repo = repo_with_changed_dependency("other", repo)?;
+        let report = sync(&repo, &[], &sync_options(), &mut NoProgress)?;
+        assert_eq!(report.changed, vec!["other"]);
+        let dependencies = list(&repo)?;
+        let resynced = dependencies["dep"].last_synced.clone().unwrap();
+        assert!(synced < resynced);
+        assert_eq!(dependencies["other"].last_synced, Some(resynced));
+        Ok(())
+    }
+
+    #[test]
+    fn size() -> Result<(), anyhow::Error> {
+        let repo = add()?;
+        let report = crate::size(&repo, None)?;
+        // The demo dependency is a single commit with an empty tree
+        let expected = Footprint {
+            objects: ObjectCounts {
+                commits: 1,
+                trees: 1,
+                blobs: 0,
+            },
+            blob_bytes: 0,
+        };
+        assert_eq!(report.dependencies["dep"], expected);
+        assert_eq!(report.total, expected);
+
+        let mut repo = repo_with_changed_dependency("dep", repo)?;
+        {
+            let dep = repo.get_mut_dependency("dep").unwrap();
+            let blob = dep.blob(b"hello")?;
+            let mut tree = dep.treebuilder(None)?;
+            tree.insert("hello", blob, 0o100644)?;
+            let tree = dep.find_tree(tree.write()?)?;
+            let sig = dep.signature()?;
+            dep.commit(
+                Some("refs/heads/master"),
+                &sig,
+                &sig,
+                "hello",
+                &tree,
+                &[&dep.head()?.peel_to_commit()?],
+            )?;
+        }
+        sync(&repo, &[], &sync_options(), &mut NoProgress)?;
+        let report = crate::size(&repo, Some("dep"))?;
+        assert_eq!(report.dependencies.len(), 1);
+        assert_eq!(
+            report.total,
+            Footprint {
+                objects: ObjectCounts {
+                    commits: 3,
+                    trees: 2,
+                    blobs: 1,
+                },
+                blob_bytes: 5,
+            }
+        );
+        assert!(crate::size(&repo, Some("nonexistent")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn stats() -> Result<(), anyhow::Error> {
+        // Both are the same single commit with an empty tree
+        let repo = add_dependency_to_repo(add()?, "other")?;
+        let single = Footprint {
+            objects: ObjectCounts {
+                commits: 1,
+                trees: 1,
+                blobs: 0,
+            },
+            blob_bytes: 0,
+        };
+        let report = crate::stats(&repo)?;
+        assert_eq!(report.dependencies["dep"], single);
+        assert_eq!(report.dependencies["other"], single);
+        assert_eq!(report.unique, single);
+        assert_eq!(report.saved(), single);
+        assert_eq!(report.sum.objects.commits, 2);
+
+        let repo = repo_with_changed_dependency("dep", repo)?;
+        sync(
+            &repo,
+            &["dep".to_string()],
+            &sync_options(),
+            &mut NoProgress,
+        )?;
+        let report = crate::stats(&repo)?;
+        assert_eq!(report.dependencies["dep"].objects.commits, 2);
+        assert_eq!(report.unique.objects.commits, 2);
+        assert_eq!(report.sum.objects.commits, 3);
+        assert_eq!(report.saved().objects.commits, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn objects() -> Result<(), anyhow::Error> {
+        // Both are the same single commit with an empty tree
+        let repo = add_dependency_to_repo(add()?, "other")?;
+        let single = Footprint {
+            objects: ObjectCounts {
+                commits: 1,
+                trees: 1,
+                blobs: 0,
+            },
+            blob_bytes: 0,
+        };
+        let report = crate::objects(&repo, "dep")?;
+        assert_eq!(report.total, single);
+        assert_eq!(report.unique, Footprint::default());
+        assert_eq!(report.shared, single);
+
+        // The new commit reuses the empty tree
+        let repo = repo_with_changed_dependency("dep", repo)?;
+        sync(&repo, &[], &sync_options(), &mut NoProgress)?;
+        let report = crate::objects(&repo, "dep")?;
+        assert_eq!(report.total.objects.commits, 2);
+        assert_eq!(report.unique.objects.commits, 1);
+        assert_eq!(report.unique.objects.trees, 0);
+        assert_eq!(report.shared, single);
+        assert_eq!(crate::objects(&repo, "other")?.unique, Footprint::default());
+        assert!(crate::objects(&repo, "nonexistent").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn repack() -> Result<(), anyhow::Error> {
+        let loose = |repo: &Repository| -> Result<usize, anyhow::Error> {
+            let mut count = 0;
+            for entry in std::fs::read_dir(repo.path().join("objects"))? {
+                let entry = entry?;
+                if entry.file_name().len() == 2 {
+                    count += std::fs::read_dir(entry.path())?.count();
+                }
+            }
+            Ok(count)
+        };
+
+        let repo = add()?;
+        assert!(loose(&repo)? > 0);
+        let report = crate::repack(&repo, true)?;
+        assert_eq!(loose(&repo)?, 0);
+        assert!(report.before > 0 && report.after > 0);
+        assert!(crate::verify(&repo, true)?.is_empty());
+
+        // Objects that aren't paravendor's are left loose
+        let repo = repo_with_changed_dependency("dep", repo)?;
+        let blob = repo.blob(b"unrelated")?;
+        crate::repack(&repo, true)?;
+        assert!(repo.odb()?.exists(blob));
+        assert_eq!(loose(&repo)?, 1);
+
+        crate::repack(&repo, false)?;
+        assert!(crate::verify(&repo, true)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn export_import_pack() -> Result<(), anyhow::Error> {
+        let repo = add()?;
+        let dir = tempdir()?;
+        let path = dir.path().join("paravendor.pack");
+        let exported = export_pack(&repo, &path)?;
+        assert_eq!(exported.tip, repo.revparse_single("paravendor")?.id());
+        // Two paravendor commits, their trees and config blobs, and the dependency's
+        // commit and tree
+        assert_eq!(exported.objects, 8);
+
+        let target = TempRepository::new()?;
+        let tip = exported.tip.to_string();
+        let (objects, moved) = import_pack(&target, &path, None)?;
+        assert_eq!((objects, moved), (8, None));
+        assert!(target.find_commit(exported.tip).is_ok());
+        assert!(ensure_initialized(&target).is_err());
+
+        let (_, moved) = import_pack(&target, &path, Some(&tip))?;
+        assert_eq!(moved, Some(exported.tip));
+        assert_eq!(list(&target)?, list(&repo)?);
+        assert!(crate::verify(&target, true)?.is_empty());
+        // Importing again changes nothing
+        assert_eq!(import_pack(&target, &path, Some(&tip))?.1, None);
+
+        std::fs::write(&path, "not a pack")?;
+        assert!(import_pack(&target, &path, None).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn bundles() -> Result<(), anyhow::Error> {
+        let repo = add()?;
+        let dir = tempdir()?;
+        let tip = repo.revparse_single("paravendor")?.id();
+        // Bundles written with git and without can be read either way
+        for (create_with_git, apply_with_git) in
+            [(true, true), (true, false), (false, true), (false, false)]
+        {
+            if (create_with_git || apply_with_git) && !has_git() {
+                continue;
+            }
+            let path = dir.path().join("paravendor.bundle");
+            assert_eq!(bundle_create_with(&repo, &path, create_with_git)?, tip);
+            assert_eq!(bundle_verify_with(&repo, &path, apply_with_git)?, tip);
+
+            let target = TempRepository::new()?;
+            assert_eq!(
+                bundle_apply_with(&target, &path, apply_with_git)?,
+                Some(tip)
+            );
+            assert_eq!(list(&target)?, list(&repo)?);
+            assert!(crate::verify(&target, true)?.is_empty());
+            assert_eq!(bundle_apply_with(&target, &path, apply_with_git)?, None);
+            assert!(!target.path().join("paravendor-bundle").exists());
+        }
+
+        // A truncated bundle is rejected before anything is applied
+        let path = dir.path().join("paravendor.bundle");
+        bundle_create_with(&repo, &path, false)?;
+        let contents = std::fs::read(&path)?;
+        std::fs::write(&path, &contents[..contents.len() - 10])?;
+        let target = TempRepository::new()?;
+        assert!(bundle_verify_with(&target, &path, false).is_err());
+        assert!(bundle_apply_with(&target, &path, false).is_err());
+        assert!(target.find_branch("paravendor", BranchType::Local).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn config_checksums() -> Result<(), anyhow::Error> {
+        let repo = add()?;
+        let commit = repo.revparse_single("paravendor")?.peel_to_commit()?;
+        let checksum = sha256(&cat_config(&repo, "paravendor")?);
+        assert!(commit
+            .message()
+            .unwrap()
+            .ends_with(&format!("\n\n{CONFIG_SHA256_TRAILER}: {checksum}")));
+        assert!(verify_config(&repo)?.is_empty());
+
+        // A commit with a corrupted trailer
+        let message = commit
+            .message()
+            .unwrap()
+            .replace(&checksum, &"0".repeat(64));
+        let sig = repo.signature()?;
+        let corrupted = repo.commit(
+            Some("refs/heads/paravendor"),
+            &sig,
+            &sig,
+            &message,
+            &commit.tree()?,
+            &[&commit],
+        )?;
+        assert_eq!(verify_config(&repo)?, vec![corrupted]);
+        Ok(())
+    }
+
+    #[test]
+    fn contains() -> Result<(), anyhow::Error> {
+        let repo = add()?;
+        let first = repo
+            .get_dependency("dep")
+            .unwrap()
+            .head()?
+            .peel_to_commit()?
+            .id()
+            .to_string();
+        let heads = crate::contains(&repo, "dep", &first)?;
+        assert!(heads.contains(&"refs/heads/master".to_string()));
+        assert!(heads.contains(&"HEAD".to_string()));
+
+        let repo = repo_with_changed_dependency("dep", repo)?;
+        sync(&repo, &[], &sync_options(), &mut NoProgress)?;
+        assert!(!crate::contains(&repo, "dep", &first[..7])?.is_empty());
+        // The paravendor branch's own commits aren't part of the dependency
+        assert!(crate::contains(&repo, "dep", "paravendor")?.is_empty());
+
+        let err = crate::contains(&repo, "dep", &"1".repeat(40)).unwrap_err();
+        assert!(err.to_string().contains("not present"), "{err}");
+        assert!(crate::contains(&repo, "nonexistent", &first).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn which() -> Result<(), anyhow::Error> {
+        let repo = add_dependency_to_repo(add()?, "other")?;
+        let repo = repo_with_changed_dependency("dep", repo)?;
+        sync(&repo, &[], &sync_options(), &mut NoProgress)?;
+        let changed = repo
+            .get_dependency("dep")
+            .unwrap()
+            .head()?
+            .peel_to_commit()?;
+        let found = crate::which(&repo, &changed.id().to_string())?;
+        assert_eq!(
+            found.into_iter().collect::<Vec<_>>(),
+            vec![(
+                "dep".to_string(),
+                vec!["HEAD".to_string(), "refs/heads/master".to_string()]
+            )]
+        );
+        // Both dependencies start with the same commit
+        let first = changed.parent_id(0)?.to_string();
+        assert_eq!(
+            crate::which(&repo, &first)?.into_keys().collect::<Vec<_>>(),
+            vec!["dep", "other"]
+        );
+        assert!(crate::which(&repo, "paravendor")?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn manifest() -> Result<(), anyhow::Error> {
+        let repo = add()?;
+        let config = list(&repo)?;
+        let manifest = crate::manifest(&repo, false)?;
+        let dep = &manifest.dependencies["dep"];
+        assert_eq!(dep.url, config["dep"].url);
+        assert!(dep.refs.is_none());
+        let exported = toml::to_string_pretty(&manifest)?;
+        assert!(!exported.contains("refs"));
+        assert_eq!(toml::from_str::<Manifest>(&exported)?, manifest);
+
+        let manifest = crate::manifest(&repo, true)?;
+        let refs = manifest.dependencies["dep"].refs.as_ref().unwrap();
+        assert_eq!(
+            refs["refs/heads/master"],
+            config["dep"].heads["refs/heads/master"].commit
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn env_in_url() -> Result<(), anyhow::Error> {
+        let repo = init_clean()?;
+        let dep = demo_repo_with_one_commit()?;
+        std::env::set_var("PARAVENDOR_TEST_DEP", dep.dir.as_ref());
+        crate::add(
+            &repo,
+            "dep",
+            "${PARAVENDOR_TEST_DEP}",
+            &add_options(),
+            &mut NoProgress,
+        )?;
+        // Stored as is
+        assert_eq!(list(&repo)?["dep"].url, "${PARAVENDOR_TEST_DEP}");
+        assert!(!list(&repo)?["dep"].heads.is_empty());
+
+        std::env::remove_var("PARAVENDOR_TEST_DEP");
+        let err = sync(&repo, &[], &sync_options(), &mut NoProgress).unwrap_err();
+        let err = format!("{err:#}");
+        assert!(err.contains("failed to sync dep"), "{err}");
+        assert!(err.contains("PARAVENDOR_TEST_DEP"), "{err}");
+
+        // Offline, the URL isn't needed
+        let options = SyncOptions {
+            fetch: FetchOptions {
+                offline: true,
+                ..fetch_options()
+            },
+            ..sync_options()
+        };
+        assert!(sync(&repo, &[], &options, &mut NoProgress)?
+            .changed
+            .is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn instead_of() -> Result<(), anyhow::Error> {
+        let repo = init_clean()?;
+        let dep = demo_repo_with_one_commit()?;
+        let path = dep.dir.as_ref();
+        repo.config()?.set_str(
+            &format!(
+                "url.{}/.insteadOf",
+                path.parent().unwrap().to_string_lossy()
+            ),
+            "https://paravendor.invalid/",
+        )?;
+        let url = format!(
+            "https://paravendor.invalid/{}",
+            path.file_name().unwrap().to_string_lossy()
+        );
+        crate::add(&repo, "dep", &url, &add_options(), &mut NoProgress)?;
+        let dependency = &list(&repo)?["dep"];
+        assert_eq!(dependency.url, url);
+        assert_eq!(
+            dependency.heads["HEAD"].commit,
+            dep.head()?.peel_to_commit()?.id().to_string()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn readd_without_redundant_parents() -> Result<(), anyhow::Error> {
+        let repo = add()?;
+        crate::remove(&repo, "dep")?;
+        let url = repo
+            .get_dependency("dep")
+            .unwrap()
+            .dir
+            .as_ref()
+            .to_string_lossy()
+            .to_string();
+        let commit = repo.find_commit(crate::add(
+            &repo,
+            "dep",
+            &url,
+            &add_options(),
+            &mut NoProgress,
+        )?)?;
+        assert_eq!(commit.parent_count(), 1);
+        assert!(list(&repo)?.contains_key("dep"));
+        Ok(())
+    }
+
+    #[test]
+    fn sync_disabled() -> Result<(), anyhow::Error> {
+        let repo = add()?;
+        assert!(set_disabled(&repo, "dep", true)?.is_some());
+        assert!(set_disabled(&repo, "dep", true)?.is_none());
+        let config = list(&repo)?;
+        assert!(config["dep"].disabled);
+        let repo = repo_with_changed_dependency("dep", repo)?;
+
+        let report = sync(&repo, &[], &sync_options(), &mut NoProgress)?;
+        assert_eq!(report.skipped, vec!["dep"]);
+        assert!(report.commit.is_none());
+        assert_eq!(list(&repo)?, config);
+
+        // Unless asked for explicitly
+        let report = sync(
+            &repo,
+            &["dep".to_string()],
+            &sync_options(),
+            &mut NoProgress,
+        )?;
+        assert!(report.skipped.is_empty());
+        assert_eq!(report.changed, vec!["dep"]);
+
+        set_disabled(&repo, "dep", false)?;
+        let (_branch, config) = ensure_initialized(&repo)?;
+        assert!(!config.dependency("dep")?.disabled);
+        Ok(())
+    }
+
+    #[test]
+    fn sync_frozen() -> Result<(), anyhow::Error> {
+        let repo = add()?;
+        assert!(set_frozen(&repo, "dep", true)?.is_some());
+        assert!(set_frozen(&repo, "dep", true)?.is_none());
+        let config = list(&repo)?;
+        assert!(config["dep"].frozen);
+        let repo = repo_with_changed_dependency("dep", repo)?;
+
+        let report = sync(&repo, &[], &sync_options(), &mut NoProgress)?;
+        assert_eq!(report.frozen, vec!["dep"]);
+        assert!(report.commit.is_none());
+        assert_eq!(list(&repo)?, config);
+
+        // Even if asked for explicitly
+        let report = sync(
+            &repo,
+            &["dep".to_string()],
+            &sync_options(),
+            &mut NoProgress,
+        )?;
+        assert_eq!(report.frozen, vec!["dep"]);
+        assert!(report.changed.is_empty());
+        assert_eq!(list(&repo)?, config);
+
+        set_frozen(&repo, "dep", false)?;
+        let report = sync(&repo, &[], &sync_options(), &mut NoProgress)?;
+        assert!(report.frozen.is_empty());
+        assert_eq!(report.changed, vec!["dep"]);
+        Ok(())
+    }
+
+    #[test]
+    fn sync_unknown() -> Result<(), anyhow::Error> {
+        let repo = add()?;
+        let names = ["dep".to_string(), "nope".to_string()];
+        let e = sync(&repo, &names, &sync_options(), &mut NoProgress).unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<Error>(),
+            Some(Error::DependencyNotFound(name)) if name == "nope"
+        ));
+
+        let repo = repo_with_changed_dependency("dep", repo)?;
+        let options = SyncOptions {
+            keep_going: true,
+            ..sync_options()
+        };
+        let report = sync(&repo, &names, &options, &mut NoProgress)?;
+        assert_eq!(report.changed, vec!["dep"]);
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].0, "nope");
+        Ok(())
+    }
+
+    #[test]
+    fn sync_interrupted() -> Result<(), anyhow::Error> {
+        let repo = add_dependency_to_repo(init_clean()?, "a")?;
+        let repo = add_dependency_to_repo(repo, "b")?;
+        let repo = repo_with_changed_dependency("a", repo)?;
+        let changed = repo
+            .get_dependency("a")
+            .unwrap()
+            .refname_to_id("refs/heads/master")?;
+        std::fs::remove_dir_all(repo.get_dependency("b").unwrap().dir.path())?;
+        assert!(interrupted_sync(&repo)?.is_none());
+
+        // Fetching `a` succeeds, but `b` fails before anything is committed
+        let options = SyncOptions {
+            jobs: 1,
+            ..sync_options()
+        };
+        assert!(sync(&repo, &[], &options, &mut NoProgress).is_err());
+        let interrupted = interrupted_sync(&repo)?.unwrap();
+        assert_eq!(interrupted.dependencies, vec!["a"]);
+        let tip = repo.refname_to_id(IN_PROGRESS_REF)?;
+        assert!(repo.graph_descendant_of(tip, changed)?);
+
+        // Syncing again finishes it
+        let report = sync(&repo, &["a".to_string()], &options, &mut NoProgress)?;
+        assert_eq!(report.changed, vec!["a"]);
+        assert!(interrupted_sync(&repo)?.is_none());
+
+        // Or it can be discarded
+        let repo = repo_with_changed_dependency("a", repo)?;
+        assert!(sync(&repo, &[], &options, &mut NoProgress).is_err());
+        assert!(discard_interrupted_sync(&repo)?);
+        assert!(interrupted_sync(&repo)?.is_none());
+        assert!(!discard_interrupted_sync(&repo)?);
+        Ok(())
+    }
+
+    #[test]
+    fn alternate_object_store() -> Result<(), anyhow::Error> {
+        // A repository with the dependency already in its object store
+        let shared = add()?;
+        let store = objects_dir(&shared);
+
+        let repo = init_clean()?;
+        assert!(add_alternate(&repo, &store)?);
+        assert!(!add_alternate(&repo, &store)?);
+        let alternates = std::fs::read_to_string(objects_dir(&repo).join("info/alternates"))?;
+        assert_eq!(alternates.lines().count(), 1);
+        assert!(add_alternate(&repo, &objects_dir(&repo).join("info")).is_err());
+
+        // Nothing has to be fetched into the repository itself
+        let packs = || -> Result<usize, anyhow::Error> {
+            Ok(std::fs::read_dir(objects_dir(&repo).join("pack"))?.count())
+        };
+        let before = packs()?;
+        let dep = shared.get_dependency("dep").unwrap();
+        crate::add(
+            &repo,
+            "dep",
+            &dep.dir.as_ref().to_string_lossy(),
+            &add_options(),
+            &mut NoProgress,
+        )?;
+        assert_eq!(packs()?, before);
+        assert!(crate::verify(&repo, true)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn prune_history() -> Result<(), anyhow::Error> {
+        let repo = add_dependency_to_repo(init_clean()?, "a")?;
+        let repo = add_dependency_to_repo(repo, "b")?;
+        let repo = repo_with_changed_dependency("a", repo)?;
+        sync(&repo, &[], &sync_options(), &mut NoProgress)?;
+        let repo = repo_with_changed_dependency("b", repo)?;
+        sync(&repo, &[], &sync_options(), &mut NoProgress)?;
+        let tip = repo.refname_to_id("refs/heads/paravendor")?;
+        let (_, config) = ensure_initialized(&repo)?;
+
+        // Nothing changes with a dry run, or when there's too little to squash
+        let options = PruneOptions {
+            keep: Some(2),
+            dry_run: true,
+            ..Default::default()
+        };
+        let report = crate::prune(&repo, &options)?;
+        assert_eq!((report.kept, report.squashed, report.commit), (2, 3, None));
+        for keep in [None, Some(4), Some(10)] {
+            let report = crate::prune(
+                &repo,
+                &PruneOptions {
+                    keep,
+                    dry_run: false,
+                    ..Default::default()
+                },
+            )?;
+            assert_eq!((report.kept, report.squashed, report.commit), (5, 0, None));
+        }
+        let options = PruneOptions {
+            keep: Some(0),
+            dry_run: true,
+            ..Default::default()
+        };
+        assert!(crate::prune(&repo, &options).is_err());
+        assert_eq!(repo.refname_to_id("refs/heads/paravendor")?, tip);
+
+        let report = crate::prune(
+            &repo,
+            &PruneOptions {
+                keep: Some(2),
+                dry_run: false,
+                ..Default::default()
+            },
+        )?;
+        assert_eq!((report.kept, report.squashed), (2, 3));
+        let new_tip = repo.find_commit(repo.refname_to_id("refs/heads/paravendor")?)?;
+        assert_eq!(Some(new_tip.id()), report.commit);
+        assert_eq!(new_tip.tree_id(), repo.find_commit(tip)?.tree_id());
+        assert_eq!(ensure_initialized(&repo)?.1, config);
+        assert!(verify_config(&repo)?.is_empty());
+        assert!(note(&repo, &new_tip.id().to_string())?.is_some());
+
+        let mut first_parents = 0;
+        let mut top = Some(new_tip.clone());
+        while let Some(commit) = top {
+            first_parents += 1;
+            top = commit.parents().next();
+        }
+        assert_eq!(first_parents, 3);
+        // Heads are still reachable, including the ones added before the kept commits
+        for commit in head_commits(&repo, &config)?.into_values().flatten() {
+            assert!(repo.graph_descendant_of(new_tip.id(), commit)?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn prune_older_than() -> Result<(), anyhow::Error> {
+        let repo = add_dependency_to_repo(init_clean()?, "a")?;
+        let repo = add_dependency_to_repo(repo, "b")?;
+        // Commit times are in seconds
+        std::thread::sleep(Duration::from_secs(2));
+        let repo = repo_with_changed_dependency("a", repo)?;
+        sync(&repo, &[], &sync_options(), &mut NoProgress)?;
+
+        let options = PruneOptions {
+            older_than: Some(Duration::from_secs(1)),
+            dry_run: true,
+            ..Default::default()
+        };
+        let report = crate::prune(&repo, &options)?;
+        assert_eq!((report.kept, report.squashed), (1, 3));
+        // Both have to agree on squashing a commit
+        let options = PruneOptions {
+            keep: Some(2),
+            ..options
+        };
+        let report = crate::prune(&repo, &options)?;
+        assert_eq!((report.kept, report.squashed), (2, 2));
+        let options = PruneOptions {
+            older_than: Some(Duration::from_secs(3600)),
+            ..options
+        };
+        assert_eq!(crate::prune(&repo, &options)?.squashed, 0);
+
+        let options = PruneOptions {
+            older_than: Some(Duration::from_secs(1)),
+            ..Default::default()
+        };
+        let report = crate::prune(&repo, &options)?;
+        assert_eq!((report.kept, report.squashed), (1, 3));
+        let tip = repo.find_commit(report.commit.unwrap())?;
+        let (_, config) = ensure_initialized(&repo)?;
+        for commit in head_commits(&repo, &config)?.into_values().flatten() {
+            assert!(repo.graph_descendant_of(tip.id(), commit)?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn clean_removed() -> Result<(), anyhow::Error> {
+        let repo = add_dependency_to_repo(init_clean()?, "a")?;
+        let dep = demo_repo_with_one_commit()?;
+        let first = dep.head()?.peel_to_commit()?;
+        let sig = git2::Signature::new("John Doe", "john@doe.com", &git2::Time::new(0, 0))?;
+        let second = dep.commit(
+            Some("refs/heads/master"),
+            &sig,
+            &sig,
+            "update",
+            &first.tree()?,
+            &[&first],
+        )?;
+        let url = dep.dir.as_ref().to_string_lossy().to_string();
+        crate::add(&repo, "b", &url, &add_options(), &mut NoProgress)?;
+        // `c` only refers to the first commit, which is reachable through `b`
+        let options = AddOptions {
+            no_fetch: true,
+            heads: vec![("refs/heads/master".to_string(), first.id().to_string())],
+            ..add_options()
+        };
+        crate::add(&repo, "c", &url, &options, &mut NoProgress)?;
+        crate::remove(&repo, "b")?;
+        let tip = repo.refname_to_id("refs/heads/paravendor")?;
+        let (_, config) = ensure_initialized(&repo)?;
+
+        let report = crate::clean(&repo, true)?;
+        assert_eq!(report.dependencies, vec!["b"]);
+        assert_eq!(report.commits, vec![second]);
+        assert_eq!(report.objects.commits, 1);
+        assert_eq!(report.commit, None);
+        assert_eq!(repo.refname_to_id("refs/heads/paravendor")?, tip);
+
+        let report = crate::clean(&repo, false)?;
+        let new_tip = repo.refname_to_id("refs/heads/paravendor")?;
+        assert_eq!(report.commit, Some(new_tip));
+        assert!(!repo.graph_descendant_of(new_tip, second)?);
+        for commit in head_commits(&repo, &config)?.into_values().flatten() {
+            assert!(repo.graph_descendant_of(new_tip, commit)?);
+        }
+        assert_eq!(ensure_initialized(&repo)?.1, config);
+        assert!(verify_config(&repo)?.is_empty());
+
+        // Nothing is left to clean
+        let report = crate::clean(&repo, false)?;
+        assert_eq!(report, CleanReport::default());
+        Ok(())
+    }
+
+    #[test]
+    fn restore_removed() -> Result<(), anyhow::Error> {
+        let repo = add_dependency_to_repo(init_clean()?, "a")?;
+        let repo = add_dependency_to_repo(repo, "b")?;
+        let added = list(&repo)?;
+        crate::remove(&repo, "a")?;
+        crate::remove(&repo, "b")?;
+        // `b` isn't in the config after removing it
+        assert!(crate::restore(&repo, "b", Some("paravendor")).is_err());
+
+        // Objects are brought back after cleaning
+        crate::clean(&repo, false)?;
+        let tip = repo.refname_to_id("refs/heads/paravendor")?;
+        let head = git2::Oid::from_str(&added["a"].heads["HEAD"].commit)?;
+        assert!(!repo.graph_descendant_of(tip, head)?);
+        let commit = crate::restore(&repo, "a", None)?;
+        assert_eq!(list(&repo)?["a"], added["a"]);
+        assert!(repo.graph_descendant_of(commit, head)?);
+
+        assert!(crate::restore(&repo, "a", None).is_err());
+        assert!(crate::restore(&repo, "missing", None).is_err());
+        crate::restore(&repo, "b", Some("paravendor~3"))?;
+        assert_eq!(list(&repo)?, added);
+        Ok(())
+    }
+
+    #[test]
+    fn sync_group() -> Result<(), anyhow::Error> {
+        let mut repo = init_clean()?;
+        for (name, groups) in [("backend", vec!["backend".to_string()]), ("other", vec![])] {
+            let dep = demo_repo_with_one_commit()?;
+            crate::add(
+                &repo,
+                name,
+                &dep.dir.as_ref().to_string_lossy(),
+                &AddOptions {
+                    groups,
+                    ..add_options()
+                },
+                &mut NoProgress,
+            )?;
+            repo.depends_on(name, dep);
+        }
+        assert_eq!(list(&repo)?["backend"].groups, vec!["backend"]);
+        let repo = repo_with_changed_dependency("backend", repo)?;
+        let repo = repo_with_changed_dependency("other", repo)?;
+
+        let report = sync(
+            &repo,
+            &[],
+            &SyncOptions {
+                groups: vec!["backend".to_string()],
+                ..sync_options()
+            },
+            &mut NoProgress,
+        )?;
+        assert_eq!(report.changed, vec!["backend"]);
+
+        // Combined with names
+        let report = sync(
+            &repo,
+            &["other".to_string()],
+            &SyncOptions {
+                groups: vec!["backend".to_string()],
+                ..sync_options()
+            },
+            &mut NoProgress,
+        )?;
+        assert_eq!(report.changed, vec!["other"]);
+        Ok(())
+    }
+
+    #[test]
+    fn sync_shared_commits() -> Result<(), anyhow::Error> {
+        // Demo dependencies are identical, and so are their changes
+        let repo = add_dependency_to_repo(add()?, "fork")?;
+        let repo = repo_with_changed_dependency("dep", repo)?;
+        let repo = repo_with_changed_dependency("fork", repo)?;
+        let report = sync(&repo, &[], &sync_options(), &mut NoProgress)?;
+        assert_eq!(report.changed, vec!["dep", "fork"]);
+        assert_eq!(report.objects.commits, 1);
+        let commit = repo.find_commit(report.commit.unwrap())?;
+        assert_eq!(commit.parent_count(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn prune() -> Result<(), anyhow::Error> {
+        let repo = demo_repo_with_one_commit()?;
+        let root = repo.head()?.peel_to_commit()?;
+        let tree = root.tree()?;
+        let sig = repo.signature()?;
+        let a = repo.commit(None, &sig, &sig, "a", &tree, &[&root])?;
+        let b = repo.commit(None, &sig, &sig, "b", &tree, &[&root])?;
+        let b1 = repo.commit(None, &sig, &sig, "b1", &tree, &[&repo.find_commit(b)?])?;
+
+        let mut expected = vec![a, b1];
+        expected.sort();
+        let pruned = prune_head_commits(&repo, &[root.id(), a, b, b1, a])?
+            .iter()
+            .map(|c| c.id())
+            .collect::<Vec<_>>();
+        assert_eq!(pruned, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn prune_missing_objects() -> Result<(), anyhow::Error> {
+        let repo = demo_repo_with_one_commit()?;
+        let head = repo.head()?.peel_to_commit()?;
+        // A commit whose parent is missing, like after a partial fetch
+        let broken = repo.odb()?.write(
+            ObjectType::Commit,
+            format!(
+                "tree {}\nparent {}\nauthor A <a@b> 0 +0000\ncommitter A <a@b> 0 +0000\n\nbroken\n",
+                head.tree_id(),
+                "1".repeat(40)
+            )
+            .as_bytes(),
+        )?;
+        assert!(prune_head_commits(&repo, &[head.id(), broken]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn sync_singular_dependency_change() -> Result<(), anyhow::Error> {
+        for names in [vec![], vec!["dep".to_string()]] {
+            let repo = add()?;
+            let original_branch_commit = {
+                let (original_branch, _config) = ensure_initialized(&repo)?;
+                dbg!(&_config);
+                original_branch.into_reference().peel_to_commit()?.id()
+            };
+
+            let repo = repo_with_changed_dependency("dep", repo)?;
+
+            sync(&repo, &names, &sync_options(), &mut NoProgress)?;
+
+            let (branch, config) = ensure_initialized(&repo)?;
+
+            let dep_last_commit = repo
+                .get_dependency("dep")
+                .unwrap()
+                .head()?
+                .peel_to_commit()?;
+            // config is pointing to the updated dependency
+            dbg!(&config);
+            assert_eq!(
+                dep_last_commit.id().to_string(),
+                config
+                    .dependencies
+                    .get("dep")
+                    .unwrap()
+                    .heads
+                    .get("refs/heads/master")
+                    .unwrap()
+                    .commit
+            );
+            // paravendor branch has been updated to include the dependency
+            assert_eq!(
+                1,
+                branch
+                    .get()
+                    .peel_to_commit()?
+                    .parents()
+                    .filter(|p| p.id() == original_branch_commit)
+                    .count()
+            );
+        }
+        Ok(())
+    }
+}
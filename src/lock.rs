@@ -0,0 +1,37 @@
+//! Advisory locking of the paravendor branch
+
+use fs2::FileExt;
+use git2::Repository;
+use std::fs::{File, OpenOptions};
+
+/// Exclusive lock on `$GIT_DIR/paravendor.lock`, held by mutating commands
+///
+/// The lock is released when dropped.
+pub(crate) struct Lock(File);
+
+impl Lock {
+    pub(crate) fn acquire(repository: &Repository) -> Result<Self, anyhow::Error> {
+        let path = repository.path().join("paravendor.lock");
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)?;
+        match file.try_lock_exclusive() {
+            Ok(()) => Ok(Self(file)),
+            Err(e) if e.raw_os_error() == fs2::lock_contended_error().raw_os_error() => {
+                Err(anyhow::Error::msg(format!(
+                    "{} is held by another paravendor process, try again later",
+                    path.display()
+                )))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        let _ = self.0.unlock();
+    }
+}
@@ -1,41 +1,15 @@
-use clap::{Parser, Subcommand, ValueHint};
-use git2::build::TreeUpdateBuilder;
-use git2::{
-    AutotagOption, BranchType, FileMode, ObjectType, Reference, RemoteCallbacks, Repository,
-};
-use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
-use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum, ValueHint};
+use git_paravendor::{AddOptions, FetchOptions, NoProgress, ProgressSink, SyncOptions, Transfer};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use owo_colors::OwoColorize;
+use std::io::{IsTerminal, Write};
 use std::path::PathBuf;
+use std::process::ExitCode;
 use which::which;
 
-#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
-pub(crate) struct Config {
-    pub version: String,
-    pub dependencies: BTreeMap<String, Dependency>,
-}
-
-#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
-pub(crate) struct Dependency {
-    pub url: String,
-    pub heads: BTreeMap<String, Head>,
-}
-
-#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
-pub(crate) struct Head {
-    commit: String,
-}
-
-impl Default for Config {
-    fn default() -> Self {
-        Self {
-            version: "1.1".to_string(),
-            dependencies: BTreeMap::new(),
-        }
-    }
-}
-
 #[derive(Parser)]
+#[command(version)]
 pub(crate) struct Cli {
     #[command(subcommand)]
     pub command: Command,
@@ -47,687 +21,2601 @@ pub(crate) struct Cli {
     /// Directory where the GIT_DIR is
     #[clap(long, env = "GIT_DIR", value_hint = ValueHint::DirPath)]
     pub git_dir: Option<PathBuf>,
+
+    /// Don't show progress or status messages
+    #[clap(short, long, global = true)]
+    pub quiet: bool,
+
+    /// Proxy to fetch dependencies through
+    ///
+    /// Defaults to `http.proxy` from git config, or the `HTTPS_PROXY`/`HTTP_PROXY`/`ALL_PROXY`
+    /// environment variables
+    #[clap(long, global = true, value_hint = ValueHint::Url)]
+    pub proxy: Option<String>,
+
+    /// CA bundle to verify HTTPS certificates against
+    ///
+    /// Defaults to `http.sslCAInfo` from git config
+    #[clap(long, global = true, value_hint = ValueHint::FilePath)]
+    pub ca_info: Option<PathBuf>,
+
+    /// Don't verify HTTPS certificates (for testing only!)
+    #[clap(long, global = true)]
+    pub insecure: bool,
+
+    /// How many times to retry fetches failing due to transient network errors
+    #[clap(long, global = true, default_value_t = 2)]
+    pub retries: u32,
+
+    /// How many dependencies to fetch at once, defaults to the number of CPUs
+    ///
+    /// With `--jobs 1`, dependencies are fetched one after another, in order
+    #[clap(short, long, global = true, value_parser = clap::value_parser!(u32).range(1..))]
+    pub jobs: Option<u32>,
+
+    /// Fail on dependencies without any refs (such as empty repositories), instead of
+    /// warning about them
+    #[clap(long, global = true)]
+    pub strict: bool,
+
+    /// Never access the network, only using objects that are already in the repository
+    ///
+    /// Syncing leaves dependencies as they are (failing if their objects are missing),
+    /// while adding (without `--no-fetch`), pushing and pulling fail
+    #[clap(long, global = true, env = "PARAVENDOR_OFFLINE", value_parser = clap::builder::FalseyValueParser::new())]
+    pub offline: bool,
+
+    /// Remote to take the `paravendor` branch from if there's no local one
+    ///
+    /// Without it, `origin` is preferred, then the remote of the current branch's
+    /// upstream, or the only remote that has a `paravendor` branch
+    #[clap(long, global = true, value_name = "REMOTE")]
+    pub from_remote: Option<String>,
+
+    /// Don't ask for confirmation before destructive operations
+    ///
+    /// Confirmation isn't asked for when stdin isn't a terminal, either
+    #[clap(short, long, global = true)]
+    pub yes: bool,
+
+    /// Author of paravendor commits, as `Name <email>`
+    ///
+    /// Same as setting `GIT_AUTHOR_NAME` and `GIT_AUTHOR_EMAIL`. Without either, `user.name`
+    /// and `user.email` are used, or a generic identity if those aren't configured
+    #[clap(long, global = true, value_parser = parse_identity)]
+    pub author: Option<(String, String)>,
+
+    /// Committer of paravendor commits, as `Name <email>`
+    ///
+    /// Same as setting `GIT_COMMITTER_NAME` and `GIT_COMMITTER_EMAIL`
+    #[clap(long, global = true, value_parser = parse_identity)]
+    pub committer: Option<(String, String)>,
+
+    /// When to use colors
+    ///
+    /// With `auto`, colors are used if the output is a terminal and `NO_COLOR` isn't set
+    #[clap(long, global = true, value_enum, default_value_t = ColorChoice::Auto)]
+    pub color: ColorChoice,
+
+    /// Print results as JSON, and errors as JSON objects on stderr
+    ///
+    /// Results are printed as JSON by `sync --dry-run`, `diff`, `config get`, `which`,
+    /// `show-ref`, `stats` and `objects`.
+    /// Errors are reported as `{"error": "...", "kind": "..."}`, where `kind` is one of
+    /// `not_initialized`, `dependency_not_found`, `usage` or `error`
+    #[clap(long, global = true)]
+    pub json: bool,
+
+    /// Log what paravendor is doing (repeat for more detail)
+    #[clap(short, long, global = true, action = clap::ArgAction::Count)]
+    pub verbose: u8,
 }
 
 #[derive(Subcommand)]
 pub(crate) enum Command {
     /// Initializes paravendor in a repository
+    ///
+    /// If a remote has a `paravendor` branch, it's used (pick the remote with
+    /// `--from-remote`, otherwise `origin` is preferred, then the remote of the current
+    /// branch's upstream)
     Init {
         /// If no local `paravendor` branch is found, don't try to get a remote one
-        #[clap(long, default_value = "false")]
+        #[clap(long, default_value = "false", conflicts_with = "from_remote")]
         ignore_remote: bool,
+        /// Use the objects in this object store, like `git clone --reference` (can be
+        /// repeated)
+        ///
+        /// The store is the `objects` directory of another repository. It's recorded in
+        /// `objects/info/alternates`, and has to stay where it is
+        #[clap(long = "alternate", value_name = "OBJECTS", value_hint = ValueHint::DirPath)]
+        alternates: Vec<PathBuf>,
+        /// Name the local paravendor branch differently (recorded as `paravendor.branch`)
+        ///
+        /// A remote `paravendor` branch is still used, and becomes its upstream
+        #[clap(long, value_name = "NAME")]
+        local_branch: Option<String>,
     },
     /// Vendorizes a new dependency
+    ///
+    /// If the name or URL are omitted, they are prompted for
     Add {
         /// Dependency name
-        name: String,
+        name: Option<String>,
         /// Dependency URL
         #[clap(value_hint = ValueHint::Url)]
-        url: String,
+        url: Option<String>,
+        /// Use the given message for the paravendor commit
+        #[clap(short, long, conflicts_with = "no_commit")]
+        message: Option<String>,
+        /// Describe why the dependency is vendored (shown by `list --verbose`)
+        #[clap(short, long)]
+        description: Option<String>,
+        /// Add the dependency to a group (can be repeated)
+        #[clap(short, long = "group")]
+        groups: Vec<String>,
+        /// URL to fetch from if fetching from <URL> fails (can be repeated, tried in order)
+        #[clap(long = "mirror", value_hint = ValueHint::Url)]
+        mirrors: Vec<String>,
+        /// Stage the dependency instead of committing it
+        ///
+        /// Staged dependencies are kept in `refs/paravendor/staged` (which also keeps their
+        /// objects from being garbage collected) until `git paravendor commit` folds them
+        /// into a single commit. If that never happens, nothing is lost: the staged
+        /// dependencies stay there until committed or the ref is deleted.
+        #[clap(long)]
+        no_commit: bool,
+        /// Sign the paravendor commit, using the backend selected by `gpg.format`
+        #[clap(short = 'S', long)]
+        sign: bool,
+        /// Record objects that are already in this repository instead of fetching
+        ///
+        /// The heads are the ones given with `--head`, or the remote-tracking branches of
+        /// a remote with the same URL
+        #[clap(long)]
+        no_fetch: bool,
+        /// Use the objects in this object store, like `init --alternate` (can be repeated)
+        #[clap(long = "alternate", value_name = "OBJECTS", value_hint = ValueHint::DirPath)]
+        alternates: Vec<PathBuf>,
+        /// Head to record with `--no-fetch`, as `<ref>=<revision>` (can be repeated)
+        #[clap(long = "head", requires = "no_fetch", value_parser = parse_head)]
+        heads: Vec<(String, String)>,
+    },
+    /// Commits dependencies staged with `add --no-commit`
+    Commit {
+        /// Use the given message for the paravendor commit
+        #[clap(short, long)]
+        message: Option<String>,
+        /// Sign the paravendor commit, using the backend selected by `gpg.format`
+        #[clap(short = 'S', long)]
+        sign: bool,
     },
     /// List vendorized dependencies
-    List,
+    ///
+    /// With `--verbose`, descriptions and last sync times are shown, too
+    List {
+        /// Only list members of a group
+        #[clap(short, long)]
+        group: Option<String>,
+        /// Paravendor revision to list the dependencies of
+        #[clap(long)]
+        rev: Option<String>,
+        /// Print one `<name> TAB <url> TAB <number of refs>` line per dependency
+        ///
+        /// Unlike the default output, this format is stable across versions, for scripts.
+        /// `--verbose` doesn't change it
+        #[clap(long)]
+        porcelain: bool,
+        /// Like `--porcelain`, but terminating every field with NUL instead of tabs and
+        /// newlines
+        #[clap(short = 'z')]
+        nul: bool,
+    },
     /// Shows all refs for a vendorized dependency
     ShowRefs {
         /// Dependency name
         name: String,
+        /// Paravendor revision to read the refs from
+        #[clap(long)]
+        rev: Option<String>,
+        /// Terminate refs with NUL instead of newlines
+        #[clap(short = 'z')]
+        nul: bool,
+        /// Print the object id and type (`commit` or `tag`) of each ref, separated by tabs
+        #[clap(long)]
+        long: bool,
     },
     /// Resolves a ref in a vendorized dependency
     ShowRef {
         /// Dependency name
         name: String,
-        /// Ref
+        /// Ref (or an abbreviated commit id)
+        reference: String,
+        /// Print annotated tag objects instead of the commits they point to
+        #[clap(long)]
+        no_peel: bool,
+        /// Paravendor revision to read the ref from
+        #[clap(long)]
+        rev: Option<String>,
+        /// Terminate the object id with NUL instead of a newline
+        #[clap(short = 'z')]
+        nul: bool,
+        /// Print the type of the object (`commit` or `tag`) after its id
+        #[clap(long = "type")]
+        object_type: bool,
+    },
+    /// Checks whether a commit is part of a dependency's history
+    ///
+    /// Prints "yes" (followed by the dependency's refs containing the commit) and exits
+    /// with 0, or prints "no" and exits with 1
+    Contains {
+        /// Dependency name
+        name: String,
+        /// Commit (or anything else resolving to one)
+        commit: String,
+    },
+    /// Finds the dependencies a commit comes from
+    ///
+    /// Prints the dependencies whose history includes the commit, with their refs
+    /// containing it, and exits with 1 if there are none
+    Which {
+        /// Commit (or anything else resolving to one)
+        commit: String,
+    },
+    /// Shows how much the histories of two dependencies overlap
+    ///
+    /// Prints the number of commits only in either of them and shared by both, and the
+    /// newest shared commits (their merge bases)
+    Compare {
+        /// First dependency name
+        first: String,
+        /// Second dependency name
+        second: String,
+    },
+    /// Shows the commits a dependency ref pointed to over time
+    ///
+    /// Prints each value of the ref, oldest first, with the paravendor commit that
+    /// recorded it and its date
+    RefHistory {
+        /// Dependency name
+        name: String,
+        /// Ref name (full or short)
         reference: String,
     },
+    /// Checks that the objects of all dependencies are in the repository
+    ///
+    /// Prints the first missing object of each incomplete dependency, and exits with 1 if
+    /// there are any
+    Verify {
+        /// Check the whole history of every head, including trees and blobs
+        #[clap(long)]
+        deep: bool,
+        /// Also check the configs of paravendor commits against their checksums
+        #[clap(long)]
+        config: bool,
+    },
+    /// Fetches objects missing from the history of dependencies again
+    ///
+    /// Only dependencies found incomplete by `verify --deep` are fetched. Exits with 1 if
+    /// some objects couldn't be recovered
+    Repair,
+    /// Connects to the URL and mirrors of every dependency, without fetching anything
+    ///
+    /// Prints whether each of them is reachable and how long connecting took, and exits
+    /// with 1 if any are unreachable
+    Health,
+    /// Packs the objects of the repository (like `git repack -a -d`), using `git`
+    ///
+    /// Prints the size of the object store before and after
+    Repack {
+        /// Only pack the loose objects reachable from the paravendor branch
+        #[clap(long)]
+        paravendor_only: bool,
+    },
+    /// Squashes older commits of the paravendor branch into a single base commit
+    ///
+    /// Objects referenced by the kept configs stay reachable. This rewrites the branch,
+    /// so pushing it afterwards requires `git push --force`
+    Prune {
+        /// Number of most recent commits to keep
+        #[clap(long, value_name = "N", required_unless_present = "older_than")]
+        keep: Option<usize>,
+        /// Squash commits older than this (like `90d`, `6w` or `1y`)
+        ///
+        /// With `--keep`, only commits that both would squash are squashed
+        #[clap(long, value_name = "AGE", value_parser = git_paravendor::parse_duration)]
+        older_than: Option<std::time::Duration>,
+        /// Only report what would be pruned
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// Drops the objects of removed dependencies from the paravendor branch and repacks
+    ///
+    /// Objects still referred to by other dependencies are kept. Like `prune`, this
+    /// rewrites the branch
+    Clean {
+        /// Only report what would be dropped
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// Shows the number of objects and the size of their blobs, by dependency
+    ///
+    /// History shared between dependencies is only included in the total
+    Size {
+        /// Only count objects of this dependency
+        name: Option<String>,
+    },
+    /// Shows how many objects of a dependency no other dependency has, and how many are
+    /// shared
+    ///
+    /// Unique objects are what removing the dependency (and running `clean`) would free
+    Objects {
+        /// Dependency name
+        name: String,
+    },
+    /// Shows how many objects are stored once while belonging to several dependencies
+    ///
+    /// Compares the objects of all dependencies, each counted once, with what storing
+    /// each dependency separately would take
+    Stats,
     /// Sync vendorized dependencies
     Sync {
         /// Limit syncing to a list of dependencies
         ///
-        /// If not specified, all dependencies will be synced
+        /// If neither names nor groups are specified, all dependencies will be synced.
+        /// `-` reads more names from stdin, like `--stdin`
         names: Vec<String>,
+        /// Read the names of dependencies to sync from stdin, one per line
+        ///
+        /// Only the dependencies named there (and as arguments) are synced, so if there
+        /// are none, nothing is
+        #[clap(long)]
+        stdin: bool,
+        /// With `--stdin`, names are terminated with NUL instead of newlines
+        #[clap(short = 'z')]
+        nul: bool,
+        /// Sync members of a group (can be repeated)
+        #[clap(short, long = "group")]
+        groups: Vec<String>,
+        /// Use the given message for the paravendor commit
+        #[clap(short, long)]
+        message: Option<String>,
+        /// Keep syncing other dependencies if some fail
+        ///
+        /// Successfully synced dependencies are committed, and the failures are
+        /// reported at the end
+        #[clap(long)]
+        keep_going: bool,
+        /// Fetch dependencies and report what would change without committing
+        #[clap(long)]
+        dry_run: bool,
+        /// Sign the paravendor commit, using the backend selected by `gpg.format`
+        #[clap(short = 'S', long)]
+        sign: bool,
+        /// Wait at least this many milliseconds between fetches from the same host
+        #[clap(long, value_name = "MS", default_value_t = 0)]
+        fetch_delay: u64,
+        /// Only update this ref of the dependency (can be repeated)
+        ///
+        /// Requires naming a single dependency. Other refs are left as they were
+        #[clap(long = "ref")]
+        refs: Vec<String>,
+        /// Discard the objects fetched by an interrupted sync instead of resuming it
+        ///
+        /// A sync that was interrupted after fetching some dependencies keeps their
+        /// objects, so that the next one doesn't have to download them again
+        #[clap(long)]
+        discard_interrupted: bool,
+    },
+    /// Pushes the paravendor branch, with all vendored objects, to a remote
+    Push {
+        /// Remote to push to
+        #[clap(default_value = "origin")]
+        remote: String,
+    },
+    /// Fast-forwards the paravendor branch to the one on a remote
+    Pull {
+        /// Remote to pull from
+        #[clap(default_value = "origin")]
+        remote: String,
+    },
+    /// Shows what changed between two paravendor revisions
+    Diff {
+        /// Older revision, defaults to the parent of the paravendor branch tip
+        old: Option<String>,
+        /// Newer revision, defaults to the paravendor branch
+        new: Option<String>,
+    },
+    /// Prints Cargo configuration making it use vendored dependencies
+    CargoPatch {
+        /// Ref to use in each dependency
+        #[clap(long = "ref", default_value = "HEAD")]
+        reference: String,
+        /// Kind of configuration to print
+        #[clap(long, value_enum, default_value_t = CargoFormat::Patch)]
+        format: CargoFormat,
+    },
+    /// Exports dependencies as git submodules
+    ///
+    /// Writes `.gitmodules` entries for all dependencies, pinned to their default branch
+    ExportSubmodules {
+        /// Directory (relative to the working directory) to put submodules under
+        #[clap(value_hint = ValueHint::DirPath)]
+        dir: String,
+        /// Also add the submodules to the index, ready to be committed
+        #[clap(long)]
+        gitlinks: bool,
+    },
+    /// Writes the list of dependencies to a manifest file, without the vendored objects
+    ExportManifest {
+        /// File to write the manifest to (`-` for stdout)
+        #[clap(value_hint = ValueHint::FilePath)]
+        file: PathBuf,
+        /// Include the recorded refs and the objects they point to
+        #[clap(long)]
+        with_refs: bool,
+    },
+    /// Writes all objects of the paravendor branch, including the history of every
+    /// dependency, to a packfile
+    ///
+    /// Prints the tip of the paravendor branch, to be given to `import-pack --tip`
+    ExportPack {
+        /// File to write the pack to
+        #[clap(value_hint = ValueHint::FilePath)]
+        file: PathBuf,
+    },
+    /// Moves the paravendor branch between repositories as a git bundle
+    Bundle {
+        #[command(subcommand)]
+        command: BundleCommand,
+    },
+    /// Adds the objects of a packfile (written by `export-pack`) to the repository
+    ImportPack {
+        /// Pack to import
+        #[clap(value_hint = ValueHint::FilePath)]
+        file: PathBuf,
+        /// Fast-forward the paravendor branch (or create it) to this commit
+        #[clap(long)]
+        tip: Option<String>,
+    },
+    /// Removes a dependency
+    ///
+    /// Its objects stay reachable from earlier paravendor commits
+    Remove {
+        /// Dependency name
+        name: String,
+    },
+    /// Brings back a removed dependency from an earlier paravendor commit
+    ///
+    /// Its objects have to still be in the repository
+    Restore {
+        /// Dependency name
+        name: String,
+        /// Paravendor revision to take the dependency from, defaults to the last one that
+        /// has it
+        #[clap(long)]
+        rev: Option<String>,
+    },
+    /// Excludes a dependency from syncing all dependencies
+    Disable {
+        /// Dependency name
+        name: String,
+    },
+    /// Re-enables syncing of a disabled dependency
+    Enable {
+        /// Dependency name
+        name: String,
+    },
+    /// Stops syncing a dependency, keeping it vendored as it is
+    ///
+    /// Unlike disabled dependencies, frozen ones aren't synced even when named explicitly
+    Freeze {
+        /// Dependency name
+        name: String,
+    },
+    /// Resumes syncing a frozen dependency
+    Unfreeze {
+        /// Dependency name
+        name: String,
+    },
+    /// Points a recorded ref of a dependency to a commit that is already in the repository
+    ///
+    /// For recovering without syncing. The ref is updated by later syncs as usual
+    UpdateHead {
+        /// Dependency name
+        name: String,
+        /// Full ref name (like `refs/heads/main`)
+        reference: String,
+        /// Commit (or annotated tag) to point the ref to
+        commit: String,
+    },
+    /// Reads or changes paravendor config values
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Upgrades paravendor config to the current format version
+    ///
+    /// Minor version upgrades are read automatically and stored by the next commit, major
+    /// ones require this command
+    Migrate,
+    /// Prints the JSON Schema of the paravendor config
+    Schema,
+    /// Prints the version of paravendor and the config format versions it supports
+    Version,
+    /// Prints a shell completion script
+    ///
+    /// For bash and fish, dependency names are completed, too
+    Completions {
+        /// Shell to complete in
+        shell: clap_complete::Shell,
+    },
+    /// Writes man pages for paravendor and each of its commands into a directory
+    #[clap(hide = true)]
+    GenerateMan {
+        /// Directory to write the pages into (created if needed)
+        #[clap(value_hint = ValueHint::DirPath)]
+        dir: PathBuf,
+    },
+    /// Opens the config in an editor, committing it if it changed
+    ///
+    /// The editor is picked like git does: `GIT_EDITOR`, `core.editor`, `VISUAL`, `EDITOR`,
+    /// or `vi`. Invalid configs are never committed
+    Edit {
+        /// Sign the paravendor commit, using the backend selected by `gpg.format`
+        #[clap(short = 'S', long)]
+        sign: bool,
+    },
+    /// Prints the config exactly as it was committed
+    CatConfig {
+        /// Paravendor revision to read the config from, defaults to the paravendor branch
+        #[clap(long)]
+        rev: Option<String>,
     },
     /// Shows commits belonging to paravendor branch
     Log {
+        /// Draw what each commit brought in, without using `git`
+        #[clap(long)]
+        graph: bool,
+        /// Only show commits that added, removed or changed this dependency
+        #[clap(long = "dep", value_name = "NAME")]
+        dependency: Option<String>,
         /// Extra options for `git log`
         ///
-        /// Effective if `git` is present, otherwise ignored
+        /// Without `git`, only `--oneline`, `-n <count>` (`--max-count`) and `--reverse`
+        /// are supported
         options: Option<Vec<String>>,
     },
 }
 
-impl Cli {
-    pub(crate) fn ensure_initialized(
-        repository: &Repository,
-    ) -> Result<(git2::Branch, Config), anyhow::Error> {
-        repository
-            .find_branch("paravendor", BranchType::Local)
-            .or_else(|e| {
-                if e.code() == git2::ErrorCode::NotFound {
-                    if let Some(ref remote) = repository
-                        // Try resolving head to branch
-                        .head()
-                        .ok()
-                        .filter(Reference::is_branch)
-                        .and_then(|r| r.name().map(|n| n.to_string()))
-                        // And then the branch to a remote
-                        .and_then(|branch| {
-                            repository
-                                .branch_upstream_remote(&branch)
-                                .ok()
-                                .and_then(|b| b.as_str().map(str::to_string))
-                        })
-                        // Otherwise, pick the first one (FIXME: is this a good idea?)
-                        .or_else(|| {
-                            repository
-                                .remotes()
-                                .ok()
-                                .and_then(|arr| arr.get(0).map(str::to_string))
-                        })
-                    {
-                        if let Ok(branch) = repository
-                            .find_branch(&format!("{remote}/paravendor"), BranchType::Remote)
-                        {
-                            return repository.branch(
-                                "paravendor",
-                                &branch.get().peel_to_commit()?,
-                                false,
-                            );
-                        }
-                    }
-                }
-                Err(e)
-            })
-            .map_err(|e| {
-                if e.code() == git2::ErrorCode::NotFound {
-                    anyhow::Error::msg("paravendor is not initialized, run `git paravendor init`")
-                } else {
-                    anyhow::Error::new(e)
-                }
-            })
-            .and_then(|branch| {
-                let obj = repository.revparse_single("paravendor:config")?;
-                if obj.kind() == Some(ObjectType::Blob) {
-                    let config: Config =
-                        toml::from_str(std::str::from_utf8(obj.as_blob().unwrap().content())?)?;
-                    Ok((branch, config))
-                } else {
-                    Err(anyhow::Error::msg("paravendor config not found"))
-                }
-            })
-    }
-
-    pub(crate) fn sync_dependency<'a>(
-        repository: &'a Repository,
-        url: &str,
-    ) -> Result<(BTreeMap<String, Head>, Vec<git2::Commit<'a>>), anyhow::Error> {
-        let mut remote = repository.remote_anonymous(url)?;
-        let mut cb = RemoteCallbacks::new();
-
-        let received_objects = ProgressBar::hidden();
-        received_objects.set_message("Received objects");
-        received_objects.set_style(ProgressStyle::with_template(
-            "{msg} {wide_bar} {pos:>7}/{len:7} (ETA {eta})",
-        )?);
-        let indexed_deltas = ProgressBar::hidden();
-        indexed_deltas.set_message("Indexed deltas");
-        indexed_deltas.set_style(ProgressStyle::with_template(
-            "{msg} {wide_bar} {pos:>7}/{len:7} (ETA {eta})",
-        )?);
-        let multi_pb = MultiProgress::with_draw_target(ProgressDrawTarget::stderr());
-        multi_pb.add(received_objects.clone());
-        multi_pb.add(indexed_deltas.clone());
-
-        cb.transfer_progress(move |p| {
-            if received_objects.is_hidden() {
-                received_objects.set_draw_target(ProgressDrawTarget::stderr());
-                indexed_deltas.set_draw_target(ProgressDrawTarget::stderr());
-            }
-            received_objects.set_length(p.total_objects() as u64);
-            received_objects.set_position(p.received_objects() as u64);
-            if p.total_objects() == p.received_objects() {
-                received_objects.finish_and_clear();
-            }
-
-            indexed_deltas.set_length(p.total_deltas() as u64);
-            indexed_deltas.set_position(p.indexed_deltas() as u64);
-
-            if p.total_deltas() == p.indexed_deltas() {
-                indexed_deltas.finish_and_clear();
-            }
-
-            true
-        });
-        remote.fetch::<&str>(
-            &[],
-            Some(
-                git2::FetchOptions::new()
-                    .download_tags(AutotagOption::None)
-                    .remote_callbacks(cb),
-            ),
-            None,
-        )?;
-
-        let heads = remote
-            .list()?
-            .iter()
-            .map(|h| {
-                (
-                    h.name().to_string(),
-                    Head {
-                        commit: h.oid().to_string(),
-                    },
-                )
-            })
-            .collect();
-
-        let head_commits: Vec<_> = remote
-            .list()?
-            .iter()
-            .filter_map(|h| repository.find_commit(h.oid()).ok())
-            .collect();
-
-        fn is_commit_in_history(
-            repo: &Repository,
-            target: &git2::Commit,
-            reference: &git2::Commit,
-        ) -> Result<bool, anyhow::Error> {
-            let mut revwalk = repo.revwalk()?;
-            revwalk.push(reference.id())?;
-
-            for oid in revwalk {
-                let oid = oid?;
-                if oid == target.id() {
-                    return Ok(true);
-                }
-            }
-            Ok(false)
-        }
+#[derive(Subcommand)]
+pub(crate) enum ConfigCommand {
+    /// Prints a config value
+    Get {
+        /// Dotted key, e.g. `dependencies.<name>.url`
+        key: String,
+    },
+    /// Changes a scalar config value, committing the change
+    ///
+    /// The value has to be set already, and the new value has to be of the same type
+    Set {
+        /// Dotted key, e.g. `dependencies.<name>.url`
+        key: String,
+        /// New value
+        value: String,
+    },
+}
 
-        let pruned_head_commits: Vec<_> = head_commits
-            .clone()
-            .into_iter()
-            .filter(|c| {
-                !head_commits
-                    .iter()
-                    .any(|c_| c_.id() != c.id() && is_commit_in_history(repository, c, c_).unwrap())
-            })
-            .collect();
+#[derive(Subcommand)]
+pub(crate) enum BundleCommand {
+    /// Writes a bundle of the paravendor branch and all objects reachable from it
+    Create {
+        /// File to write the bundle to
+        #[clap(value_hint = ValueHint::FilePath)]
+        file: PathBuf,
+    },
+    /// Checks that a bundle is complete and can be applied
+    Verify {
+        /// Bundle to check
+        #[clap(value_hint = ValueHint::FilePath)]
+        file: PathBuf,
+    },
+    /// Adds the objects of a bundle and fast-forwards the paravendor branch to it
+    Apply {
+        /// Bundle to apply
+        #[clap(value_hint = ValueHint::FilePath)]
+        file: PathBuf,
+    },
+}
 
-        Ok((heads, pruned_head_commits))
+/// Asks the user for a value on stderr, falling back to `default` if nothing is entered
+fn prompt(question: &str, default: Option<&str>) -> Result<String, anyhow::Error> {
+    match default {
+        Some(default) => eprint!("{question} [{default}]: "),
+        None => eprint!("{question}: "),
     }
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    match (answer.trim(), default) {
+        ("", Some(default)) => Ok(default.to_string()),
+        ("", None) => Err(anyhow::Error::msg(format!("{question} is required"))),
+        (answer, _) => Ok(answer.to_string()),
+    }
+}
 
-    pub(crate) fn execute(mut self) -> Result<Self, anyhow::Error> {
-        let option = std::env::current_dir().ok();
-        let repository_path = self
-            .git_dir
-            .as_ref()
-            .or(self.change_dir.as_ref())
-            .or(option.as_ref())
-            .ok_or(anyhow::Error::msg("no repository path specified"))?;
-        let repository = git2::Repository::open(repository_path)?;
-        match self.command {
-            Command::Init { ignore_remote } => {
-                match repository.find_branch("paravendor", BranchType::Local) {
-                    Ok(_) => return Err(anyhow::Error::msg("'paravendor' branch already exists")),
-                    Err(err) => {
-                        if err.code() == git2::ErrorCode::NotFound && !ignore_remote {
-                            if let Ok(branch) =
-                                repository.find_branch("origin/paravendor", BranchType::Remote)
-                            {
-                                repository.branch(
-                                    "paravendor",
-                                    &branch.get().peel_to_commit()?,
-                                    false,
-                                )?;
-                                return Ok(self);
-                            }
-                        }
-
-                        let config = Config::default();
-                        let serialized_config = toml::to_string_pretty(&config)?;
-
-                        // Prepare initial commit
-                        let mut tree = repository.treebuilder(None)?;
-                        let odb = repository.odb()?;
-                        let blob = odb.write(ObjectType::Blob, serialized_config.as_bytes())?;
-                        tree.insert("config", blob, 0o100644)?;
-                        let tree_oid = tree.write()?;
-
-                        let signature = repository.signature()?;
-                        let init_commit = repository.commit(
-                            None,
-                            &signature,
-                            &signature,
-                            "Initialize paravendor",
-                            &repository.find_tree(tree_oid)?,
-                            &[],
-                        )?;
-
-                        // Create the branch
-                        repository.branch(
-                            "paravendor",
-                            &repository.find_commit(init_commit)?,
-                            false,
-                        )?;
-                    }
-                }
-            }
-            Command::Add { ref name, ref url } => {
-                let (branch, mut config) = Self::ensure_initialized(&repository)?;
-                if config.dependencies.get(name).is_some() {
-                    return Err(anyhow::Error::msg(format!(
-                        "{name} has been already added, aborting"
-                    )));
-                }
-
-                let (heads, mut pruned_head_commits) = Self::sync_dependency(&repository, url)?;
-
-                config.dependencies.insert(
-                    name.clone(),
-                    Dependency {
-                        url: url.clone(),
-                        heads,
-                    },
-                );
-
-                let serialized_config = toml::to_string_pretty(&config)?;
-                let commit = branch.into_reference().peel_to_commit()?;
-
-                let mut tree = TreeUpdateBuilder::new();
-                let odb = repository.odb()?;
-                let blob = odb.write(ObjectType::Blob, serialized_config.as_bytes())?;
-                tree.upsert("config", blob, FileMode::Blob);
-                let tree_oid = tree.create_updated(&repository, &commit.tree()?)?;
-
-                pruned_head_commits.insert(0, commit);
-
-                let _add_commit = repository.commit(
-                    Some("refs/heads/paravendor"),
-                    &repository.signature()?,
-                    &repository.signature()?,
-                    &format!("Add {} from {}", name, url),
-                    &repository.find_tree(tree_oid)?,
-                    &pruned_head_commits.iter().collect::<Vec<_>>(),
-                )?;
-            }
-            Command::Sync { ref names } => {
-                let (branch, mut config) = Self::ensure_initialized(&repository)?;
-                let original_config = config.clone();
-
-                let effective_dependencies = config
-                    .dependencies
-                    .iter_mut()
-                    .filter(|d| names.is_empty() || names.iter().any(|n| d.0 == n))
-                    .collect::<Vec<_>>();
-
-                let mut pruned_head_commits = Vec::new();
-                let mut changed_dependencies = Vec::new();
-                for (name, dependency) in effective_dependencies {
-                    let (heads, mut dependency_pruned_head_commits) =
-                        Self::sync_dependency(&repository, &dependency.url)?;
-                    let old_heads = dependency.heads.clone();
-                    dependency.heads = heads;
-                    pruned_head_commits.append(&mut dependency_pruned_head_commits);
-                    if old_heads != dependency.heads {
-                        println!("Synced {name}");
-                        changed_dependencies.push(name.to_string());
-                    }
-                }
+/// Opens `path` in the user's editor, waiting for it to exit
+fn edit(repository: &git2::Repository, path: &std::path::Path) -> Result<(), anyhow::Error> {
+    let editor = std::env::var("GIT_EDITOR")
+        .ok()
+        .or_else(|| repository.config().ok()?.get_string("core.editor").ok())
+        .or_else(|| std::env::var("VISUAL").ok())
+        .or_else(|| std::env::var("EDITOR").ok())
+        .filter(|editor| !editor.is_empty())
+        .unwrap_or_else(|| "vi".to_string());
+    // Through the shell, as editors are often configured with arguments
+    let status = std::process::Command::new("sh")
+        .args(["-c", &format!("{editor} \"$@\""), &editor])
+        .arg(path)
+        .status()?;
+    if !status.success() {
+        return Err(anyhow::Error::msg(format!("editor {editor} failed")));
+    }
+    Ok(())
+}
 
-                if original_config == config {
-                    eprintln!("No updates detected");
-                } else {
-                    let serialized_config = toml::to_string_pretty(&config)?;
+/// Suggested dependency name for `url`: its last path segment, without `.git`
+fn default_name(url: &str) -> Option<&str> {
+    let name = url
+        .trim_end_matches('/')
+        .rsplit(['/', ':'])
+        .next()?
+        .trim_end_matches(".git");
+    (!name.is_empty()).then_some(name)
+}
 
-                    let commit = branch.into_reference().peel_to_commit()?;
+/// Formats a number of bytes with a binary unit
+fn human_bytes(bytes: u64) -> String {
+    const UNITS: &[&str] = &["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{size:.1} {}", UNITS[unit])
+}
 
-                    let mut tree = TreeUpdateBuilder::new();
-                    let odb = repository.odb()?;
-                    let blob = odb.write(ObjectType::Blob, serialized_config.as_bytes())?;
-                    tree.upsert("config", blob, FileMode::Blob);
-                    let tree_oid = tree.create_updated(&repository, &commit.tree()?)?;
+fn parse_identity(identity: &str) -> Result<(String, String), anyhow::Error> {
+    match identity
+        .trim_end()
+        .strip_suffix('>')
+        .and_then(|i| i.split_once('<'))
+    {
+        Some((name, email)) if !name.trim().is_empty() && !email.trim().is_empty() => {
+            Ok((name.trim().to_string(), email.trim().to_string()))
+        }
+        _ => Err(anyhow::Error::msg("expected `Name <email>`")),
+    }
+}
 
-                    pruned_head_commits.insert(0, commit);
+fn parse_head(head: &str) -> Result<(String, String), anyhow::Error> {
+    match head.split_once('=') {
+        Some((name, revision)) if !name.is_empty() && !revision.is_empty() => {
+            Ok((name.to_string(), revision.to_string()))
+        }
+        _ => Err(anyhow::Error::msg("expected `<ref>=<revision>`")),
+    }
+}
 
-                    let _sync_commit = repository.commit(
-                        Some("refs/heads/paravendor"),
-                        &repository.signature()?,
-                        &repository.signature()?,
-                        &format!("Sync: {}", changed_dependencies.join(", ")),
-                        &repository.find_tree(tree_oid)?,
-                        &pruned_head_commits.iter().collect::<Vec<_>>(),
-                    )?;
-                }
+/// Prints the paravendor history with the dependency commits merged into it
+///
+/// ```text
+/// * 1a2b3c4 Sync: dep
+/// |\
+/// | o 5d6e7f8 dep refs/heads/main
+/// |/
+/// |   dep refs/heads/old 9abcdef -> 0000000
+/// * 0123456 Add dep from https://example.com/dep.git
+/// ```
+fn print_graph(entries: &[git_paravendor::LogEntry]) {
+    let short = |oid: &str| oid[..7.min(oid.len())].to_string();
+    for (i, entry) in entries.iter().enumerate() {
+        anstream::println!(
+            "* {} {}",
+            short(&entry.id.to_string()).yellow(),
+            entry.summary
+        );
+        if !entry.merged.is_empty() {
+            println!("|\\");
+            for merged in &entry.merged {
+                // Refs are grouped by dependency: `dep HEAD, refs/heads/main`
+                let refs = merged
+                    .refs
+                    .iter()
+                    .enumerate()
+                    .map(|(i, (name, reference))| match i.checked_sub(1) {
+                        Some(previous) if merged.refs[previous].0 == *name => reference.clone(),
+                        _ => format!("{name} {reference}"),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                anstream::println!("| o {} {refs}", short(&merged.id.to_string()).yellow());
             }
-            Command::List => {
-                let (_branch, config) = Self::ensure_initialized(&repository)?;
-
-                for (name, details) in &config.dependencies {
-                    println!("{name} {}", details.url);
-                }
+            println!("|/");
+        }
+        if let Some(changes) = &entry.changes {
+            for name in &changes.added {
+                anstream::println!("|   {} {name}", "+".green());
             }
-            Command::ShowRefs { ref name } => {
-                let (_branch, config) = Self::ensure_initialized(&repository)?;
-
-                match config.dependencies.get(name) {
-                    None => return Err(anyhow::Error::msg("dependency not found")),
-                    Some(dependency) => {
-                        for name in dependency.heads.keys() {
-                            println!("{name}");
-                        }
-                    }
-                }
+            for name in &changes.removed {
+                anstream::println!("|   {} {name}", "-".red());
             }
-            Command::ShowRef {
-                ref name,
-                ref reference,
-            } => {
-                let (_branch, config) = Self::ensure_initialized(&repository)?;
-
-                match config.dependencies.get(name) {
-                    None => return Err(anyhow::Error::msg("dependency not found")),
-                    Some(dependency) => {
-                        match dependency
-                            .heads
-                            .get(reference)
-                            .or_else(|| dependency.heads.get(&format!("refs/heads/{reference}")))
-                            .or_else(|| {
-                                dependency.heads.get(&format!("refs/tags/{reference}^{{}}"))
-                            })
-                            .or_else(|| dependency.heads.get(&format!("refs/tags/{reference}")))
-                        {
-                            None => return Err(anyhow::Error::msg("ref not found")),
-                            Some(head) => {
-                                println!("{}", head.commit);
-                            }
-                        }
+            // Refs that moved to commits drawn above are already labelled there
+            let merged = entry
+                .merged
+                .iter()
+                .map(|merged| merged.id.to_string())
+                .collect::<Vec<_>>();
+            let null = git2::Oid::zero().to_string();
+            for (name, changes) in &changes.changed {
+                for (reference, change) in changes {
+                    if change.new.as_ref().is_some_and(|new| merged.contains(new)) {
+                        continue;
                     }
+                    println!(
+                        "|   {name} {reference} {} -> {}",
+                        short(change.old.as_ref().unwrap_or(&null)),
+                        short(change.new.as_ref().unwrap_or(&null))
+                    );
                 }
             }
-            Command::Log { ref mut options } => {
-                let (branch, _config) = Self::ensure_initialized(&repository)?;
-
-                // If possible, try doing this with git as it makes a better output
-                match which("git") {
-                    Err(which::Error::CannotFindBinaryPath) => {}
-                    Err(e) => return Err(e)?,
-                    Ok(git) => {
-                        let mut args = vec!["log".to_string()];
-                        args.append(options.as_mut().unwrap_or(&mut vec![]));
-                        args.append(&mut vec![
-                            "paravendor".to_string(),
-                            "--first-parent".to_string(),
-                            "-C".to_string(),
-                            repository.workdir().unwrap().to_string_lossy().to_string(),
-                        ]);
-                        std::process::Command::new(git).args(args).spawn()?.wait()?;
-                        return Ok(self);
-                    }
-                };
+        }
+        if i + 1 < entries.len() && entry.merged.is_empty() {
+            println!("|");
+        }
+    }
+}
 
-                // Otherwise, do it ourselves
-                let mut top = branch.into_reference().peel_to_commit()?;
-                loop {
-                    println!(
-                        "{} {}",
-                        top.id(),
-                        top.message().unwrap_or("").lines().next().unwrap_or("")
-                    );
-                    if let Some(parent) = top.parents().next() {
-                        top = parent;
-                    } else {
-                        break;
+/// Parses the `git log` options understood without `git`, and whether `--oneline` was given
+fn parse_log_options(
+    options: &[String],
+) -> Result<(git_paravendor::LogOptions, bool), anyhow::Error> {
+    let mut log_options = git_paravendor::LogOptions::default();
+    let mut oneline = false;
+    let count = |count: &str| {
+        count
+            .parse()
+            .map_err(|_| anyhow::Error::msg(format!("invalid count `{count}`")))
+    };
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match option.as_str() {
+            "--oneline" => oneline = true,
+            "--reverse" => log_options.reverse = true,
+            "-n" | "--max-count" => {
+                let value = options
+                    .next()
+                    .ok_or_else(|| anyhow::Error::msg(format!("`{option}` requires a count")))?;
+                log_options.max_count = Some(count(value)?);
+            }
+            option => {
+                let value = option
+                    .strip_prefix("--max-count=")
+                    .or_else(|| option.strip_prefix("-n"))
+                    .or_else(|| {
+                        option
+                            .strip_prefix('-')
+                            .filter(|n| n.starts_with(|c: char| c.is_ascii_digit()))
+                    });
+                match value {
+                    Some(value) => log_options.max_count = Some(count(value)?),
+                    None => {
+                        return Err(anyhow::Error::msg(format!(
+                            "`{option}` is not supported without git"
+                        )))
                     }
                 }
             }
         }
-        Ok(self)
     }
+    Ok((log_options, oneline))
 }
 
-fn main() -> Result<(), anyhow::Error> {
-    Cli::parse().execute()?;
-    Ok(())
+/// Completes dependency names in bash, for both `git-paravendor` and `git paravendor`
+///
+/// `@FIRST@` are the commands taking a dependency name as their first argument, `@ALL@`
+/// those taking names as all of them, `@OPTIONS@` the options taking a value,
+/// `@COMMANDS@` all commands and `@COMPLETE@` clap's completion function.
+const BASH_NAMES: &str = r#"
+__git_paravendor_names() {
+    git paravendor list --porcelain 2>/dev/null | cut -f1
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+__git_paravendor_complete() {
+    local cur="${COMP_WORDS[COMP_CWORD]}" command="" arguments=0 i
+    for ((i = 1; i < COMP_CWORD; i++)); do
+        case " @OPTIONS@ " in
+            *" ${COMP_WORDS[i]} "*) i=$((i + 1)); continue ;;
+        esac
+        case "${COMP_WORDS[i]}" in
+            -*) ;;
+            *) [ -z "$command" ] && command="${COMP_WORDS[i]}" || arguments=$((arguments + 1)) ;;
+        esac
+    done
+    if [[ "$cur" != -* && -n "$command" ]]; then
+        case " @ALL@ " in
+            *" $command "*) COMPREPLY=($(compgen -W "$(__git_paravendor_names)" -- "$cur")); return ;;
+        esac
+        case " @FIRST@ " in
+            *" $command "*)
+                if [ "$arguments" -eq 0 ]; then
+                    COMPREPLY=($(compgen -W "$(__git_paravendor_names)" -- "$cur"))
+                    return
+                fi
+                ;;
+        esac
+    fi
+    @COMPLETE@ "$@"
+}
 
-    use std::ops::{Deref, DerefMut};
-    use std::path::Path;
-    use std::process::{ExitCode, Termination};
-    use tempfile::*;
-
-    struct TempRepository {
-        repository: Repository,
-        dir: TempDir,
-        dependencies: BTreeMap<String, TempRepository>,
-    }
-
-    impl TempRepository {
-        fn new() -> Result<Self, anyhow::Error> {
-            let dir = tempdir()?;
-            let repository = Repository::init(dir.as_ref())?;
-            Ok(Self {
-                repository,
-                dir,
-                dependencies: BTreeMap::new(),
-            })
+complete -F __git_paravendor_complete -o bashdefault -o default git-paravendor
+
+# Used by git's own completion for `git paravendor`
+_git_paravendor() {
+    local command
+    command="$(__git_find_on_cmdline "@COMMANDS@")"
+    if [ -z "$command" ]; then
+        __gitcomp "@COMMANDS@"
+    elif [[ " @FIRST@ @ALL@ " == *" $command "* ]]; then
+        __gitcomp_nl "$(__git_paravendor_names)"
+    fi
+}
+"#;
+
+/// Completes dependency names in fish, see [`BASH_NAMES`]
+const FISH_NAMES: &str = r#"
+function __git_paravendor_names
+    git paravendor list --porcelain 2>/dev/null | string split -f1 \t
+end
+
+complete -c git-paravendor -n "__fish_seen_subcommand_from @FIRST@ @ALL@" -f -a "(__git_paravendor_names)"
+"#;
+
+/// Writes a completion script for `shell`
+///
+/// The script generated by clap completes commands and options, and for bash and fish,
+/// dependency names are completed by listing them when completing.
+fn completions(shell: clap_complete::Shell, out: &mut dyn Write) -> Result<(), anyhow::Error> {
+    let mut command = Cli::command();
+    command.build();
+    let mut generated = vec![];
+    clap_complete::generate(shell, &mut command, "git-paravendor", &mut generated);
+    out.write_all(&generated)?;
+    let generated = String::from_utf8_lossy(&generated);
+    let complete = generated
+        .split_once("complete -F ")
+        .and_then(|(_, rest)| rest.split_whitespace().next())
+        .unwrap_or_default();
+
+    let mut first = vec![];
+    let mut all = vec![];
+    let mut options = vec![];
+    let mut commands = vec![];
+    let mut add_options = |command: &clap::Command| {
+        for arg in command.get_arguments() {
+            if arg.get_action().takes_values() && !arg.is_positional() {
+                if let Some(short) = arg.get_short() {
+                    options.push(format!("-{short}"));
+                }
+                if let Some(long) = arg.get_long() {
+                    options.push(format!("--{long}"));
+                }
+            }
         }
-
-        fn depends_on(&mut self, name: &str, repository: TempRepository) {
-            self.dependencies.insert(name.to_string(), repository);
+    };
+    add_options(&command);
+    for subcommand in command.get_subcommands() {
+        let name = subcommand.get_name().to_string();
+        add_options(subcommand);
+        // Dependency names are the first argument, named after what they are, except for
+        // `add` and `restore`, which name dependencies that aren't there
+        match subcommand
+            .get_positionals()
+            .next()
+            .map(|arg| arg.get_id().as_str())
+        {
+            Some("names") => all.push(name.clone()),
+            Some("name" | "first") if name != "add" && name != "restore" => {
+                first.push(name.clone())
+            }
+            _ => {}
         }
+        commands.push(name);
+    }
+    options.sort();
+    options.dedup();
+    let script = match shell {
+        clap_complete::Shell::Bash => BASH_NAMES,
+        clap_complete::Shell::Fish => FISH_NAMES,
+        _ => return Ok(()),
+    };
+    write!(
+        out,
+        "{}",
+        script
+            .replace("@FIRST@", &first.join(" "))
+            .replace("@ALL@", &all.join(" "))
+            .replace("@OPTIONS@", &options.join(" "))
+            .replace("@COMMANDS@", &commands.join(" "))
+            .replace("@COMPLETE@", complete)
+    )?;
+    Ok(())
+}
 
-        fn get_dependency(&self, name: &str) -> Option<&TempRepository> {
-            self.dependencies.get(name)
+/// Writes the man page of a built `command` and its subcommands into `dir`, as
+/// `git-paravendor.1`, `git-paravendor-sync.1` and so on, returning how many were written
+fn generate_man(command: &clap::Command, dir: &std::path::Path) -> Result<usize, anyhow::Error> {
+    // Building names subcommands after their parents
+    let name = command
+        .get_display_name()
+        .unwrap_or_else(|| command.get_name());
+    let mut page = vec![];
+    clap_mangen::Man::new(command.clone()).render(&mut page)?;
+    std::fs::write(dir.join(format!("{name}.1")), page)?;
+    let mut pages = 1;
+    for subcommand in command.get_subcommands() {
+        if !subcommand.is_hide_set() && subcommand.get_name() != "help" {
+            pages += generate_man(subcommand, dir)?;
         }
+    }
+    Ok(pages)
+}
 
-        fn get_mut_dependency(&mut self, name: &str) -> Option<&mut TempRepository> {
-            self.dependencies.get_mut(name)
-        }
+/// Reads newline (or NUL) terminated names, skipping empty ones
+fn read_names(mut input: impl std::io::Read, nul: bool) -> Result<Vec<String>, anyhow::Error> {
+    let mut buffer = String::new();
+    input.read_to_string(&mut buffer)?;
+    let names = if nul {
+        buffer.split('\0').map(str::to_string).collect::<Vec<_>>()
+    } else {
+        buffer.lines().map(|line| line.trim().to_string()).collect()
+    };
+    Ok(names.into_iter().filter(|name| !name.is_empty()).collect())
+}
+
+/// Dependency as listed by `list --porcelain` (or `-z` if `nul`), which mustn't change
+/// across versions
+fn porcelain_record(name: &str, dependency: &git_paravendor::Dependency, nul: bool) -> String {
+    let (url, refs) = (&dependency.url, dependency.heads.len());
+    if nul {
+        format!("{name}\0{url}\0{refs}\0")
+    } else {
+        format!("{name}\t{url}\t{refs}\n")
     }
+}
 
-    impl Deref for TempRepository {
-        type Target = Repository;
+/// Ref as listed by `show-refs --long` (terminated with NUL if `nul`)
+fn long_ref_record(name: &str, head: &git_paravendor::Head, kind: &str, nul: bool) -> String {
+    let terminator = if nul { '\0' } else { '\n' };
+    format!("{name}\t{}\t{kind}{terminator}", head.commit)
+}
 
-        fn deref(&self) -> &Self::Target {
-            &self.repository
+/// Object id printed by `show-ref`, followed by its type if `kind` is set
+fn show_ref_output(
+    target: &str,
+    kind: Option<&str>,
+    json: bool,
+    nul: bool,
+) -> Result<String, anyhow::Error> {
+    if json {
+        let mut output = serde_json::json!({ "oid": target });
+        if let Some(kind) = kind {
+            output["type"] = kind.into();
         }
+        return Ok(format!("{}\n", serde_json::to_string_pretty(&output)?));
     }
+    let line = match kind {
+        Some(kind) => format!("{target} {kind}"),
+        None => target.to_string(),
+    };
+    Ok(if nul {
+        format!("{line}\0")
+    } else {
+        format!("{line}\n")
+    })
+}
 
-    impl DerefMut for TempRepository {
-        fn deref_mut(&mut self) -> &mut Self::Target {
-            &mut self.repository
+/// Markdown summary of a sync for `$GITHUB_STEP_SUMMARY`
+fn github_step_summary(report: &git_paravendor::SyncReport, dry_run: bool) -> String {
+    let mut summary = if dry_run {
+        "### paravendor sync (dry run)\n\n".to_string()
+    } else {
+        "### paravendor sync\n\n".to_string()
+    };
+    if report.changed.is_empty() {
+        summary.push_str("No updates detected\n");
+    } else {
+        summary.push_str("| Dependency | Ref | Old | New |\n|---|---|---|---|\n");
+        let oid = |oid: &Option<String>| match oid {
+            Some(oid) => format!("`{oid}`"),
+            None => "-".to_string(),
+        };
+        for name in &report.changed {
+            for (reference, change) in report.refs.get(name).into_iter().flatten() {
+                summary.push_str(&format!(
+                    "| {name} | {reference} | {} | {} |\n",
+                    oid(&change.old),
+                    oid(&change.new)
+                ));
+            }
         }
     }
+    for (name, e) in &report.failed {
+        summary.push_str(&format!("\n**Failed to sync {name}**: {e:#}\n"));
+    }
+    summary
+}
+
+/// GitHub Actions workflow commands annotating changed and failed dependencies
+fn github_annotations(report: &git_paravendor::SyncReport, dry_run: bool) -> Vec<String> {
+    // Workflow command data ends at the end of the line
+    let escape = |message: &str| {
+        message
+            .replace('%', "%25")
+            .replace('\r', "%0D")
+            .replace('\n', "%0A")
+    };
+    let changed = if dry_run { "Would sync" } else { "Synced" };
+    report
+        .changed
+        .iter()
+        .map(|name| {
+            format!(
+                "::notice title=paravendor::{}",
+                escape(&format!("{changed} {name}"))
+            )
+        })
+        .chain(report.failed.iter().map(|(name, e)| {
+            format!(
+                "::warning title=paravendor::{}",
+                escape(&format!("Failed to sync {name}: {e:#}"))
+            )
+        }))
+        .collect()
+}
 
-    impl Termination for TempRepository {
-        fn report(self) -> ExitCode {
-            ExitCode::SUCCESS
+#[derive(Clone, Copy, ValueEnum)]
+pub(crate) enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl From<ColorChoice> for anstream::ColorChoice {
+    fn from(choice: ColorChoice) -> Self {
+        match choice {
+            ColorChoice::Auto => Self::Auto,
+            ColorChoice::Always => Self::Always,
+            ColorChoice::Never => Self::Never,
         }
     }
+}
 
-    #[test]
-    fn init_clean() -> Result<TempRepository, anyhow::Error> {
-        let repo = TempRepository::new()?;
-        {
-            assert!(repo.find_branch("paravendor", BranchType::Local).is_err());
+#[derive(Clone, Copy, ValueEnum)]
+pub(crate) enum CargoFormat {
+    /// `[patch]` tables for `Cargo.toml`
+    Patch,
+    /// Source replacements for `.cargo/config.toml`
+    SourceReplacement,
+}
 
-            let cli = Cli {
-                command: Command::Init {
-                    ignore_remote: false,
-                },
-                change_dir: Some(repo.dir.as_ref().to_path_buf()),
-                git_dir: None,
-            };
-            cli.execute()?;
-            let (_branch, config) = Cli::ensure_initialized(&repo)?;
-            assert_eq!(config.version, "1.1");
+impl From<CargoFormat> for git_paravendor::CargoFormat {
+    fn from(format: CargoFormat) -> Self {
+        match format {
+            CargoFormat::Patch => Self::Patch,
+            CargoFormat::SourceReplacement => Self::SourceReplacement,
         }
-        Ok(repo)
     }
+}
 
-    fn demo_repo_with_one_commit() -> Result<TempRepository, anyhow::Error> {
-        let repo = TempRepository::new()?;
-        let sig = git2::Signature::new("John Doe", "john@doe.com", &git2::Time::new(0, 0))?;
-
-        // Prepare initial commit
-        let tree_oid = repo.treebuilder(None)?.write()?;
+/// Shows fetch progress as progress bars on stderr
+struct ProgressBars {
+    style: ProgressStyle,
+    /// Received objects and indexed deltas bars, created once the transfer starts
+    bars: Option<(MultiProgress, ProgressBar, ProgressBar)>,
+}
 
-        let _commit = repo.commit(
-            Some("refs/heads/master"),
-            &sig,
-            &sig,
-            "init",
-            &repo.find_tree(tree_oid)?,
-            &[],
-        )?;
-        Ok(repo)
+impl ProgressBars {
+    fn new() -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            style: ProgressStyle::with_template("{msg} {wide_bar} {pos:>7}/{len:7} (ETA {eta})")?,
+            bars: None,
+        })
     }
+}
 
-    fn add_dependency_to_repo(
-        mut repo: TempRepository,
-        name: &str,
-    ) -> Result<TempRepository, anyhow::Error> {
-        let dep = demo_repo_with_one_commit()?;
-        let dep_repo_commit = dep.head()?.peel_to_commit()?.id();
+impl ProgressSink for ProgressBars {
+    fn on_fetch(&mut self, _url: &str) {
+        self.bars = None;
+    }
 
-        {
-            let init_commit = dep.head()?.peel_to_commit()?;
-            let cli = Cli {
-                change_dir: Some(repo.dir.as_ref().to_path_buf()),
-                git_dir: None,
-                command: Command::Add {
-                    name: name.to_string(),
-                    url: dep.dir.as_ref().to_string_lossy().to_string(),
-                },
+    fn on_transfer(&mut self, transfer: Transfer) {
+        let style = &self.style;
+        let (_, received_objects, indexed_deltas) = self.bars.get_or_insert_with(|| {
+            let multi = MultiProgress::new();
+            let bar = |message| {
+                multi.add(
+                    ProgressBar::new(0)
+                        .with_style(style.clone())
+                        .with_message(message),
+                )
             };
-            let _cli = cli.execute()?;
-            let (branch, config) = Cli::ensure_initialized(&repo)?;
-
-            let dep = config.dependencies.get(name).unwrap();
-            for head_name in ["HEAD", "refs/heads/master"] {
-                let head = dep.heads.get(head_name).unwrap();
-                assert_eq!(head.commit, dep_repo_commit.to_string());
+            let received_objects = bar("Received objects");
+            let indexed_deltas = bar("Indexed deltas");
+            (multi, received_objects, indexed_deltas)
+        });
 
-                let commit = branch.get().peel_to_commit()?;
-                assert!(commit.parents().any(|p| p.id() == dep_repo_commit));
-                assert!(commit.parents().any(|p| p.id() == init_commit.id()));
-            }
+        received_objects.set_length(transfer.total_objects as u64);
+        received_objects.set_position(transfer.received_objects as u64);
+        if transfer.total_objects == transfer.received_objects {
+            received_objects.finish_and_clear();
         }
 
-        repo.depends_on(name, dep);
-
-        Ok(repo)
+        indexed_deltas.set_length(transfer.total_deltas as u64);
+        indexed_deltas.set_position(transfer.indexed_deltas as u64);
+        if transfer.total_deltas == transfer.indexed_deltas {
+            indexed_deltas.finish_and_clear();
+        }
     }
 
-    #[test]
-    fn add() -> Result<TempRepository, anyhow::Error> {
-        add_dependency_to_repo(init_clean()?, "dep")
+    fn on_empty(&mut self, url: &str) {
+        anstream::eprintln!(
+            "{} {url} has no refs, is the repository empty?",
+            "Warning:".yellow()
+        );
     }
 
-    #[test]
-    fn sync_no_changes() -> Result<(), anyhow::Error> {
-        let repo = add()?;
-
-        let (original_branch, _config) = Cli::ensure_initialized(&repo)?;
-
-        let cli = Cli {
-            command: Command::Sync { names: vec![] },
-            change_dir: repo.workdir().map(Path::to_path_buf),
-            git_dir: None,
-        };
-        let _ = cli.execute()?;
-
-        let (branch, _config) = Cli::ensure_initialized(&repo)?;
-
-        assert_eq!(
-            branch.get().peel_to_commit()?.id(),
-            original_branch.get().peel_to_commit()?.id()
+    fn on_retry(
+        &mut self,
+        url: &str,
+        error: &git2::Error,
+        delay: std::time::Duration,
+        attempt: u32,
+        retries: u32,
+    ) {
+        eprintln!(
+            "Fetching {url} failed ({}), retrying in {}s ({attempt}/{retries})",
+            error.message(),
+            delay.as_secs(),
         );
-
-        Ok(())
     }
+}
 
-    fn repo_with_changed_dependency(
-        name: &str,
-        mut repo: TempRepository,
-    ) -> Result<TempRepository, anyhow::Error> {
-        {
-            let dep = repo
-                .get_mut_dependency(name)
-                .ok_or_else(|| anyhow::Error::msg(format!("{name} dependency not found")))?;
-
-            let tree = dep.repository.treebuilder(None)?.write()?;
-            let tree = dep.find_tree(tree)?;
-
-            let sig = git2::Signature::new("John Doe", "john@doe.com", &git2::Time::new(0, 0))?;
-            // Prepare a commit
-            let _commit = dep.commit(
-                Some("refs/heads/master"),
-                &sig,
-                &sig,
-                "update",
-                &tree,
-                &[&dep.head()?.peel_to_commit()?],
-            )?;
+impl Cli {
+    /// Progress bars and retry notices, unless `--quiet`
+    fn progress(&self) -> Result<Box<dyn ProgressSink>, anyhow::Error> {
+        if self.quiet {
+            Ok(Box::new(NoProgress))
+        } else {
+            Ok(Box::new(ProgressBars::new()?))
         }
-        Ok(repo)
     }
 
-    #[test]
-    fn sync_singular_dependency_change() -> Result<(), anyhow::Error> {
-        for names in [vec![], vec!["dep".to_string()]] {
-            let repo = add()?;
-            let original_branch_commit = {
-                let (original_branch, _config) = Cli::ensure_initialized(&repo)?;
-                dbg!(&_config);
-                original_branch.into_reference().peel_to_commit()?.id()
-            };
+    fn fetch_options(&self) -> FetchOptions {
+        FetchOptions {
+            proxy: self.proxy.clone(),
+            ca_info: self.ca_info.clone(),
+            insecure: self.insecure,
+            retries: self.retries,
+            strict: self.strict,
+            offline: self.offline,
+        }
+    }
 
-            let repo = repo_with_changed_dependency("dep", repo)?;
+    /// Asks the user to confirm a destructive operation
+    fn confirm(&self, summary: &str) -> Result<bool, anyhow::Error> {
+        if self.yes || !std::io::stdin().is_terminal() {
+            return Ok(true);
+        }
+        eprint!("{summary}\nContinue? [y/N] ");
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer)?;
+        Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+    }
 
-            let cli = Cli {
-                // don't specify dependency name
-                command: Command::Sync { names },
-                change_dir: repo.workdir().map(Path::to_path_buf),
-                git_dir: None,
-            };
-            let _ = cli.execute()?;
-
-            let (branch, config) = Cli::ensure_initialized(&repo)?;
-
-            let dep_last_commit = repo
-                .get_dependency("dep")
-                .unwrap()
-                .head()?
-                .peel_to_commit()?;
-            // config is pointing to the updated dependency
-            dbg!(&config);
-            assert_eq!(
-                dep_last_commit.id().to_string(),
-                config
-                    .dependencies
-                    .get("dep")
-                    .unwrap()
-                    .heads
-                    .get("refs/heads/master")
-                    .unwrap()
-                    .commit
+    pub(crate) fn execute(mut self) -> Result<ExitCode, anyhow::Error> {
+        // Doesn't need a repository
+        if let Command::Schema = self.command {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&git_paravendor::schema())?
             );
-            // paravendor branch has been updated to include the dependency
-            assert_eq!(
-                1,
-                branch
-                    .get()
-                    .peel_to_commit()?
-                    .parents()
-                    .filter(|p| p.id() == original_branch_commit)
-                    .count()
+            return Ok(ExitCode::SUCCESS);
+        }
+        if let Command::Version = self.command {
+            if self.json {
+                let versions = serde_json::json!({
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "config_version": git_paravendor::CONFIG_VERSION,
+                    "oldest_config_version": git_paravendor::OLDEST_CONFIG_VERSION,
+                });
+                println!("{}", serde_json::to_string_pretty(&versions)?);
+            } else {
+                println!("git-paravendor {}", env!("CARGO_PKG_VERSION"));
+                println!(
+                    "config format {} (reads {} to {})",
+                    git_paravendor::CONFIG_VERSION,
+                    git_paravendor::OLDEST_CONFIG_VERSION,
+                    git_paravendor::CONFIG_VERSION
+                );
+            }
+            return Ok(ExitCode::SUCCESS);
+        }
+        if let Command::Completions { shell } = self.command {
+            completions(shell, &mut std::io::stdout().lock())?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        if let Command::GenerateMan { ref dir } = self.command {
+            std::fs::create_dir_all(dir)?;
+            let mut command = Cli::command();
+            command.build();
+            let pages = generate_man(&command, dir)?;
+            if !self.quiet {
+                eprintln!("Wrote {pages} man pages to {}", dir.display());
+            }
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        let base = match self.change_dir {
+            Some(ref dir) => dir.clone(),
+            None => std::env::current_dir()?,
+        };
+        let repository = match self.git_dir {
+            // Like with git, an explicit git dir is used as is, relative to -C
+            Some(ref git_dir) => git2::Repository::open(base.join(git_dir))?,
+            // Otherwise, it's looked up from the directory, which can also be a bare repository
+            None => git2::Repository::discover(&base)?,
+        };
+        if self.insecure {
+            eprintln!(
+                "WARNING: HTTPS certificate verification is disabled, \
+                 do not use --insecure outside of testing"
             );
         }
+        if let Some(ref remote) = self.from_remote {
+            if !matches!(self.command, Command::Init { .. })
+                && repository
+                    .find_branch(
+                        &git_paravendor::branch_name(&repository)?,
+                        git2::BranchType::Local,
+                    )
+                    .is_err()
+            {
+                git_paravendor::paravendor_from_remote(&repository, remote)?;
+            }
+        }
+        let fetch = self.fetch_options();
+        match self.command {
+            Command::Init {
+                ignore_remote,
+                ref alternates,
+                ref local_branch,
+            } => {
+                for alternate in alternates {
+                    git_paravendor::add_alternate(&repository, alternate)?;
+                }
+                let local_branch = local_branch.as_deref();
+                match self.from_remote {
+                    Some(ref remote) => {
+                        git_paravendor::init_from_remote(&repository, remote, local_branch)?
+                    }
+                    None => git_paravendor::init(&repository, ignore_remote, local_branch)?,
+                };
+            }
+            Command::Add {
+                ref name,
+                ref url,
+                ref message,
+                ref description,
+                ref groups,
+                ref mirrors,
+                no_commit,
+                sign,
+                no_fetch,
+                ref alternates,
+                ref heads,
+            } => {
+                if (name.is_none() || url.is_none()) && !std::io::stdin().is_terminal() {
+                    let mut command = Cli::command();
+                    command.build();
+                    return Err(command
+                        .find_subcommand_mut("add")
+                        .unwrap()
+                        .error(
+                            ErrorKind::MissingRequiredArgument,
+                            "<NAME> and <URL> are required when stdin is not a terminal",
+                        )
+                        .into());
+                }
+                let url = match url {
+                    Some(url) => url.clone(),
+                    None => prompt("Dependency URL", None)?,
+                };
+                let name = match name {
+                    Some(name) => name.clone(),
+                    None => prompt("Dependency name", default_name(&url))?,
+                };
+                for alternate in alternates {
+                    git_paravendor::add_alternate(&repository, alternate)?;
+                }
+                git_paravendor::add(
+                    &repository,
+                    &name,
+                    &url,
+                    &AddOptions {
+                        fetch,
+                        message: message.clone(),
+                        no_commit,
+                        description: description.clone(),
+                        groups: groups.clone(),
+                        mirrors: mirrors.clone(),
+                        sign,
+                        no_fetch,
+                        heads: heads.clone(),
+                    },
+                    self.progress()?.as_mut(),
+                )?;
+            }
+            Command::Commit { ref message, sign } => {
+                let added = git_paravendor::commit_staged(&repository, message.as_deref(), sign)?;
+                if added.is_empty() && !self.quiet {
+                    eprintln!("Nothing to commit");
+                }
+            }
+            Command::Sync {
+                ref names,
+                stdin,
+                nul,
+                ref message,
+                keep_going,
+                dry_run,
+                ref groups,
+                sign,
+                fetch_delay,
+                ref refs,
+                discard_interrupted,
+            } => {
+                if discard_interrupted {
+                    if git_paravendor::discard_interrupted_sync(&repository)? && !self.quiet {
+                        eprintln!("Discarded an interrupted sync");
+                    }
+                } else if let Some(interrupted) = git_paravendor::interrupted_sync(&repository)? {
+                    if !self.quiet {
+                        anstream::eprintln!(
+                            "{} an interrupted sync that fetched {} (at {}), \
+                             use --discard-interrupted to start over",
+                            "Resuming".cyan(),
+                            interrupted.dependencies.join(", "),
+                            interrupted.fetched_at
+                        );
+                    }
+                }
+                let mut names = names.clone();
+                if stdin || names.iter().any(|name| name == "-") {
+                    names.retain(|name| name != "-");
+                    names.extend(read_names(std::io::stdin().lock(), nul)?);
+                    if names.is_empty() {
+                        if !self.quiet {
+                            eprintln!("No dependencies given on stdin");
+                        }
+                        return Ok(ExitCode::SUCCESS);
+                    }
+                }
+                let report = git_paravendor::sync(
+                    &repository,
+                    &names,
+                    &SyncOptions {
+                        fetch,
+                        message: message.clone(),
+                        keep_going,
+                        dry_run,
+                        groups: groups.clone(),
+                        sign,
+                        jobs: self.jobs.map_or(0, |jobs| jobs as usize),
+                        fetch_delay: std::time::Duration::from_millis(fetch_delay),
+                        refs: refs.clone(),
+                    },
+                    self.progress()?.as_mut(),
+                )?;
+
+                // The sync is done by now, so failing to summarize it isn't an error
+                if let Some(path) = std::env::var_os("GITHUB_STEP_SUMMARY") {
+                    let written = std::fs::OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(&path)
+                        .and_then(|mut file| {
+                            file.write_all(github_step_summary(&report, dry_run).as_bytes())
+                        });
+                    if let Err(e) = written {
+                        anstream::eprintln!("{} {e}", "Failed to write the job summary:".yellow());
+                    }
+                }
+                if std::env::var_os("GITHUB_ACTIONS").is_some() {
+                    // The runner picks workflow commands up from stderr too, keeping stdout
+                    // clean for `--json`
+                    for annotation in github_annotations(&report, dry_run) {
+                        eprintln!("{annotation}");
+                    }
+                }
+                if !self.quiet {
+                    for name in &report.skipped {
+                        anstream::eprintln!("{} {name} (disabled)", "Skipping".yellow());
+                    }
+                    for name in &report.frozen {
+                        anstream::eprintln!("{} {name}", "Frozen".cyan());
+                    }
+                    for (name, reference) in &report.deleted {
+                        anstream::println!("{} {reference} from {}", "Deleted".red(), name.bold());
+                    }
+                    let short = |oid: &Option<String>| match oid {
+                        Some(oid) => oid[..7.min(oid.len())].to_string(),
+                        None => "0000000".to_string(),
+                    };
+                    for name in &report.changed {
+                        if dry_run {
+                            anstream::println!("{} {}", "Would sync".green(), name.bold());
+                        } else {
+                            anstream::println!("{} {}", "Synced".green(), name.bold());
+                        }
+                        for (reference, change) in report.refs.get(name).into_iter().flatten() {
+                            println!(
+                                "  {reference} {} -> {}",
+                                short(&change.old),
+                                short(&change.new)
+                            );
+                        }
+                    }
+                    if report.changed.is_empty() {
+                        eprintln!("No updates detected");
+                    }
+                }
+                if self.json {
+                    println!("{}", serde_json::to_string_pretty(&report.objects)?);
+                } else if dry_run || (!self.quiet && !report.changed.is_empty()) {
+                    let objects = report.objects;
+                    println!(
+                        "New objects: {} commits, {} trees, {} blobs",
+                        objects.commits, objects.trees, objects.blobs
+                    );
+                }
+
+                if !report.failed.is_empty() {
+                    for (name, e) in &report.failed {
+                        anstream::eprintln!("{} {}: {e:#}", "Failed to sync".red(), name.bold());
+                    }
+                    return Err(anyhow::Error::msg(format!(
+                        "failed to sync {}",
+                        report
+                            .failed
+                            .iter()
+                            .map(|(name, _)| name.as_str())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    )));
+                }
+                if dry_run && !report.changed.is_empty() {
+                    return Ok(ExitCode::from(exit::CHANGES_DETECTED));
+                }
+            }
+            Command::Push { ref remote } => {
+                let objects = git_paravendor::push(&repository, remote, &fetch)?;
+                if !self.quiet {
+                    eprintln!("Pushed paravendor to {remote} ({objects} objects)");
+                }
+            }
+            Command::Pull { ref remote } => {
+                let (moved, config) = git_paravendor::pull(&repository, remote, &fetch)?;
+                if !self.quiet {
+                    match moved {
+                        Some(tip) => eprintln!(
+                            "Updated paravendor to {tip} ({} dependencies)",
+                            config.dependencies.len()
+                        ),
+                        None => eprintln!("Already up to date"),
+                    }
+                }
+            }
+            Command::Edit { sign } => {
+                let source = git_paravendor::cat_config(
+                    &repository,
+                    &git_paravendor::branch_name(&repository)?,
+                )?;
+                // Kept in the git dir, like git's COMMIT_EDITMSG
+                let path = repository.path().join("PARAVENDOR_CONFIG.toml");
+                std::fs::write(&path, source)?;
+                let result = loop {
+                    edit(&repository, &path)?;
+                    let edited = std::fs::read_to_string(&path)?;
+                    match git_paravendor::edit_config(&repository, &edited, sign) {
+                        Err(e) if std::io::stdin().is_terminal() => {
+                            eprintln!("Error: {e:#}");
+                            if prompt("Edit again? (y/n)", Some("y"))? != "y" {
+                                break Err(anyhow::Error::msg("aborted, config left unchanged"));
+                            }
+                        }
+                        result => break result,
+                    }
+                };
+                std::fs::remove_file(&path)?;
+                if result?.is_none() && !self.quiet {
+                    eprintln!("Config unchanged");
+                }
+            }
+            Command::CatConfig { ref rev } => {
+                let rev = match rev {
+                    Some(rev) => rev.clone(),
+                    None => git_paravendor::branch_name(&repository)?,
+                };
+                std::io::stdout().write_all(&git_paravendor::cat_config(&repository, &rev)?)?;
+            }
+            Command::Diff { ref old, ref new } => {
+                let branch = git_paravendor::branch_name(&repository)?;
+                let old = old.clone().unwrap_or_else(|| format!("{branch}~1"));
+                let new = new.clone().unwrap_or(branch);
+                let diff = git_paravendor::diff(&repository, &old, &new)?;
+                if self.json {
+                    println!("{}", serde_json::to_string_pretty(&diff)?);
+                } else {
+                    let null = git2::Oid::zero().to_string();
+                    for name in &diff.added {
+                        anstream::println!("{} {}", "Added".green(), name.bold());
+                    }
+                    for name in &diff.removed {
+                        anstream::println!("{} {}", "Removed".red(), name.bold());
+                    }
+                    for (name, changes) in &diff.changed {
+                        for (reference, change) in changes {
+                            println!(
+                                "{name} {reference} {} -> {}",
+                                change.old.as_ref().unwrap_or(&null),
+                                change.new.as_ref().unwrap_or(&null)
+                            );
+                        }
+                    }
+                }
+            }
+            Command::CargoPatch {
+                ref reference,
+                format,
+            } => {
+                println!(
+                    "{}",
+                    git_paravendor::cargo_patch(&repository, reference, format.into())?
+                );
+            }
+            Command::ExportSubmodules { ref dir, gitlinks } => {
+                for submodule in git_paravendor::export_submodules(&repository, dir, gitlinks)? {
+                    match submodule.commit {
+                        Some(commit) => println!("{} {commit}", submodule.path),
+                        None => eprintln!(
+                            "{} has no default branch, not pinning {}",
+                            submodule.name, submodule.path
+                        ),
+                    }
+                }
+            }
+            Command::ExportManifest {
+                ref file,
+                with_refs,
+            } => {
+                let manifest =
+                    toml::to_string_pretty(&git_paravendor::manifest(&repository, with_refs)?)?;
+                if file.as_os_str() == "-" {
+                    print!("{manifest}");
+                } else {
+                    std::fs::write(file, manifest)?;
+                }
+            }
+            Command::ExportPack { ref file } => {
+                let exported = git_paravendor::export_pack(&repository, file)?;
+                if !self.quiet {
+                    eprintln!("Exported {} objects", exported.objects);
+                }
+                println!("{}", exported.tip);
+            }
+            Command::Bundle {
+                command: BundleCommand::Create { ref file },
+            } => {
+                let tip = git_paravendor::bundle_create(&repository, file)?;
+                if !self.quiet {
+                    println!("Bundled paravendor at {tip}");
+                }
+            }
+            Command::Bundle {
+                command: BundleCommand::Verify { ref file },
+            } => {
+                let tip = git_paravendor::bundle_verify(&repository, file)?;
+                if !self.quiet {
+                    println!("{} is okay, paravendor at {tip}", file.display());
+                }
+            }
+            Command::Bundle {
+                command: BundleCommand::Apply { ref file },
+            } => {
+                let moved = git_paravendor::bundle_apply(&repository, file)?;
+                if !self.quiet {
+                    match moved {
+                        Some(tip) => println!("Updated paravendor to {tip}"),
+                        None => println!("Already up to date"),
+                    }
+                }
+            }
+            Command::ImportPack { ref file, ref tip } => {
+                let (objects, moved) =
+                    git_paravendor::import_pack(&repository, file, tip.as_deref())?;
+                if !self.quiet {
+                    println!("Imported {objects} objects");
+                    if let Some(moved) = moved {
+                        println!("Updated paravendor to {moved}");
+                    }
+                }
+            }
+            Command::Remove { ref name } => {
+                let dependencies = git_paravendor::list(&repository)?;
+                let dependency = dependencies
+                    .get(name)
+                    .ok_or_else(|| git_paravendor::Error::DependencyNotFound(name.clone()))?;
+                if !self.confirm(&format!(
+                    "This will remove {name} ({}) and its {} refs from the paravendor config",
+                    dependency.url,
+                    dependency.heads.len()
+                ))? {
+                    return Err(anyhow::Error::msg("aborted"));
+                }
+                git_paravendor::remove(&repository, name)?;
+            }
+            Command::Restore { ref name, ref rev } => {
+                git_paravendor::restore(&repository, name, rev.as_deref())?;
+                if !self.quiet {
+                    anstream::eprintln!("{} {name}", "Restored".green());
+                }
+            }
+            Command::Disable { ref name } => {
+                if git_paravendor::set_disabled(&repository, name, true)?.is_none() && !self.quiet {
+                    eprintln!("{name} is already disabled");
+                }
+            }
+            Command::Enable { ref name } => {
+                if git_paravendor::set_disabled(&repository, name, false)?.is_none() && !self.quiet
+                {
+                    eprintln!("{name} is already enabled");
+                }
+            }
+            Command::Freeze { ref name } => {
+                if git_paravendor::set_frozen(&repository, name, true)?.is_none() && !self.quiet {
+                    eprintln!("{name} is already frozen");
+                }
+            }
+            Command::Unfreeze { ref name } => {
+                if git_paravendor::set_frozen(&repository, name, false)?.is_none() && !self.quiet {
+                    eprintln!("{name} is not frozen");
+                }
+            }
+            Command::UpdateHead {
+                ref name,
+                ref reference,
+                ref commit,
+            } => {
+                if git_paravendor::update_head(&repository, name, reference, commit)?.is_none()
+                    && !self.quiet
+                {
+                    eprintln!("{reference} of {name} is already at {commit}");
+                }
+            }
+            Command::List {
+                ref group,
+                ref rev,
+                porcelain,
+                nul,
+            } => {
+                let dependencies = match rev {
+                    Some(rev) => git_paravendor::config_at(&repository, rev)?.dependencies,
+                    None => git_paravendor::list(&repository)?,
+                };
+                for (name, details) in dependencies
+                    .iter()
+                    .filter(|(_, d)| group.as_ref().is_none_or(|g| d.groups.contains(g)))
+                {
+                    if porcelain || nul {
+                        print!("{}", porcelain_record(name, details, nul));
+                        continue;
+                    }
+                    let empty = details.heads.is_empty().then_some("(no refs)");
+                    if self.verbose == 0 {
+                        match empty {
+                            Some(empty) => anstream::println!(
+                                "{} {} {}",
+                                name.bold(),
+                                details.url.cyan(),
+                                empty.yellow()
+                            ),
+                            None => anstream::println!("{} {}", name.bold(), details.url.cyan()),
+                        }
+                        continue;
+                    }
+                    let last_synced = details
+                        .last_synced
+                        .as_ref()
+                        .map(|at| format!("(last synced {at})"));
+                    // Only worth mentioning when the primary URL failed over to a mirror
+                    let last_source = details
+                        .last_source
+                        .as_ref()
+                        .filter(|source| **source != details.url)
+                        .map(|source| format!("(last fetched from {source})"));
+                    let mirrors = (!details.mirrors.is_empty())
+                        .then(|| format!("(mirrors: {})", details.mirrors.join(", ")));
+                    let extra = [
+                        empty,
+                        details.description.as_deref(),
+                        mirrors.as_deref(),
+                        last_synced.as_deref(),
+                        last_source.as_deref(),
+                    ]
+                    .into_iter()
+                    .flatten()
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                    anstream::println!("{} {} {}", name.bold(), details.url.cyan(), extra.dimmed());
+                }
+            }
+            Command::ShowRefs {
+                ref name,
+                ref rev,
+                nul,
+                long,
+            } => {
+                if long {
+                    let config = match rev {
+                        Some(rev) => git_paravendor::config_at(&repository, rev)?,
+                        None => git_paravendor::config_at(
+                            &repository,
+                            &git_paravendor::branch_name(&repository)?,
+                        )?,
+                    };
+                    let odb = repository.odb()?;
+                    for (name, head) in &config.dependency(name)?.heads {
+                        let kind = match head.peeled {
+                            Some(_) => "tag",
+                            None => git2::Oid::from_str(&head.commit)
+                                .and_then(|oid| odb.read_header(oid))
+                                .map_or("missing", |(_, kind)| kind.str()),
+                        };
+                        print!("{}", long_ref_record(name, head, kind, nul));
+                    }
+                    return Ok(ExitCode::SUCCESS);
+                }
+                let refs = match rev {
+                    Some(rev) => git_paravendor::config_at(&repository, rev)?
+                        .dependency(name)?
+                        .heads
+                        .keys()
+                        .cloned()
+                        .collect(),
+                    None => git_paravendor::show_refs(&repository, name)?,
+                };
+                for name in refs {
+                    if nul {
+                        print!("{name}\0");
+                    } else if name.starts_with("refs/tags/") {
+                        anstream::println!("{}", name.yellow());
+                    } else if name.starts_with("refs/heads/") {
+                        anstream::println!("{}", name.green());
+                    } else {
+                        anstream::println!("{}", name.bold());
+                    }
+                }
+            }
+            Command::ShowRef {
+                ref name,
+                ref reference,
+                no_peel,
+                ref rev,
+                nul,
+                object_type,
+            } => {
+                let target = match rev {
+                    Some(rev) => git_paravendor::config_at(&repository, rev)?
+                        .dependency(name)?
+                        .resolve(reference, !no_peel)?
+                        .to_string(),
+                    None => git_paravendor::show_ref(&repository, name, reference, !no_peel)?,
+                };
+                let kind = if object_type {
+                    let object = repository
+                        .find_object(git2::Oid::from_str(&target)?, None)
+                        .map_err(|e| {
+                            anyhow::Error::new(e)
+                                .context(format!("{target} is not in the repository"))
+                        })?;
+                    object.kind().map(|kind| kind.str())
+                } else {
+                    None
+                };
+                print!("{}", show_ref_output(&target, kind, self.json, nul)?);
+            }
+            Command::Config {
+                command: ConfigCommand::Get { ref key },
+            } => {
+                let value = git_paravendor::config_get(&repository, key)?;
+                match value {
+                    _ if self.json => println!("{}", serde_json::to_string_pretty(&value)?),
+                    toml::Value::String(value) => println!("{value}"),
+                    toml::Value::Table(_) => print!("{}", toml::to_string_pretty(&value)?),
+                    value => println!("{value}"),
+                }
+            }
+            Command::Config {
+                command: ConfigCommand::Set { ref key, ref value },
+            } => {
+                git_paravendor::config_set(&repository, key, value)?;
+            }
+            Command::Contains {
+                ref name,
+                ref commit,
+            } => {
+                let heads = git_paravendor::contains(&repository, name, commit)?;
+                if heads.is_empty() {
+                    println!("no");
+                    return Ok(ExitCode::from(exit::ERROR));
+                }
+                println!("yes");
+                if !self.quiet {
+                    for head in heads {
+                        println!("{head}");
+                    }
+                }
+            }
+            Command::Which { ref commit } => {
+                let dependencies = git_paravendor::which(&repository, commit)?;
+                if self.json {
+                    println!("{}", serde_json::to_string_pretty(&dependencies)?);
+                } else {
+                    for (name, heads) in &dependencies {
+                        anstream::println!("{} {}", name.bold(), heads.join(" "));
+                    }
+                }
+                if dependencies.is_empty() {
+                    return Ok(ExitCode::from(exit::ERROR));
+                }
+            }
+            Command::Compare {
+                ref first,
+                ref second,
+            } => {
+                let comparison = git_paravendor::compare(&repository, first, second)?;
+                if self.json {
+                    println!("{}", serde_json::to_string_pretty(&comparison)?);
+                } else {
+                    anstream::println!("{} only: {}", first.bold(), comparison.only_first);
+                    anstream::println!("{} only: {}", second.bold(), comparison.only_second);
+                    println!("shared: {}", comparison.shared);
+                    for merge_base in &comparison.merge_bases {
+                        anstream::println!("merge base: {}", merge_base.yellow());
+                    }
+                }
+            }
+            Command::RefHistory {
+                ref name,
+                ref reference,
+            } => {
+                let history = git_paravendor::ref_history(&repository, name, reference)?;
+                if self.json {
+                    println!("{}", serde_json::to_string_pretty(&history)?);
+                } else {
+                    for entry in &history {
+                        let target = match &entry.target {
+                            Some(target) => target.clone(),
+                            None => "(removed)".red().to_string(),
+                        };
+                        anstream::println!(
+                            "{target} {} {}",
+                            (&entry.commit[..7]).yellow(),
+                            entry.date
+                        );
+                    }
+                }
+            }
+            Command::Verify { deep, config } => {
+                let mismatches = if config {
+                    git_paravendor::verify_config(&repository)?
+                } else {
+                    vec![]
+                };
+                for commit in &mismatches {
+                    anstream::println!(
+                        "{} {}",
+                        commit.to_string().bold(),
+                        "config checksum mismatch".red()
+                    );
+                }
+                let missing = git_paravendor::verify(&repository, deep)?;
+                for (name, object) in &missing {
+                    anstream::println!(
+                        "{} {} {} {} (from {})",
+                        name.bold(),
+                        "missing".red(),
+                        object.kind,
+                        object.id,
+                        object.head
+                    );
+                }
+                if !missing.is_empty() || !mismatches.is_empty() {
+                    return Ok(ExitCode::from(exit::ERROR));
+                }
+            }
+            Command::Health => {
+                let checks = git_paravendor::health(&repository, &fetch)?;
+                if self.json {
+                    println!("{}", serde_json::to_string_pretty(&checks)?);
+                } else {
+                    for check in &checks {
+                        let url = if check.mirror {
+                            format!("{} (mirror)", check.url)
+                        } else {
+                            check.url.clone()
+                        };
+                        let elapsed = format!("{}ms", check.elapsed_ms);
+                        match &check.error {
+                            None => anstream::println!(
+                                "{} {} {} {}",
+                                check.name.bold(),
+                                url.cyan(),
+                                "reachable".green(),
+                                elapsed.dimmed()
+                            ),
+                            Some(error) => anstream::println!(
+                                "{} {} {} {} {}",
+                                check.name.bold(),
+                                url.cyan(),
+                                "unreachable".red(),
+                                elapsed.dimmed(),
+                                error
+                            ),
+                        }
+                    }
+                }
+                if checks.iter().any(|check| !check.reachable) {
+                    return Ok(ExitCode::from(exit::ERROR));
+                }
+            }
+            Command::Repack { paravendor_only } => {
+                let report = git_paravendor::repack(&repository, paravendor_only)?;
+                if self.json {
+                    println!("{}", serde_json::to_string_pretty(&report)?);
+                } else if !self.quiet {
+                    println!(
+                        "Repacked: {} -> {}",
+                        human_bytes(report.before),
+                        human_bytes(report.after)
+                    );
+                }
+            }
+            Command::Prune {
+                keep,
+                older_than,
+                dry_run,
+            } => {
+                let report = git_paravendor::prune(
+                    &repository,
+                    &git_paravendor::PruneOptions {
+                        keep,
+                        older_than,
+                        dry_run,
+                    },
+                )?;
+                if !self.quiet {
+                    if report.squashed == 0 {
+                        eprintln!("Nothing to prune");
+                    } else if dry_run {
+                        eprintln!(
+                            "Would squash {} commits, keeping {}",
+                            report.squashed, report.kept
+                        );
+                    } else {
+                        anstream::eprintln!(
+                            "{} {} commits, keeping {}",
+                            "Squashed".green(),
+                            report.squashed,
+                            report.kept
+                        );
+                    }
+                }
+            }
+            Command::Clean { dry_run } => {
+                let report = git_paravendor::clean(&repository, dry_run)?;
+                if !self.quiet {
+                    if report.commits.is_empty() {
+                        eprintln!("Nothing to clean");
+                    } else {
+                        let freed = format!(
+                            "{} of {} ({} objects, {} in blobs)",
+                            if report.commits.len() == 1 {
+                                "1 commit".to_string()
+                            } else {
+                                format!("{} commits", report.commits.len())
+                            },
+                            report.dependencies.join(", "),
+                            report.objects.commits + report.objects.trees + report.objects.blobs,
+                            human_bytes(report.blob_bytes)
+                        );
+                        if dry_run {
+                            eprintln!("Would drop {freed}");
+                        } else {
+                            anstream::eprintln!("{} {freed}", "Dropped".green());
+                        }
+                    }
+                    if let Some(repack) = report.repack {
+                        println!(
+                            "Repacked: {} -> {}",
+                            human_bytes(repack.before),
+                            human_bytes(repack.after)
+                        );
+                    }
+                }
+            }
+            Command::Repair => {
+                let report =
+                    git_paravendor::repair(&repository, &fetch, self.progress()?.as_mut())?;
+                if !self.quiet {
+                    for name in &report.repaired {
+                        anstream::eprintln!("{} {name}", "Repaired".green());
+                    }
+                    if report.repaired.is_empty() && report.unrepaired.is_empty() {
+                        eprintln!("Nothing to repair");
+                    }
+                }
+                for (name, object) in &report.unrepaired {
+                    anstream::eprintln!(
+                        "{} {name}, {} {} is still missing",
+                        "Couldn't repair".red(),
+                        object.kind,
+                        object.id
+                    );
+                }
+                if !report.unrepaired.is_empty() {
+                    return Ok(ExitCode::from(exit::ERROR));
+                }
+            }
+            Command::Size { ref name } => {
+                let report = git_paravendor::size(&repository, name.as_deref())?;
+                let describe = |footprint: &git_paravendor::Footprint| {
+                    let objects = footprint.objects;
+                    format!(
+                        "{} commits, {} trees, {} blobs ({})",
+                        objects.commits,
+                        objects.trees,
+                        objects.blobs,
+                        human_bytes(footprint.blob_bytes)
+                    )
+                };
+                for (name, footprint) in &report.dependencies {
+                    anstream::println!("{}: {}", name.bold(), describe(footprint));
+                }
+                anstream::println!("{}: {}", "Total".bold(), describe(&report.total));
+            }
+            Command::Objects { ref name } => {
+                let report = git_paravendor::objects(&repository, name)?;
+                if self.json {
+                    println!("{}", serde_json::to_string_pretty(&report)?);
+                } else {
+                    let describe = |footprint: &git_paravendor::Footprint| {
+                        let objects = footprint.objects;
+                        format!(
+                            "{} commits, {} trees, {} blobs ({})",
+                            objects.commits,
+                            objects.trees,
+                            objects.blobs,
+                            human_bytes(footprint.blob_bytes)
+                        )
+                    };
+                    anstream::println!("{}: {}", "Unique".bold(), describe(&report.unique));
+                    anstream::println!("{}: {}", "Shared".bold(), describe(&report.shared));
+                    anstream::println!("{}: {}", "Total".bold(), describe(&report.total));
+                }
+            }
+            Command::Stats => {
+                let report = git_paravendor::stats(&repository)?;
+                if self.json {
+                    println!("{}", serde_json::to_string_pretty(&report)?);
+                } else {
+                    let describe = |footprint: &git_paravendor::Footprint| {
+                        let objects = footprint.objects;
+                        format!(
+                            "{} objects ({})",
+                            objects.commits + objects.trees + objects.blobs,
+                            human_bytes(footprint.blob_bytes)
+                        )
+                    };
+                    for (name, footprint) in &report.dependencies {
+                        anstream::println!("{}: {}", name.bold(), describe(footprint));
+                    }
+                    anstream::println!("{}: {}", "Separately".bold(), describe(&report.sum));
+                    anstream::println!("{}: {}", "Stored".bold(), describe(&report.unique));
+                    anstream::println!("{}: {}", "Saved".green().bold(), describe(&report.saved()));
+                }
+            }
+            Command::Migrate => match git_paravendor::migrate(&repository)? {
+                None => {
+                    if !self.quiet {
+                        eprintln!("Config is already at the current version");
+                    }
+                }
+                Some(from) => {
+                    let (_branch, config) = git_paravendor::ensure_initialized(&repository)?;
+                    println!(
+                        "Migrated paravendor config from {from} to {}",
+                        config.version
+                    );
+                }
+            },
+            Command::Schema
+            | Command::Version
+            | Command::Completions { .. }
+            | Command::GenerateMan { .. } => {
+                unreachable!("handled before opening the repository")
+            }
+            Command::Log {
+                graph,
+                ref dependency,
+                ref mut options,
+            } => {
+                git_paravendor::ensure_initialized(&repository)?;
+
+                // If possible, try doing this with git as it makes a better output
+                match which("git") {
+                    _ if graph => {}
+                    Err(which::Error::CannotFindBinaryPath) => {}
+                    Err(e) => return Err(e)?,
+                    Ok(git) => {
+                        // Bare repositories have no working directory to change into
+                        let mut args = match repository.workdir() {
+                            Some(workdir) => {
+                                vec!["-C".to_string(), workdir.to_string_lossy().to_string()]
+                            }
+                            None => vec![
+                                "--git-dir".to_string(),
+                                repository.path().to_string_lossy().to_string(),
+                            ],
+                        };
+                        args.push("log".to_string());
+                        args.push(format!("--notes={}", git_paravendor::NOTES_REF));
+                        args.append(options.as_mut().unwrap_or(&mut vec![]));
+                        if let Some(dependency) = dependency {
+                            // git shows just the commits found to touch the dependency
+                            let log_options = git_paravendor::LogOptions {
+                                dependency: Some(dependency.clone()),
+                                ..Default::default()
+                            };
+                            let entries = git_paravendor::log(&repository, &log_options)?;
+                            if entries.is_empty() {
+                                return Ok(ExitCode::SUCCESS);
+                            }
+                            args.push("--no-walk=unsorted".to_string());
+                            args.extend(entries.iter().map(|entry| entry.id.to_string()));
+                        } else {
+                            args.append(&mut vec![
+                                git_paravendor::branch_name(&repository)?,
+                                "--first-parent".to_string(),
+                            ]);
+                        }
+                        std::process::Command::new(git).args(args).spawn()?.wait()?;
+                        return Ok(ExitCode::SUCCESS);
+                    }
+                };
+
+                // Otherwise, do it ourselves
+                let (mut log_options, oneline) =
+                    parse_log_options(options.as_deref().unwrap_or(&[]))?;
+                log_options.dependency = dependency.clone();
+                if graph {
+                    log_options.changes = true;
+                    print_graph(&git_paravendor::log(&repository, &log_options)?);
+                    return Ok(ExitCode::SUCCESS);
+                }
+                for entry in git_paravendor::log(&repository, &log_options)? {
+                    let id = entry.id.to_string();
+                    let id = if oneline { &id[..7] } else { &id };
+                    println!("{id} {}", entry.summary);
+                }
+            }
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+/// Exit codes, besides 0 for success
+mod exit {
+    /// Any other error
+    pub const ERROR: u8 = 1;
+    /// Invalid command line (the same as clap uses)
+    pub const USAGE: u8 = 2;
+    /// There's no paravendor branch
+    pub const NOT_INITIALIZED: u8 = 3;
+    /// The named dependency doesn't exist
+    pub const DEPENDENCY_NOT_FOUND: u8 = 4;
+    /// `sync --dry-run` found changes
+    pub const CHANGES_DETECTED: u8 = 5;
+}
+
+fn exit_code(error: &anyhow::Error) -> u8 {
+    if error.downcast_ref::<clap::Error>().is_some() {
+        return exit::USAGE;
+    }
+    match error
+        .chain()
+        .find_map(|e| e.downcast_ref::<git_paravendor::Error>())
+    {
+        Some(git_paravendor::Error::NotInitialized) => exit::NOT_INITIALIZED,
+        Some(git_paravendor::Error::DependencyNotFound(_)) => exit::DEPENDENCY_NOT_FOUND,
+        None => exit::ERROR,
+    }
+}
+
+/// Stable name of the kind of `error`, for `--json` error reports
+fn error_kind(error: &anyhow::Error) -> &'static str {
+    if error.downcast_ref::<clap::Error>().is_some() {
+        return "usage";
+    }
+    match error
+        .chain()
+        .find_map(|e| e.downcast_ref::<git_paravendor::Error>())
+    {
+        Some(git_paravendor::Error::NotInitialized) => "not_initialized",
+        Some(git_paravendor::Error::DependencyNotFound(_)) => "dependency_not_found",
+        None => "error",
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    anstream::ColorChoice::from(cli.color).write_global();
+    for (prefix, identity) in [
+        ("GIT_AUTHOR", &cli.author),
+        ("GIT_COMMITTER", &cli.committer),
+    ] {
+        if let Some((name, email)) = identity {
+            std::env::set_var(format!("{prefix}_NAME"), name);
+            std::env::set_var(format!("{prefix}_EMAIL"), email);
+        }
+    }
+    if cli.verbose > 0 {
+        tracing_subscriber::fmt()
+            .with_max_level(match cli.verbose {
+                1 => tracing::Level::INFO,
+                2 => tracing::Level::DEBUG,
+                _ => tracing::Level::TRACE,
+            })
+            .with_writer(std::io::stderr)
+            .with_ansi(std::io::stderr().is_terminal())
+            .init();
+    }
+    let json = cli.json;
+    match cli.execute() {
+        Ok(code) => code,
+        Err(e) if json => {
+            let message = match e.downcast_ref::<clap::Error>() {
+                // Without the usage and the hints, which are meant for people
+                Some(e) => {
+                    let rendered = e.to_string();
+                    let line = rendered.lines().next().unwrap_or_default();
+                    line.trim_start_matches("error: ").to_string()
+                }
+                None => format!("{e:#}"),
+            };
+            let report = serde_json::json!({ "error": message, "kind": error_kind(&e) });
+            eprintln!("{report}");
+            ExitCode::from(exit_code(&e))
+        }
+        Err(e) => {
+            if let Some(e) = e.downcast_ref::<clap::Error>() {
+                e.exit();
+            }
+            eprintln!("Error: {e:?}");
+            ExitCode::from(exit_code(&e))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identity() {
+        assert_eq!(
+            parse_identity("John Doe <john@doe.com>").unwrap(),
+            ("John Doe".to_string(), "john@doe.com".to_string())
+        );
+        assert!(parse_identity("John Doe").is_err());
+        assert!(parse_identity("<john@doe.com>").is_err());
+    }
+
+    #[test]
+    fn completion_scripts() -> Result<(), anyhow::Error> {
+        let mut bash = vec![];
+        completions(clap_complete::Shell::Bash, &mut bash)?;
+        let bash = String::from_utf8(bash)?;
+        assert!(bash.contains("__git_paravendor_names"));
+        assert!(bash.contains(r#"case " sync " in"#));
+        assert!(bash.contains(" show-refs show-ref "));
+        assert!(!bash.contains(" add show-refs "));
+        assert!(!bash.contains("@FIRST@") && !bash.contains("@COMPLETE@"));
+
+        let mut fish = vec![];
+        completions(clap_complete::Shell::Fish, &mut fish)?;
+        assert!(String::from_utf8(fish)?.contains("(__git_paravendor_names)"));
+
+        let mut zsh = vec![];
+        completions(clap_complete::Shell::Zsh, &mut zsh)?;
+        assert!(!String::from_utf8(zsh)?.contains("__git_paravendor_names"));
+        Ok(())
+    }
+
+    #[test]
+    fn man_pages() -> Result<(), anyhow::Error> {
+        let dir = tempfile::tempdir()?;
+        let mut command = Cli::command();
+        command.build();
+        let pages = generate_man(&command, dir.path())?;
+        assert_eq!(pages, std::fs::read_dir(dir.path())?.count());
+        let sync = std::fs::read_to_string(dir.path().join("git-paravendor-sync.1"))?;
+        assert!(sync.contains("git\\-paravendor\\-sync"));
+        assert!(dir.path().join("git-paravendor-config-get.1").exists());
+        // Hidden commands aren't documented
+        assert!(!dir.path().join("git-paravendor-generate-man.1").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn names_from_stdin() -> Result<(), anyhow::Error> {
+        assert_eq!(
+            read_names(&b"a\n\n b \r\nc"[..], false)?,
+            vec!["a", "b", "c"]
+        );
+        assert_eq!(
+            read_names(&b"a b\0c\nd\0\0"[..], true)?,
+            vec!["a b", "c\nd"]
+        );
+        assert!(read_names(&b""[..], false)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn porcelain() {
+        let dependency = git_paravendor::Dependency {
+            url: "https://example.com/dep.git".to_string(),
+            mirrors: vec![],
+            description: Some("ignored".to_string()),
+            disabled: false,
+            frozen: false,
+            groups: vec![],
+            last_synced: None,
+            last_source: None,
+            heads: [(
+                "HEAD".to_string(),
+                git_paravendor::Head {
+                    commit: "a".repeat(40),
+                    peeled: None,
+                },
+            )]
+            .into(),
+        };
+        assert_eq!(
+            porcelain_record("dep", &dependency, false),
+            "dep\thttps://example.com/dep.git\t1\n"
+        );
+        assert_eq!(
+            porcelain_record("dep", &dependency, true),
+            "dep\0https://example.com/dep.git\x001\0"
+        );
+    }
+
+    #[test]
+    fn long_refs() {
+        let head = git_paravendor::Head {
+            commit: "a".repeat(40),
+            peeled: Some("b".repeat(40)),
+        };
+        assert_eq!(
+            long_ref_record("refs/tags/v1", &head, "tag", false),
+            format!("refs/tags/v1\t{}\ttag\n", "a".repeat(40))
+        );
+        assert_eq!(
+            long_ref_record("refs/tags/v1", &head, "tag", true),
+            format!("refs/tags/v1\t{}\ttag\0", "a".repeat(40))
+        );
+    }
+
+    #[test]
+    fn show_ref_types() -> Result<(), anyhow::Error> {
+        let oid = "a".repeat(40);
+        assert_eq!(
+            show_ref_output(&oid, None, false, false)?,
+            format!("{oid}\n")
+        );
+        assert_eq!(
+            show_ref_output(&oid, Some("tag"), false, false)?,
+            format!("{oid} tag\n")
+        );
+        assert_eq!(
+            show_ref_output(&oid, Some("commit"), false, true)?,
+            format!("{oid} commit\0")
+        );
+        let json: serde_json::Value =
+            serde_json::from_str(&show_ref_output(&oid, Some("tag"), true, true)?)?;
+        assert_eq!(json, serde_json::json!({ "oid": oid, "type": "tag" }));
+        let json: serde_json::Value =
+            serde_json::from_str(&show_ref_output(&oid, None, true, false)?)?;
+        assert_eq!(json, serde_json::json!({ "oid": oid }));
+        Ok(())
+    }
+
+    #[test]
+    fn github_actions() {
+        let mut report = git_paravendor::SyncReport {
+            changed: vec!["dep".to_string()],
+            failed: vec![("other".to_string(), anyhow::Error::msg("100%\nbroken"))],
+            ..Default::default()
+        };
+        report.refs.entry("dep".to_string()).or_default().insert(
+            "refs/heads/main".to_string(),
+            git_paravendor::RefChange {
+                old: Some("a".repeat(40)),
+                new: None,
+            },
+        );
+        let summary = github_step_summary(&report, false);
+        assert!(summary.starts_with("### paravendor sync\n"));
+        assert!(summary.contains(&format!(
+            "| dep | refs/heads/main | `{}` | - |\n",
+            "a".repeat(40)
+        )));
+        assert!(summary.contains("**Failed to sync other**"));
+        assert!(github_step_summary(&Default::default(), true).contains("No updates detected"));
+
+        assert_eq!(
+            github_annotations(&report, false),
+            vec![
+                "::notice title=paravendor::Synced dep",
+                "::warning title=paravendor::Failed to sync other: 100%25%0Abroken",
+            ]
+        );
+    }
+
+    #[test]
+    fn log_options() {
+        let parse = |options: &[&str]| {
+            let options = options.iter().map(|o| o.to_string()).collect::<Vec<_>>();
+            parse_log_options(&options)
+                .map(|(options, oneline)| (options.max_count, options.reverse, oneline))
+        };
+        assert_eq!(parse(&[]).unwrap(), (None, false, false));
+        assert_eq!(
+            parse(&["--oneline", "--reverse"]).unwrap(),
+            (None, true, true)
+        );
+        for count in [
+            &["-n", "3"][..],
+            &["-n3"],
+            &["-3"],
+            &["--max-count", "3"],
+            &["--max-count=3"],
+        ] {
+            assert_eq!(parse(count).unwrap(), (Some(3), false, false));
+        }
+        assert!(parse(&["-n"]).is_err());
+        assert!(parse(&["-n", "many"]).is_err());
+        assert!(parse(&["--graph"]).is_err());
+    }
+
+    #[test]
+    fn bytes() {
+        assert_eq!(human_bytes(5), "5 B");
+        assert_eq!(human_bytes(1536), "1.5 KiB");
+        assert_eq!(human_bytes(3 * 1024 * 1024 * 1024), "3.0 GiB");
+    }
+
+    #[test]
+    fn default_names() {
+        assert_eq!(
+            default_name("https://github.com/yrashk/git-paravendor"),
+            Some("git-paravendor")
+        );
+        assert_eq!(default_name("https://example.com/dep.git/"), Some("dep"));
+        assert_eq!(default_name("git@github.com:org/repo.git"), Some("repo"));
+        assert_eq!(default_name("host:repo"), Some("repo"));
+        assert_eq!(default_name("/"), None);
+    }
+
+    #[test]
+    fn exit_codes() -> Result<(), anyhow::Error> {
+        let dir = tempfile::tempdir()?;
+        git2::Repository::init(dir.path())?;
+        let execute = |args: &[&str]| {
+            Cli::try_parse_from(
+                ["git-paravendor", "-C", &dir.path().to_string_lossy()]
+                    .into_iter()
+                    .chain(args.iter().copied()),
+            )
+            .unwrap()
+            .execute()
+        };
+        let err = execute(&["list"]).unwrap_err();
+        assert_eq!(exit_code(&err), exit::NOT_INITIALIZED);
+        assert_eq!(error_kind(&err), "not_initialized");
+
+        execute(&["init"])?;
+        let err = execute(&["show-refs", "nonexistent"]).unwrap_err();
+        assert_eq!(exit_code(&err), exit::DEPENDENCY_NOT_FOUND);
+        assert_eq!(error_kind(&err), "dependency_not_found");
+        let err = execute(&["-q", "add", "dep", "/nonexistent"]).unwrap_err();
+        assert_eq!(exit_code(&err), exit::ERROR);
+        assert_eq!(error_kind(&err), "error");
+        Ok(())
+    }
+
+    #[test]
+    fn bare() -> Result<(), anyhow::Error> {
+        let dir = tempfile::tempdir()?;
+        let repository = git2::Repository::init_bare(dir.path().join("repo.git"))?;
+        let dependency = tempfile::tempdir()?;
+        {
+            let dependency = git2::Repository::init(dependency.path())?;
+            let sig = git2::Signature::now("John Doe", "john@doe.com")?;
+            let tree = dependency.find_tree(dependency.treebuilder(None)?.write()?)?;
+            dependency.commit(Some("HEAD"), &sig, &sig, "init", &tree, &[])?;
+        }
+        let url = dependency.path().to_string_lossy().to_string();
+        let execute = |args: &[&str]| {
+            Cli::try_parse_from(["git-paravendor", "-q"].iter().chain(args))?.execute()
+        };
+
+        let path = repository.path().to_string_lossy().to_string();
+        execute(&["-C", &path, "init"])?;
+        execute(&["-C", &path, "add", "dep", &url])?;
+        // Found both as a directory and as an explicit git dir
+        for location in [["-C", path.as_str()], ["--git-dir", path.as_str()]] {
+            let run = |args: &[&str]| execute(&[&location[..], args].concat());
+            run(&["sync"])?;
+            run(&["list"])?;
+            run(&["show-refs", "dep"])?;
+            run(&["show-ref", "dep", "HEAD"])?;
+            run(&["log"])?;
+            run(&["log", "--graph"])?;
+            run(&["log", "--dep", "dep"])?;
+        }
+        execute(&["-C", &path, "--yes", "remove", "dep"])?;
+        assert!(git_paravendor::list(&repository)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn log_bare() -> Result<(), anyhow::Error> {
+        let dir = tempfile::tempdir()?;
+        let repository = git2::Repository::init_bare(dir.path())?;
+        git_paravendor::init(&repository, false, None)?;
+
+        Cli::try_parse_from([
+            "git-paravendor".as_ref(),
+            "-C".as_ref(),
+            dir.path().as_os_str(),
+            "log".as_ref(),
+        ])?
+        .execute()?;
         Ok(())
     }
 }
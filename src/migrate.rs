@@ -0,0 +1,226 @@
+//! Upgrades configs written by older versions of paravendor
+
+use crate::Config;
+use toml::{Table, Value};
+
+/// Config format version written by this version of paravendor
+pub const CURRENT_VERSION: &str = "1.2";
+
+/// Oldest config format version this version of paravendor can read (and upgrade)
+pub const OLDEST_VERSION: &str = MIGRATIONS[0].0;
+
+/// Upgrades a raw config table in place
+type Migration = fn(&mut Table) -> Result<(), anyhow::Error>;
+
+/// Ordered migrations, each upgrading a config from the first version to the second
+const MIGRATIONS: &[(&str, &str, Migration)] =
+    &[("1.0", "1.1", heads_as_tables), ("1.1", "1.2", peeled_tags)];
+
+/// Config loaded through [`migrate`]
+pub(crate) struct Migrated {
+    pub config: Config,
+    /// Version the config was upgraded from, if any migrations were applied
+    pub from: Option<String>,
+}
+
+fn major(version: &str) -> &str {
+    version.split('.').next().unwrap_or(version)
+}
+
+/// Major and minor numbers of a version, for comparing them
+fn numbers(version: &str) -> Option<(u64, u64)> {
+    let (major, minor) = version.split_once('.')?;
+    Some((major.parse().ok()?, minor.parse().ok()?))
+}
+
+/// Deserializes a config, upgrading it to [`CURRENT_VERSION`] if necessary
+///
+/// Unless `allow_major` is set, configs with a different major version are
+/// rejected, and the user is asked to run `git paravendor migrate` explicitly.
+pub(crate) fn migrate(source: &str, allow_major: bool) -> Result<Migrated, anyhow::Error> {
+    let mut table: Table = toml::from_str(source)?;
+    let original = match table.get("version") {
+        Some(Value::String(version)) => version.clone(),
+        _ => return Err(anyhow::Error::msg("paravendor config has no version")),
+    };
+
+    if original == CURRENT_VERSION {
+        return Ok(Migrated {
+            config: Value::Table(table).try_into()?,
+            from: None,
+        });
+    }
+
+    // Configs written by newer versions may have anything in them
+    if numbers(&original) > numbers(CURRENT_VERSION) {
+        return Err(anyhow::Error::msg(format!(
+            "paravendor config version {original} is newer than {CURRENT_VERSION}, \
+             the latest this version of paravendor supports, upgrade paravendor"
+        )));
+    }
+
+    if !allow_major && major(&original) != major(CURRENT_VERSION) {
+        return Err(anyhow::Error::msg(format!(
+            "paravendor config version {original} is incompatible with {CURRENT_VERSION}, \
+             run `git paravendor migrate`"
+        )));
+    }
+
+    let mut version = original.clone();
+    while version != CURRENT_VERSION {
+        let (_, to, migration) = MIGRATIONS
+            .iter()
+            .find(|(from, _, _)| *from == version)
+            .ok_or_else(|| {
+                anyhow::Error::msg(format!(
+                    "don't know how to migrate paravendor config version {version}"
+                ))
+            })?;
+        migration(&mut table)?;
+        version = to.to_string();
+        table.insert("version".to_string(), Value::String(version.clone()));
+    }
+
+    Ok(Migrated {
+        config: Value::Table(table).try_into()?,
+        from: Some(original),
+    })
+}
+
+/// 1.0 recorded heads as bare commit ids, 1.1 records them as tables
+fn heads_as_tables(table: &mut Table) -> Result<(), anyhow::Error> {
+    let Some(Value::Table(dependencies)) = table.get_mut("dependencies") else {
+        return Ok(());
+    };
+    for (_, dependency) in dependencies.iter_mut() {
+        let Some(Value::Table(heads)) = dependency.get_mut("heads") else {
+            continue;
+        };
+        for (_, head) in heads.iter_mut() {
+            if let Value::String(commit) = head {
+                let mut upgraded = Table::new();
+                upgraded.insert("commit".to_string(), Value::String(commit.clone()));
+                *head = Value::Table(upgraded);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 1.1 recorded peeled annotated tags as separate `<tag>^{}` heads, 1.2 records
+/// them in the tag's head
+fn peeled_tags(table: &mut Table) -> Result<(), anyhow::Error> {
+    let Some(Value::Table(dependencies)) = table.get_mut("dependencies") else {
+        return Ok(());
+    };
+    for (_, dependency) in dependencies.iter_mut() {
+        let Some(Value::Table(heads)) = dependency.get_mut("heads") else {
+            continue;
+        };
+        let peeled_names = heads
+            .keys()
+            .filter(|name| name.ends_with("^{}"))
+            .cloned()
+            .collect::<Vec<_>>();
+        for peeled_name in peeled_names {
+            let peeled = heads.remove(&peeled_name).unwrap();
+            let commit = peeled.get("commit").cloned();
+            if let (Some(Value::Table(head)), Some(commit)) =
+                (heads.get_mut(peeled_name.trim_end_matches("^{}")), commit)
+            {
+                head.insert("peeled".to_string(), commit);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn current_version_is_untouched() -> Result<(), anyhow::Error> {
+        let config = Config::default();
+        let migrated = migrate(&toml::to_string_pretty(&config)?, false)?;
+        assert!(migrated.from.is_none());
+        assert_eq!(migrated.config, config);
+        Ok(())
+    }
+
+    #[test]
+    fn from_1_0() -> Result<(), anyhow::Error> {
+        let migrated = migrate(
+            r#"
+version = "1.0"
+
+[dependencies.dep]
+url = "https://example.com/dep.git"
+
+[dependencies.dep.heads]
+HEAD = "0123456789012345678901234567890123456789"
+"#,
+            false,
+        )?;
+        assert_eq!(migrated.from.as_deref(), Some("1.0"));
+        assert_eq!(migrated.config.version, CURRENT_VERSION);
+        let dep = migrated.config.dependencies.get("dep").unwrap();
+        assert_eq!(
+            dep.resolve("HEAD", true)?,
+            "0123456789012345678901234567890123456789"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn from_1_1() -> Result<(), anyhow::Error> {
+        let migrated = migrate(
+            r#"
+version = "1.1"
+
+[dependencies.dep]
+url = "https://example.com/dep.git"
+
+[dependencies.dep.heads."refs/tags/v1"]
+commit = "1111111111111111111111111111111111111111"
+
+[dependencies.dep.heads."refs/tags/v1^{}"]
+commit = "0123456789012345678901234567890123456789"
+"#,
+            false,
+        )?;
+        assert_eq!(migrated.from.as_deref(), Some("1.1"));
+        let dep = migrated.config.dependencies.get("dep").unwrap();
+        assert_eq!(dep.heads.len(), 1);
+        assert_eq!(
+            dep.resolve("v1", true)?,
+            "0123456789012345678901234567890123456789"
+        );
+        assert_eq!(
+            dep.resolve("v1", false)?,
+            "1111111111111111111111111111111111111111"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn newer_version() {
+        for version in ["1.3", "1.10", "2.0"] {
+            let source = format!("version = \"{version}\"\n[dependencies]\n");
+            for allow_major in [false, true] {
+                let err = migrate(&source, allow_major).err().unwrap().to_string();
+                assert!(err.contains("upgrade paravendor"), "{err}");
+            }
+        }
+    }
+
+    #[test]
+    fn major_version_requires_explicit_migration() {
+        let source = "version = \"0.1\"\n[dependencies]\n";
+        let err = migrate(source, false).err().unwrap().to_string();
+        assert!(err.contains("git paravendor migrate"));
+        // there's no migration path from 0.1, but it is attempted
+        let err = migrate(source, true).err().unwrap().to_string();
+        assert!(err.contains("don't know how to migrate"));
+    }
+}
@@ -0,0 +1,181 @@
+//! Counting objects introduced to the repository
+
+use crate::ObjectCounts;
+use git2::{ObjectType, Odb, Oid, Repository};
+use std::collections::HashSet;
+
+/// Counts objects reachable from `new` commits but not from `old`
+///
+/// Like `git rev-list --objects`, trees and blobs are considered known if they are
+/// reachable from the trees of the known commits at the boundary.
+pub(crate) fn count_new(
+    repository: &Repository,
+    old: Oid,
+    new: &[Oid],
+) -> Result<ObjectCounts, anyhow::Error> {
+    count(repository, &[old], new, &mut None)
+}
+
+/// Counts objects reachable from `new` commits but not from `hidden` ones, adding up the
+/// sizes of the counted blobs in `blob_bytes` (if it's set)
+pub(crate) fn count(
+    repository: &Repository,
+    hidden: &[Oid],
+    new: &[Oid],
+    blob_bytes: &mut Option<u64>,
+) -> Result<ObjectCounts, anyhow::Error> {
+    let mut revwalk = repository.revwalk()?;
+    for oid in new {
+        revwalk.push(*oid)?;
+    }
+    for oid in hidden {
+        revwalk.hide(*oid)?;
+    }
+    let commits = revwalk.collect::<Result<HashSet<_>, _>>()?;
+    let odb = repository.odb()?;
+
+    let mut seen = HashSet::new();
+    for oid in &commits {
+        for parent in repository.find_commit(*oid)?.parent_ids() {
+            if !commits.contains(&parent) {
+                let tree = repository.find_commit(parent)?.tree_id();
+                walk_tree(
+                    repository,
+                    &odb,
+                    tree,
+                    &mut seen,
+                    &mut ObjectCounts::default(),
+                    &mut None,
+                )?;
+            }
+        }
+    }
+
+    let mut counts = ObjectCounts {
+        commits: commits.len(),
+        ..Default::default()
+    };
+    for oid in &commits {
+        let tree = repository.find_commit(*oid)?.tree_id();
+        walk_tree(repository, &odb, tree, &mut seen, &mut counts, blob_bytes)?;
+    }
+    Ok(counts)
+}
+
+/// Finds an object reachable from `commit` that isn't in the repository, skipping
+/// objects `seen` before (and marking the visited ones seen)
+///
+/// Returns the missing object and its expected type.
+pub(crate) fn first_missing(
+    repository: &Repository,
+    commit: Oid,
+    seen: &mut HashSet<Oid>,
+) -> Result<Option<(Oid, ObjectType)>, anyhow::Error> {
+    let odb = repository.odb()?;
+    let mut pending = vec![(commit, ObjectType::Commit)];
+    while let Some((oid, kind)) = pending.pop() {
+        if !seen.insert(oid) {
+            continue;
+        }
+        if !odb.exists(oid) {
+            return Ok(Some((oid, kind)));
+        }
+        match kind {
+            ObjectType::Commit => {
+                let commit = repository.find_commit(oid)?;
+                pending.extend(
+                    commit
+                        .parent_ids()
+                        .map(|parent| (parent, ObjectType::Commit)),
+                );
+                pending.push((commit.tree_id(), ObjectType::Tree));
+            }
+            ObjectType::Tree => {
+                // Submodule commits aren't part of the repository
+                for entry in repository.find_tree(oid)?.iter() {
+                    if let Some(kind @ (ObjectType::Tree | ObjectType::Blob)) = entry.kind() {
+                        pending.push((entry.id(), kind));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(None)
+}
+
+/// Copies the objects reachable from `commit` in `source` that are missing from `target`,
+/// returning how many were copied
+///
+/// Objects missing from `source` as well are skipped.
+pub(crate) fn copy_missing(
+    source: &Repository,
+    target: &Odb,
+    commit: Oid,
+) -> Result<usize, anyhow::Error> {
+    let source_odb = source.odb()?;
+    let mut copied = 0;
+    let mut seen = HashSet::new();
+    let mut pending = vec![(commit, ObjectType::Commit)];
+    while let Some((oid, kind)) = pending.pop() {
+        if !seen.insert(oid) || !source_odb.exists(oid) {
+            continue;
+        }
+        if !target.exists(oid) {
+            let object = source_odb.read(oid)?;
+            target.write(object.kind(), object.data())?;
+            copied += 1;
+        }
+        match kind {
+            ObjectType::Commit => {
+                let commit = source.find_commit(oid)?;
+                pending.extend(
+                    commit
+                        .parent_ids()
+                        .map(|parent| (parent, ObjectType::Commit)),
+                );
+                pending.push((commit.tree_id(), ObjectType::Tree));
+            }
+            ObjectType::Tree => {
+                for entry in source.find_tree(oid)?.iter() {
+                    if let Some(kind @ (ObjectType::Tree | ObjectType::Blob)) = entry.kind() {
+                        pending.push((entry.id(), kind));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(copied)
+}
+
+/// Counts the trees and blobs of `tree` that weren't `seen` before, marking them seen
+fn walk_tree(
+    repository: &Repository,
+    odb: &Odb,
+    tree: Oid,
+    seen: &mut HashSet<Oid>,
+    counts: &mut ObjectCounts,
+    blob_bytes: &mut Option<u64>,
+) -> Result<(), anyhow::Error> {
+    if !seen.insert(tree) {
+        return Ok(());
+    }
+    counts.trees += 1;
+    for entry in repository.find_tree(tree)?.iter() {
+        match entry.kind() {
+            Some(ObjectType::Tree) => {
+                walk_tree(repository, odb, entry.id(), seen, counts, blob_bytes)?
+            }
+            Some(ObjectType::Blob) if seen.insert(entry.id()) => {
+                counts.blobs += 1;
+                if let Some(bytes) = blob_bytes {
+                    *bytes += odb.read_header(entry.id())?.0 as u64;
+                }
+            }
+            // Known blobs, and submodule commits that aren't part of the repository
+            _ => {}
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,120 @@
+//! Signing paravendor commits the way git does
+
+use git2::{Repository, Signature};
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Whether commits should be signed even if not asked to, as with `commit.gpgSign`
+pub(crate) fn enabled(repository: &Repository) -> bool {
+    repository
+        .config()
+        .and_then(|config| config.get_bool("commit.gpgSign"))
+        .unwrap_or(false)
+}
+
+/// Signs a commit `buffer` with the backend selected by `gpg.format`
+///
+/// `openpgp` (the default) and `x509` use `gpg`/`gpgsm` with `user.signingKey`, or the
+/// committer's identity if it's not set. `ssh` uses `ssh-keygen` with `user.signingKey`
+/// (a key file, or a `key::` literal public key whose private key is in the agent).
+pub(crate) fn sign(
+    repository: &Repository,
+    buffer: &str,
+    committer: &Signature,
+) -> Result<String, anyhow::Error> {
+    let config = repository.config()?;
+    let format = config
+        .get_string("gpg.format")
+        .unwrap_or_else(|_| "openpgp".to_string());
+    let key = config.get_string("user.signingKey").ok();
+
+    // A literal SSH key has to be passed to ssh-keygen as a file
+    let mut literal_key = None;
+    let (program, args) = match format.as_str() {
+        "openpgp" | "x509" => {
+            let default = if format == "openpgp" { "gpg" } else { "gpgsm" };
+            let program = config
+                .get_string(&format!("gpg.{format}.program"))
+                .or_else(|_| match format.as_str() {
+                    "openpgp" => config.get_string("gpg.program"),
+                    _ => Err(git2::Error::from_str("no program")),
+                })
+                .unwrap_or_else(|_| default.to_string());
+            let key = key.unwrap_or_else(|| committer.to_string());
+            (
+                program,
+                vec!["--status-fd=2".to_string(), "-bsau".to_string(), key],
+            )
+        }
+        "ssh" => {
+            let program = config
+                .get_string("gpg.ssh.program")
+                .unwrap_or_else(|_| "ssh-keygen".to_string());
+            let key_file = match key {
+                None => {
+                    return Err(anyhow::Error::msg(
+                        "user.signingKey has to be set to sign commits with SSH",
+                    ))
+                }
+                Some(key) => match key.strip_prefix("key::") {
+                    Some(literal) => {
+                        let path = repository.path().join("paravendor-signing-key.pub");
+                        std::fs::write(&path, literal)?;
+                        literal_key = Some(path.clone());
+                        path
+                    }
+                    None => config.get_path("user.signingKey")?,
+                },
+            };
+            let args = ["-Y", "sign", "-n", "git", "-f"]
+                .into_iter()
+                .map(str::to_string)
+                .chain([key_file.to_string_lossy().to_string()])
+                .collect();
+            (program, args)
+        }
+        format => {
+            return Err(anyhow::Error::msg(format!(
+                "unsupported gpg.format {format}"
+            )))
+        }
+    };
+
+    let result = run(&program, &args, buffer);
+    if let Some(path) = literal_key {
+        let _ = std::fs::remove_file(path);
+    }
+    result
+}
+
+fn run(program: &str, args: &[String], input: &str) -> Result<String, anyhow::Error> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| anyhow::Error::new(e).context(format!("can't run {program}")))?;
+    child.stdin.take().unwrap().write_all(input.as_bytes())?;
+    let output = child.wait_with_output()?;
+    if !output.status.success() || output.stdout.is_empty() {
+        return Err(anyhow::Error::msg(format!(
+            "{program} failed to sign the commit: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+/// Generates a passphrase-less SSH key in `dir` for tests, if `ssh-keygen` is available
+#[cfg(test)]
+pub(crate) fn generate_ssh_key(dir: &std::path::Path) -> Option<std::path::PathBuf> {
+    let path = dir.join("key");
+    Command::new("ssh-keygen")
+        .args(["-q", "-t", "ed25519", "-N", "", "-f"])
+        .arg(&path)
+        .status()
+        .ok()?
+        .success()
+        .then_some(path)
+}
@@ -0,0 +1,235 @@
+//! SSH host aliases from the user's SSH config
+//!
+//! libgit2 talks to SSH servers itself, without consulting `~/.ssh/config`, so hosts
+//! defined there (`Host github-work` with `HostName github.com`, a `User`, a `Port`
+//! and an `IdentityFile`) are resolved with `ssh -G` before connecting.
+
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+/// What the SSH config says about a host
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct HostConfig {
+    pub hostname: String,
+    pub user: Option<String>,
+    pub port: Option<u16>,
+    /// Identity files that exist, in the order they are to be tried
+    pub identity_files: Vec<PathBuf>,
+}
+
+/// An SSH URL, either `ssh://[user@]host[:port]/path` or `[user@]host:path`
+#[derive(Debug, PartialEq)]
+struct SshUrl<'a> {
+    user: Option<&'a str>,
+    host: &'a str,
+    port: Option<u16>,
+    path: &'a str,
+    scp_like: bool,
+}
+
+impl<'a> SshUrl<'a> {
+    fn parse(url: &'a str) -> Option<Self> {
+        let (rest, scp_like) = match url.split_once("://") {
+            Some(("ssh", rest)) => (rest, false),
+            Some(_) => return None,
+            None if crate::url::is_scp_like(url) => (url, true),
+            None => return None,
+        };
+        let (authority, path) = if scp_like {
+            rest.split_once(':')?
+        } else {
+            let slash = rest.find('/')?;
+            (&rest[..slash], &rest[slash..])
+        };
+        let (user, host) = match authority.rsplit_once('@') {
+            Some((user, host)) => (Some(user), host),
+            None => (None, authority),
+        };
+        let (host, port) = match host.split_once(':') {
+            Some((host, port)) if !scp_like => (host, Some(port.parse().ok()?)),
+            _ => (host, None),
+        };
+        Some(SshUrl {
+            user,
+            host,
+            port,
+            path,
+            scp_like,
+        })
+    }
+}
+
+/// Parses `ssh -G` output
+fn parse(output: &str) -> Option<HostConfig> {
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    let mut config = HostConfig::default();
+    for line in output.lines() {
+        let Some((key, value)) = line.split_once(' ') else {
+            continue;
+        };
+        match key {
+            "hostname" => config.hostname = value.to_string(),
+            "user" => config.user = Some(value.to_string()),
+            "port" => config.port = value.parse().ok(),
+            "identityfile" => {
+                let path = match (value.strip_prefix("~/"), &home) {
+                    (Some(relative), Some(home)) => home.join(relative),
+                    _ => PathBuf::from(value),
+                };
+                if path.is_file() {
+                    config.identity_files.push(path);
+                }
+            }
+            _ => {}
+        }
+    }
+    (!config.hostname.is_empty()).then_some(config)
+}
+
+/// Resolves `host` with the user's SSH config, or `None` if `ssh` isn't available
+pub(crate) fn resolve(host: &str) -> Option<HostConfig> {
+    // `ssh -G` would otherwise take an option-like host as its own option
+    if host.starts_with('-') {
+        return None;
+    }
+    let output = Command::new("ssh")
+        .args(["-G", host])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Rewrites an SSH `url` to the host, user and port from its SSH `config`
+///
+/// A user given in the URL takes precedence over the configured one. Non-SSH URLs are
+/// returned as they are.
+pub(crate) fn rewrite(url: &str, config: &HostConfig) -> String {
+    let Some(parsed) = SshUrl::parse(url) else {
+        return url.to_string();
+    };
+    let user = parsed.user.or(config.user.as_deref());
+    let userinfo = user.map(|user| format!("{user}@")).unwrap_or_default();
+    let port = parsed.port.or(config.port).filter(|port| *port != 22);
+    match port {
+        None if parsed.scp_like => format!("{userinfo}{}:{}", config.hostname, parsed.path),
+        _ => {
+            // Relative scp-like paths are relative to the home directory
+            let path = if parsed.scp_like && !parsed.path.starts_with('/') {
+                format!("/~/{}", parsed.path)
+            } else {
+                parsed.path.to_string()
+            };
+            let port = port.map(|port| format!(":{port}")).unwrap_or_default();
+            format!("ssh://{userinfo}{}{port}{path}", config.hostname)
+        }
+    }
+}
+
+/// Resolves the host of an SSH `url` with the user's SSH config
+///
+/// Returns the rewritten URL and the identity files to authenticate with, or `None`
+/// for non-SSH URLs.
+pub(crate) fn resolve_url(url: &str) -> Option<(String, Vec<PathBuf>)> {
+    let parsed = SshUrl::parse(url)?;
+    let config = resolve(parsed.host)?;
+    Some((rewrite(url, &config), config.identity_files))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(hostname: &str, user: Option<&str>, port: Option<u16>) -> HostConfig {
+        HostConfig {
+            hostname: hostname.to_string(),
+            user: user.map(str::to_string),
+            port,
+            identity_files: vec![],
+        }
+    }
+
+    #[test]
+    fn parse_urls() {
+        assert_eq!(
+            SshUrl::parse("git@work:org/repo.git"),
+            Some(SshUrl {
+                user: Some("git"),
+                host: "work",
+                port: None,
+                path: "org/repo.git",
+                scp_like: true,
+            })
+        );
+        assert_eq!(
+            SshUrl::parse("ssh://work:2222/org/repo.git"),
+            Some(SshUrl {
+                user: None,
+                host: "work",
+                port: Some(2222),
+                path: "/org/repo.git",
+                scp_like: false,
+            })
+        );
+        assert_eq!(SshUrl::parse("https://example.com/repo.git"), None);
+        assert_eq!(SshUrl::parse("/tmp/repo"), None);
+    }
+
+    #[test]
+    fn parse_ssh_g_output() -> Result<(), anyhow::Error> {
+        let dir = tempfile::tempdir()?;
+        let key = dir.path().join("id_work");
+        std::fs::write(&key, "")?;
+        let output = format!(
+            "host work\nuser alice\nhostname github.com\nport 22\n\
+             identityfile {}\nidentityfile {}\n",
+            key.display(),
+            dir.path().join("missing").display()
+        );
+        assert_eq!(
+            parse(&output),
+            Some(HostConfig {
+                hostname: "github.com".to_string(),
+                user: Some("alice".to_string()),
+                port: Some(22),
+                identity_files: vec![key],
+            })
+        );
+        assert_eq!(parse(""), None);
+        Ok(())
+    }
+
+    #[test]
+    fn rewrite_urls() {
+        let github = config("github.com", Some("git"), Some(22));
+        assert_eq!(
+            rewrite("work:org/repo.git", &github),
+            "git@github.com:org/repo.git"
+        );
+        assert_eq!(
+            rewrite("me@work:org/repo.git", &github),
+            "me@github.com:org/repo.git"
+        );
+        assert_eq!(
+            rewrite("ssh://work/org/repo.git", &github),
+            "ssh://git@github.com/org/repo.git"
+        );
+        let custom_port = config("git.example.com", None, Some(2222));
+        assert_eq!(
+            rewrite("work:org/repo.git", &custom_port),
+            "ssh://git.example.com:2222/~/org/repo.git"
+        );
+        assert_eq!(
+            rewrite("work:/srv/repo.git", &custom_port),
+            "ssh://git.example.com:2222/srv/repo.git"
+        );
+        assert_eq!(
+            rewrite("https://example.com/repo.git", &github),
+            "https://example.com/repo.git"
+        );
+    }
+}
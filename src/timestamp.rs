@@ -0,0 +1,102 @@
+//! Timestamps recorded in the config, and durations relative to them
+
+use std::time::Duration;
+
+/// Formats seconds since the Unix epoch as an RFC 3339 timestamp in UTC
+pub(crate) fn rfc3339(seconds: i64) -> String {
+    let (days, seconds) = (seconds.div_euclid(86400), seconds.rem_euclid(86400));
+    // Civil date from days since the epoch, in eras of 400 years starting at 0000-03-01
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+/// Parses a duration like `90d`: a whole number followed by `s`, `m`, `h`, `d`, `w` or
+/// `y` (365 days)
+pub fn parse_duration(duration: &str) -> Result<Duration, anyhow::Error> {
+    let invalid = || {
+        anyhow::Error::msg(format!(
+            "invalid duration {duration}, expected a number followed by s, m, h, d, w or y"
+        ))
+    };
+    let unit = duration.chars().next_back().ok_or_else(invalid)?;
+    let seconds = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 3600,
+        'd' => 86400,
+        'w' => 7 * 86400,
+        'y' => 365 * 86400,
+        _ => return Err(invalid()),
+    };
+    let number = &duration[..duration.len() - 1];
+    if !number.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let number = number.parse::<u64>().map_err(|_| invalid())?;
+    Ok(Duration::from_secs(
+        number.checked_mul(seconds).ok_or_else(invalid)?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formatting() {
+        assert_eq!(rfc3339(0), "1970-01-01T00:00:00Z");
+        assert_eq!(rfc3339(951782400), "2000-02-29T00:00:00Z");
+        assert_eq!(rfc3339(1700000000), "2023-11-14T22:13:20Z");
+        assert_eq!(rfc3339(-1), "1969-12-31T23:59:59Z");
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("90s").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("5m").unwrap(), Duration::from_secs(300));
+        assert_eq!(parse_duration("2h").unwrap(), Duration::from_secs(7200));
+        assert_eq!(
+            parse_duration("30d").unwrap(),
+            Duration::from_secs(30 * 86400)
+        );
+        assert_eq!(
+            parse_duration("6w").unwrap(),
+            Duration::from_secs(42 * 86400)
+        );
+        assert_eq!(
+            parse_duration("1y").unwrap(),
+            Duration::from_secs(365 * 86400)
+        );
+        for invalid in [
+            "",
+            "d",
+            "30",
+            "-1d",
+            "+1d",
+            "1.5d",
+            "30 d",
+            "1x",
+            "99999999999999999y",
+        ] {
+            assert!(parse_duration(invalid).is_err(), "{invalid}");
+        }
+    }
+}
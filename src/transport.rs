@@ -0,0 +1,246 @@
+//! Transport-level settings for fetching dependencies
+
+use git2::{Cred, CredentialType, ErrorClass, ErrorCode};
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::os::raw::{c_char, c_int};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Makes HTTPS transports verify certificates against the CA bundle at `path`
+///
+/// This is a process-wide libgit2 setting.
+pub(crate) fn set_ca_info(path: &Path) -> Result<(), anyhow::Error> {
+    if !path.is_file() {
+        return Err(anyhow::Error::msg(format!(
+            "CA bundle {} does not exist",
+            path.display()
+        )));
+    }
+    let file = CString::new(path.to_string_lossy().as_bytes())?;
+    libgit2_sys::init();
+    // SAFETY: libgit2 copies both paths (file and directory, the latter omitted)
+    let rc = unsafe {
+        libgit2_sys::git_libgit2_opts(
+            libgit2_sys::GIT_OPT_SET_SSL_CERT_LOCATIONS as c_int,
+            file.as_ptr(),
+            std::ptr::null::<c_char>(),
+        )
+    };
+    if rc < 0 {
+        return Err(git2::Error::last_error(rc)
+            .map(anyhow::Error::new)
+            .unwrap_or_else(|| {
+                anyhow::Error::msg(format!("can't use CA bundle {}", path.display()))
+            }));
+    }
+    Ok(())
+}
+
+/// Credentials for authenticating with remotes, like git does: SSH keys come from the
+/// agent, then from the identity files, passwords from the configured credential helpers
+///
+/// libgit2 asks again for as long as authentication fails, so each SSH key is offered once
+/// per connection.
+pub(crate) struct Credentials<'a> {
+    config: &'a git2::Config,
+    identity_files: Vec<PathBuf>,
+    ssh_attempts: usize,
+}
+
+impl<'a> Credentials<'a> {
+    pub(crate) fn new(config: &'a git2::Config, identity_files: Vec<PathBuf>) -> Self {
+        Credentials {
+            config,
+            identity_files,
+            ssh_attempts: 0,
+        }
+    }
+
+    pub(crate) fn get(
+        &mut self,
+        url: &str,
+        username: Option<&str>,
+        allowed: CredentialType,
+    ) -> Result<Cred, git2::Error> {
+        if allowed.contains(CredentialType::USERNAME) {
+            return Cred::username(username.unwrap_or("git"));
+        }
+        if allowed.contains(CredentialType::SSH_KEY) {
+            let username = username.unwrap_or("git");
+            let attempt = self.ssh_attempts;
+            self.ssh_attempts += 1;
+            return match attempt.checked_sub(1) {
+                None => Cred::ssh_key_from_agent(username),
+                Some(i) => match self.identity_files.get(i) {
+                    Some(key) => Cred::ssh_key(username, None, key, None),
+                    None => Err(git2::Error::new(
+                        ErrorCode::Auth,
+                        ErrorClass::Ssh,
+                        "no more SSH keys to try",
+                    )),
+                },
+            };
+        }
+        if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) {
+            return Cred::credential_helper(self.config, url, username);
+        }
+        Cred::default()
+    }
+}
+
+/// Whether `url` is a local repository without any refs
+///
+/// libgit2's local transport lists the refs of such repositories as a null array, which
+/// git2 turns into a slice unchecked, so they have to be recognized before listing.
+pub(crate) fn is_empty_local(url: &str) -> bool {
+    let path = match url.split_once("://") {
+        Some(("file", path)) => path,
+        Some(_) => return false,
+        None if crate::url::is_scp_like(url) => return false,
+        None => url,
+    };
+    let Ok(repository) = git2::Repository::open(path) else {
+        return false;
+    };
+    let empty = match repository.references() {
+        Ok(mut references) => references.all(|r| r.map_or(true, |r| r.resolve().is_err())),
+        Err(_) => false,
+    };
+    empty
+}
+
+/// Whether a fetch failing with `error` is worth retrying
+///
+/// Network and OS-level failures (timeouts, connection resets, etc.) are; authentication,
+/// missing repositories and certificate problems aren't.
+pub(crate) fn is_transient(error: &git2::Error) -> bool {
+    !matches!(
+        error.code(),
+        ErrorCode::Auth | ErrorCode::NotFound | ErrorCode::Certificate
+    ) && matches!(error.class(), ErrorClass::Net | ErrorClass::Os)
+}
+
+/// Suggests what to do about a fetch failing with `error`
+pub(crate) fn hint(error: &git2::Error) -> Option<&'static str> {
+    match (error.code(), error.class()) {
+        (ErrorCode::Auth, _) => Some("check your credentials or SSH agent"),
+        (ErrorCode::Certificate, _) | (_, ErrorClass::Ssl) => {
+            Some("the server certificate can't be verified, see --ca-info")
+        }
+        (ErrorCode::NotFound, _) => Some("check that the URL is correct and the repository exists"),
+        (_, ErrorClass::Net | ErrorClass::Os | ErrorClass::Http) => {
+            Some("check your network connection and proxy settings")
+        }
+        _ => None,
+    }
+}
+
+/// Spaces out fetches from the same host by at least a minimum interval
+///
+/// Fetches from different hosts aren't delayed, and neither are local ones.
+pub(crate) struct HostThrottle {
+    interval: Duration,
+    /// When the next fetch from each host may start
+    next: Mutex<HashMap<String, Instant>>,
+}
+
+impl HostThrottle {
+    pub(crate) fn new(interval: Duration) -> Self {
+        HostThrottle {
+            interval,
+            next: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Waits until a fetch from `host` may start
+    pub(crate) fn wait(&self, host: Option<&str>) {
+        let Some(host) = host.filter(|_| !self.interval.is_zero()) else {
+            return;
+        };
+        // The slot is reserved before sleeping, so concurrent fetches queue up behind it
+        let start = {
+            let mut next = self.next.lock().unwrap_or_else(|e| e.into_inner());
+            let now = Instant::now();
+            let start = next.get(host).map_or(now, |next| (*next).max(now));
+            next.insert(host.to_string(), start + self.interval);
+            start
+        };
+        std::thread::sleep(start.saturating_duration_since(Instant::now()));
+    }
+}
+
+/// Delay before retry number `attempt` (starting at 1), doubling each time
+pub(crate) fn backoff(attempt: u32) -> Duration {
+    Duration::from_secs(1 << attempt.saturating_sub(1).min(5))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transient_errors() {
+        let err = |code, class| git2::Error::new(code, class, "test");
+        assert!(is_transient(&err(ErrorCode::GenericError, ErrorClass::Net)));
+        assert!(is_transient(&err(ErrorCode::GenericError, ErrorClass::Os)));
+        assert!(!is_transient(&err(ErrorCode::Auth, ErrorClass::Net)));
+        assert!(!is_transient(&err(ErrorCode::NotFound, ErrorClass::Net)));
+        assert!(!is_transient(&err(ErrorCode::Certificate, ErrorClass::Net)));
+        assert!(!is_transient(&err(
+            ErrorCode::GenericError,
+            ErrorClass::Ssl
+        )));
+        assert!(!is_transient(&err(
+            ErrorCode::GenericError,
+            ErrorClass::Odb
+        )));
+    }
+
+    #[test]
+    fn host_throttle() {
+        let throttle = HostThrottle::new(Duration::from_millis(100));
+        let started = Instant::now();
+        throttle.wait(Some("example.com"));
+        throttle.wait(Some("example.org"));
+        throttle.wait(None);
+        assert!(started.elapsed() < Duration::from_millis(100));
+        throttle.wait(Some("example.com"));
+        assert!(started.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn hints() {
+        let err = |code, class| git2::Error::new(code, class, "test");
+        assert!(hint(&err(ErrorCode::Auth, ErrorClass::Http))
+            .unwrap()
+            .contains("credentials"));
+        assert!(hint(&err(ErrorCode::Certificate, ErrorClass::Net))
+            .unwrap()
+            .contains("--ca-info"));
+        assert!(hint(&err(ErrorCode::NotFound, ErrorClass::Repository))
+            .unwrap()
+            .contains("URL"));
+        assert!(hint(&err(ErrorCode::GenericError, ErrorClass::Net))
+            .unwrap()
+            .contains("network"));
+        assert!(hint(&err(ErrorCode::GenericError, ErrorClass::Odb)).is_none());
+    }
+
+    #[test]
+    fn exponential_backoff() {
+        assert_eq!(backoff(1), Duration::from_secs(1));
+        assert_eq!(backoff(2), Duration::from_secs(2));
+        assert_eq!(backoff(3), Duration::from_secs(4));
+        assert_eq!(backoff(100), Duration::from_secs(32));
+    }
+
+    #[test]
+    fn missing_ca_bundle() {
+        let err = set_ca_info(Path::new("/nonexistent/ca.pem"))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("/nonexistent/ca.pem"));
+    }
+}
@@ -0,0 +1,321 @@
+//! Dependency URL handling
+
+use git2::Repository;
+use std::path::Path;
+
+const SCHEMES: &[&str] = &["https", "http", "ssh", "git", "file"];
+
+/// Checks that `url` looks like something git can fetch from
+///
+/// Local repositories (`file://` URLs and plain paths) must exist and be git
+/// repositories.
+pub(crate) fn validate(url: &str) -> Result<(), anyhow::Error> {
+    let invalid = |reason: &str| anyhow::Error::msg(format!("invalid URL {url}: {reason}"));
+
+    if let Some((scheme, rest)) = url.split_once("://") {
+        if !SCHEMES.contains(&scheme) {
+            return Err(invalid(&format!(
+                "unsupported scheme {scheme}, expected one of {}",
+                SCHEMES.join(", ")
+            )));
+        }
+        if rest.is_empty() {
+            return Err(invalid("nothing after the scheme"));
+        }
+        if scheme == "file" {
+            return validate_local(url, Path::new(rest));
+        }
+        return Ok(());
+    }
+
+    if is_scp_like(url) {
+        return Ok(());
+    }
+
+    validate_local(url, Path::new(url))
+}
+
+/// Expands `${VAR}` references in `url` with variables looked up with `env`
+///
+/// This lets the stored URLs refer to hosts or tokens that are kept out of the config.
+pub(crate) fn expand(
+    url: &str,
+    env: impl Fn(&str) -> Option<String>,
+) -> Result<String, anyhow::Error> {
+    let mut expanded = String::with_capacity(url.len());
+    let mut rest = url;
+    while let Some(start) = rest.find("${") {
+        expanded.push_str(&rest[..start]);
+        let (var, after) = rest[start + 2..]
+            .split_once('}')
+            .ok_or_else(|| anyhow::Error::msg(format!("invalid URL {url}: unterminated ${{")))?;
+        let value = env(var).ok_or_else(|| {
+            anyhow::Error::msg(format!(
+                "environment variable {var} used in URL {url} is not set"
+            ))
+        })?;
+        expanded.push_str(&value);
+        rest = after;
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Rewrites `url` according to git's `url.<base>.insteadOf` rules, given as
+/// `(base, prefix)` pairs
+///
+/// Like in git, the rule with the longest matching prefix wins, and only one rewrite
+/// is applied.
+pub(crate) fn rewrite(url: &str, rules: &[(String, String)]) -> String {
+    rules
+        .iter()
+        .filter(|(_, prefix)| url.starts_with(prefix.as_str()))
+        .max_by_key(|(_, prefix)| prefix.len())
+        .map_or_else(
+            || url.to_string(),
+            |(base, prefix)| format!("{base}{}", &url[prefix.len()..]),
+        )
+}
+
+/// `url.<base>.insteadOf` rules from git `config`, as `(base, prefix)` pairs
+pub(crate) fn instead_of_rules(
+    config: &git2::Config,
+) -> Result<Vec<(String, String)>, anyhow::Error> {
+    let mut rules = vec![];
+    let mut entries = config.entries(Some(r"^url\..*\.insteadof$"))?;
+    while let Some(entry) = entries.next() {
+        let entry = entry?;
+        let (Some(name), Some(prefix)) = (entry.name(), entry.value()) else {
+            continue;
+        };
+        if let Some(base) = name
+            .strip_prefix("url.")
+            .and_then(|name| name.strip_suffix(".insteadof"))
+        {
+            rules.push((base.to_string(), prefix.to_string()));
+        }
+    }
+    Ok(rules)
+}
+
+/// Picks the proxy to fetch `url` through, if any
+///
+/// An explicitly specified proxy wins over `http.proxy` from git config, which in
+/// turn wins over the conventional environment variables (looked up with `env`).
+pub(crate) fn proxy(
+    url: &str,
+    explicit: Option<&str>,
+    config: Option<&str>,
+    env: impl Fn(&str) -> Option<String>,
+) -> Option<String> {
+    if let Some(proxy) = explicit.or(config) {
+        return Some(proxy.to_string());
+    }
+    let vars: &[&str] = match url.split_once("://") {
+        Some(("https", _)) => &["https_proxy", "HTTPS_PROXY", "all_proxy", "ALL_PROXY"],
+        Some(("http", _)) => &["http_proxy", "HTTP_PROXY", "all_proxy", "ALL_PROXY"],
+        // Other transports don't go through HTTP proxies
+        _ => &[],
+    };
+    vars.iter()
+        .filter_map(|var| env(var))
+        .find(|proxy| !proxy.is_empty())
+}
+
+/// Host `url` is fetched from, or `None` for local repositories
+pub(crate) fn host(url: &str) -> Option<&str> {
+    let authority = match url.split_once("://") {
+        Some(("file", _)) => return None,
+        Some((_, rest)) => rest.split('/').next().unwrap_or(rest),
+        None if is_scp_like(url) => url.split_once(':')?.0,
+        None => return None,
+    };
+    let host = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+    let host = match host.strip_prefix('[') {
+        // IPv6 addresses
+        Some(bracketed) => bracketed.split(']').next().unwrap_or(bracketed),
+        None => host.split(':').next().unwrap_or(host),
+    };
+    (!host.is_empty()).then_some(host)
+}
+
+/// `[user@]host:path`, with no slash before the colon
+pub(crate) fn is_scp_like(url: &str) -> bool {
+    match url.split_once(':') {
+        Some((host, path)) => {
+            let host = host.rsplit_once('@').map_or(host, |(_, host)| host);
+            !host.is_empty() && !host.contains('/') && !path.is_empty()
+        }
+        None => false,
+    }
+}
+
+fn validate_local(url: &str, path: &Path) -> Result<(), anyhow::Error> {
+    if !path.exists() {
+        return Err(anyhow::Error::msg(format!(
+            "invalid URL {url}: {} does not exist",
+            path.display()
+        )));
+    }
+    if Repository::open(path).is_err() {
+        return Err(anyhow::Error::msg(format!(
+            "invalid URL {url}: {} is not a git repository",
+            path.display()
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hosts() {
+        assert_eq!(host("https://example.com/repo.git"), Some("example.com"));
+        assert_eq!(
+            host("ssh://git@example.com:2222/repo.git"),
+            Some("example.com")
+        );
+        assert_eq!(host("git@example.com:org/repo.git"), Some("example.com"));
+        assert_eq!(host("https://[::1]:8080/repo.git"), Some("::1"));
+        assert_eq!(host("file:///tmp/repo"), None);
+        assert_eq!(host("/tmp/repo"), None);
+    }
+
+    #[test]
+    fn remote_urls() {
+        for url in [
+            "https://github.com/yrashk/git-paravendor",
+            "http://example.com/repo.git",
+            "ssh://git@example.com/repo.git",
+            "git://example.com/repo.git",
+            "git@github.com:yrashk/git-paravendor.git",
+            "example.com:repo.git",
+        ] {
+            assert!(validate(url).is_ok(), "{url}");
+        }
+        for url in ["ftp://example.com/repo.git", "https://", "@:repo"] {
+            assert!(validate(url).is_err(), "{url}");
+        }
+    }
+
+    #[test]
+    fn expansion() -> Result<(), anyhow::Error> {
+        let env = |var: &str| (var == "GH_HOST").then(|| "github.example.com".to_string());
+        assert_eq!(
+            expand("https://${GH_HOST}/org/repo.git", env)?,
+            "https://github.example.com/org/repo.git"
+        );
+        assert_eq!(
+            expand("git@${GH_HOST}:org/${GH_HOST}", env)?,
+            "git@github.example.com:org/github.example.com"
+        );
+        assert_eq!(
+            expand("https://example.com/$repo", env)?,
+            "https://example.com/$repo"
+        );
+        let err = expand("https://${TOKEN}@example.com/repo.git", env).unwrap_err();
+        assert!(err.to_string().contains("TOKEN"), "{err}");
+        assert!(expand("https://${GH_HOST/repo.git", env).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn instead_of() -> Result<(), anyhow::Error> {
+        let rules = [
+            ("https://mirror.example.com/", "https://github.com/"),
+            ("https://org-mirror.example.com/", "https://github.com/org/"),
+            ("git@github.com:", "gh:"),
+        ]
+        .map(|(base, prefix)| (base.to_string(), prefix.to_string()));
+        assert_eq!(
+            rewrite("https://github.com/other/repo.git", &rules),
+            "https://mirror.example.com/other/repo.git"
+        );
+        assert_eq!(
+            rewrite("https://github.com/org/repo.git", &rules),
+            "https://org-mirror.example.com/repo.git"
+        );
+        // Applied once
+        assert_eq!(
+            rewrite("gh:org/repo.git", &rules),
+            "git@github.com:org/repo.git"
+        );
+        assert_eq!(
+            rewrite("https://example.com/repo.git", &rules),
+            "https://example.com/repo.git"
+        );
+
+        let dir = tempfile::tempdir()?;
+        let mut config = git2::Config::open(&dir.path().join("config"))?;
+        config.set_str(
+            "url.https://mirror.example.com/.insteadOf",
+            "https://github.com/",
+        )?;
+        assert_eq!(
+            instead_of_rules(&config)?,
+            vec![(
+                "https://mirror.example.com/".to_string(),
+                "https://github.com/".to_string()
+            )]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn proxy_selection() {
+        let env = |var: &str| match var {
+            "HTTPS_PROXY" => Some("http://https-proxy:3128".to_string()),
+            "ALL_PROXY" => Some("http://all-proxy:3128".to_string()),
+            _ => None,
+        };
+        let https = "https://example.com/repo.git";
+
+        assert_eq!(
+            proxy(https, None, None, env).as_deref(),
+            Some("http://https-proxy:3128")
+        );
+        assert_eq!(
+            proxy("http://example.com/repo.git", None, None, env).as_deref(),
+            Some("http://all-proxy:3128")
+        );
+        assert_eq!(proxy("ssh://example.com/repo.git", None, None, env), None);
+        assert_eq!(
+            proxy(https, None, Some("http://config-proxy:3128"), env).as_deref(),
+            Some("http://config-proxy:3128")
+        );
+        assert_eq!(
+            proxy(
+                https,
+                Some("http://explicit:3128"),
+                Some("http://config-proxy:3128"),
+                env
+            )
+            .as_deref(),
+            Some("http://explicit:3128")
+        );
+    }
+
+    #[test]
+    fn local_urls() -> Result<(), anyhow::Error> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().to_string_lossy().to_string();
+        let err = validate(&path).unwrap_err().to_string();
+        assert!(err.contains("not a git repository"), "{err}");
+
+        Repository::init(dir.path())?;
+        validate(&path)?;
+        validate(&format!("file://{path}"))?;
+
+        let missing = dir.path().join("missing").to_string_lossy().to_string();
+        let err = validate(&format!("file://{missing}"))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("does not exist"), "{err}");
+        assert!(err.contains(&missing), "{err}");
+        Ok(())
+    }
+}